@@ -0,0 +1,16 @@
+#[macro_use]
+extern crate afl;
+extern crate arbitrary;
+extern crate tar;
+
+use arbitrary::{Arbitrary, Unstructured};
+use tar::fuzz::FuzzArchive;
+
+fn main() {
+    fuzz!(|data: &[u8]| {
+        let mut u = Unstructured::new(data);
+        if let Ok(archive) = FuzzArchive::arbitrary(&mut u) {
+            archive.check_round_trip();
+        }
+    });
+}
@@ -0,0 +1,41 @@
+extern crate tar;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::process;
+
+use tar::archive::Archive;
+use tar::fuse::ArchiveMount;
+use tar::sniff::sniff_magic;
+use tar::vfs::{ArchiveFs, FsOptions};
+
+fn is_gzip(path: &str) -> bool {
+    let mut magic = [0; 2];
+    match File::open(path).and_then(|mut f| f.read_exact(&mut magic)) {
+        Ok(()) => sniff_magic(&magic) == Some("application/gzip"),
+        Err(_) => false
+    }
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<String>>();
+    if args.len() != 3 {
+        println!("usage: {} ARCHIVE MOUNTPOINT", args[0]);
+        process::exit(1);
+    }
+
+    let fs = if is_gzip(&args[1]) {
+        ArchiveFs::open_gzip(&args[1], FsOptions::default())
+    } else {
+        Archive::open_mmap(&args[1]).map(ArchiveFs::new)
+    };
+    let fs = fs.unwrap_or_else(|e| {
+        println!("cannot read archive: {}", e);
+        process::exit(1);
+    });
+    if let Err(e) = ArchiveMount::new(fs).mount(&args[2]) {
+        println!("cannot mount archive: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,472 @@
+#[cfg(feature = "gzip")]
+extern crate flate2;
+
+use std::collections::HashMap;
+#[cfg(feature = "gzip")]
+use std::io::Write;
+use std::mem::size_of;
+
+use archive::{Archive, EntryMetadata};
+use builder::split_path;
+use digest::{sha256, Digest};
+use extract::CHUNK_SIZE;
+use framing::ParseOptions;
+use parser::{padding, TypeFlag};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Storage analysis: where the bytes of an archive go
+ */
+
+/// How much space one entry takes up
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EntryStorage {
+    pub path:             String,
+    pub header_offset:    u64,
+    /// Header blocks, including extension blocks and PAX or GNU long name records
+    pub header_bytes:     u64,
+    pub content_bytes:    u64,
+    /// Zeroes rounding the contents up to a block
+    pub padding_bytes:    u64,
+    /// Earlier entry with the exact same contents, if any
+    pub duplicate_of:     Option<String>,
+    /// Approximate gzip compressed size of the entry, see
+    /// `StorageReport::for_archive_gzip`
+    pub compressed_bytes: Option<u64>
+}
+
+impl EntryStorage {
+    pub fn total_bytes(&self) -> u64 {
+        self.header_bytes + self.content_bytes + self.padding_bytes
+    }
+}
+
+/// Per-entry and aggregate storage breakdown of an archive
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StorageReport {
+    pub entries:           Vec<EntryStorage>,
+    pub total_bytes:       u64,
+    pub header_bytes:      u64,
+    pub content_bytes:     u64,
+    pub padding_bytes:     u64,
+    /// Terminator blocks, empty records and anything after the last entry
+    pub trailing_bytes:    u64,
+    /// Entries whose contents duplicate an earlier entry
+    pub duplicate_entries: u64,
+    /// Contents and padding that would be saved by storing duplicates once
+    pub duplicate_bytes:   u64,
+    /// Size of the archive once gzip compressed, see `for_archive_gzip`
+    pub compressed_bytes:  Option<u64>
+}
+
+impl StorageReport {
+    pub fn for_archive(archive: &Archive) -> StorageReport {
+        let mut seen: HashMap<Digest, &str> = HashMap::new();
+        let mut report = StorageReport {
+            entries:           Vec::with_capacity(archive.entries().len()),
+            total_bytes:       archive.as_bytes().len() as u64,
+            header_bytes:      0,
+            content_bytes:     0,
+            padding_bytes:     0,
+            trailing_bytes:    0,
+            duplicate_entries: 0,
+            duplicate_bytes:   0,
+            compressed_bytes:  None
+        };
+
+        if let Some(v) = archive.volume_header() {
+            report.header_bytes += v.data_offset - v.extension_offset;
+        }
+        for e in archive.entries() {
+            let duplicate_of = if e.size > 0 {
+                let digest = sha256(archive.contents(e));
+                match seen.get(&digest) {
+                    Some(first) => Some((*first).to_owned()),
+                    None => {
+                        seen.insert(digest, &e.path);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            let storage = EntryStorage {
+                path:             e.path.clone(),
+                header_offset:    e.extension_offset,
+                header_bytes:     e.data_offset - e.extension_offset,
+                content_bytes:    e.size,
+                padding_bytes:    padding(e.size),
+                duplicate_of:     duplicate_of,
+                compressed_bytes: None
+            };
+
+            report.header_bytes += storage.header_bytes;
+            report.content_bytes += storage.content_bytes;
+            report.padding_bytes += storage.padding_bytes;
+            if storage.duplicate_of.is_some() {
+                report.duplicate_entries += 1;
+                report.duplicate_bytes += storage.content_bytes + storage.padding_bytes;
+            }
+            report.entries.push(storage);
+        }
+
+        report.trailing_bytes = report.total_bytes.saturating_sub(report.header_bytes + report.content_bytes + report.padding_bytes);
+        report
+    }
+
+    /// Like `for_archive`, also estimating what each entry contributes to
+    /// the archive once gzip compressed. The compressed stream is flushed
+    /// after each entry, which costs a few bytes each, and the output since
+    /// the last flush is counted against it. Anything between entries, such
+    /// as a volume label, counts against the entry after it.
+    #[cfg(feature = "gzip")]
+    pub fn for_archive_gzip(archive: &Archive) -> StorageReport {
+        /* Header and trailer of a gzip member around the deflate stream */
+        const GZIP_FRAMING: u64 = 18;
+
+        let mut report = StorageReport::for_archive(archive);
+        let data = archive.as_bytes();
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        let (mut pos, mut counted) = (0, 0);
+        for (storage, e) in report.entries.iter_mut().zip(archive.entries()) {
+            let end = (e.data_offset + e.size + padding(e.size)) as usize;
+            encoder.write_all(&data[pos..end]).expect("writes to a Vec cannot fail");
+            encoder.flush().expect("writes to a Vec cannot fail");
+            let compressed = encoder.get_ref().len() as u64;
+            storage.compressed_bytes = Some(compressed - counted);
+            counted = compressed;
+            pos = end;
+        }
+        encoder.write_all(&data[pos..]).expect("writes to a Vec cannot fail");
+        let compressed = encoder.finish().expect("writes to a Vec cannot fail");
+        report.compressed_bytes = Some(compressed.len() as u64 + GZIP_FRAMING);
+        report
+    }
+
+    /// Share of the archive not holding file contents, between 0 and 1
+    pub fn overhead_ratio(&self) -> f64 {
+        match self.total_bytes {
+            0 => 0.0,
+            t => (t - self.content_bytes) as f64 / t as f64
+        }
+    }
+}
+
+/*
+ * Path portability: how entry paths encode and where they can be extracted
+ */
+
+/// Character set of a path. Names that are not valid UTF-8 fail to parse.
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PathEncoding {
+    Ascii,
+    Utf8
+}
+
+/// Something keeping a path from being extracted as is on some platform
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PortabilityIssue {
+    /// A character Windows refuses in file names, control characters included
+    WindowsInvalidChar(char),
+    /// A component such as `CON` or `lpt1.txt` naming a Windows device
+    WindowsReservedName(String),
+    /// A component ending with `.` or a space, which Windows strips
+    WindowsTrailingDotOrSpace(String),
+    /// A `:`, shown as `/` by the macOS Finder
+    MacInvalidChar(char)
+}
+
+/// How one entry path is encoded and whether it travels well
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PathClass {
+    pub path:         String,
+    pub encoding:     PathEncoding,
+    /// Only a PAX `path` record can store it: too long for the ustar
+    /// name and prefix fields, or not plain ASCII
+    pub requires_pax: bool,
+    pub issues:       Vec<PortabilityIssue>
+}
+
+impl PathClass {
+    pub fn classify(path: &str) -> PathClass {
+        let encoding = if path.is_ascii() { PathEncoding::Ascii } else { PathEncoding::Utf8 };
+        PathClass {
+            path:         path.to_owned(),
+            encoding:     encoding,
+            requires_pax: encoding != PathEncoding::Ascii || split_path(path).is_err(),
+            issues:       portability_issues(path)
+        }
+    }
+
+    /// Can be extracted unchanged on Linux, macOS and Windows
+    pub fn is_portable(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+const WINDOWS_RESERVED: &[&str] = &["CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"];
+
+fn portability_issues(path: &str) -> Vec<PortabilityIssue> {
+    let mut issues = Vec::new();
+    for c in path.chars() {
+        if c == ':' {
+            issues.push(PortabilityIssue::MacInvalidChar(c));
+        }
+        if c < ' ' || "<>:\"|?*\\".contains(c) {
+            issues.push(PortabilityIssue::WindowsInvalidChar(c));
+        }
+    }
+    for component in path.split('/').filter(|c| !c.is_empty() && *c != "." && *c != "..") {
+        /* Device names are reserved whatever their extension */
+        let stem = component.split('.').next().unwrap_or("").trim_end();
+        if WINDOWS_RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+            issues.push(PortabilityIssue::WindowsReservedName(component.to_owned()));
+        }
+        if component.ends_with('.') || component.ends_with(' ') {
+            issues.push(PortabilityIssue::WindowsTrailingDotOrSpace(component.to_owned()));
+        }
+    }
+    issues.sort();
+    issues.dedup();
+    issues
+}
+
+/// Classification of every entry path of an archive
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PathReport {
+    pub paths: Vec<PathClass>
+}
+
+impl PathReport {
+    pub fn for_archive(archive: &Archive) -> PathReport {
+        PathReport {
+            paths: archive.entries().iter().map(|e| PathClass::classify(&e.path)).collect()
+        }
+    }
+
+    /// Paths with at least one portability issue
+    pub fn non_portable(&self) -> impl Iterator<Item = &PathClass> {
+        self.paths.iter().filter(|p| !p.is_portable())
+    }
+
+    pub fn requires_pax(&self) -> impl Iterator<Item = &PathClass> {
+        self.paths.iter().filter(|p| p.requires_pax)
+    }
+}
+
+/*
+ * Resource estimates: what an operation will need before it is run
+ */
+
+/// Work planned on an archive
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Operation {
+    /// Load the archive into an `Archive`
+    ParseAll,
+    /// Stream it to a directory with an `Extractor`
+    Extract,
+    /// Load it and write every entry to a new archive
+    Convert
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EstimateOptions {
+    pub operation: Operation,
+    pub parse:     ParseOptions
+}
+
+/// Predicted needs of an operation, to refuse jobs before they start
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ResourceEstimate {
+    /// Peak heap and buffer use in bytes
+    pub peak_memory: u64,
+    /// Disk space written, counting files in whole 4 KiB blocks
+    pub disk:        u64
+}
+
+const FS_BLOCK: u64 = 4096;
+
+/* Heap used by the owned metadata of an entry and its index slot */
+fn metadata_bytes(e: &EntryMetadata) -> u64 {
+    let strings = e.path.len() + e.linkname.len() + e.uname.len() + e.gname.len();
+    let pax = e.pax.iter().map(|(k, v)| k.len() + v.len() + size_of::<String>() + size_of::<Vec<u8>>()).sum::<usize>();
+    let index = e.path.len() + size_of::<String>() + size_of::<usize>();
+    (size_of::<EntryMetadata>() + strings + pax + index) as u64
+}
+
+/* Bytes of the archive holding these entries, terminator included */
+fn archive_bytes(entries: &[EntryMetadata]) -> u64 {
+    entries.iter().map(|e| e.data_offset + e.size + padding(e.size)).max().unwrap_or(0) + 1024
+}
+
+/// Estimate what running `options.operation` on an archive with these
+/// entries needs. Counts are upper bounds of this crate's own buffers,
+/// not of the allocator or the page cache.
+pub fn estimate_resources(entries: &[EntryMetadata], options: &EstimateOptions) -> ResourceEstimate {
+    let parsed = archive_bytes(entries) + entries.iter().map(metadata_bytes).sum::<u64>();
+    match options.operation {
+        Operation::ParseAll => ResourceEstimate {
+            peak_memory: parsed,
+            disk:        0
+        },
+        Operation::Extract => {
+            /* Extension records are buffered whole, up to the metadata limit */
+            let extensions = entries.iter()
+                .map(|e| (e.header_offset - e.extension_offset).min(options.parse.max_metadata_size))
+                .max().unwrap_or(0);
+            let largest = entries.iter().map(metadata_bytes).max().unwrap_or(0);
+            let directories = entries.iter().filter(|e| e.typeflag == TypeFlag::Directory);
+            let pending = directories.clone().map(|e| e.path.len() as u64 + 40).sum::<u64>();
+            let disk = entries.iter()
+                .filter(|e| e.typeflag == TypeFlag::NormalFile || e.typeflag == TypeFlag::ContiguousFile)
+                .map(|e| e.size.div_ceil(FS_BLOCK) * FS_BLOCK)
+                .sum::<u64>();
+            ResourceEstimate {
+                peak_memory: CHUNK_SIZE as u64 + extensions + largest + pending,
+                disk:        disk + directories.count() as u64 * FS_BLOCK
+            }
+        },
+        Operation::Convert => ResourceEstimate {
+            peak_memory: parsed + CHUNK_SIZE as u64,
+            disk:        archive_bytes(entries)
+        }
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::{Builder, Header};
+
+    #[test]
+    fn storage_report_test() {
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("a"), &[1; 600]).unwrap();
+        b.append(&Header::new("b"), b"").unwrap();
+        b.append(&Header::new("c"), &[1; 600]).unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+
+        let report = StorageReport::for_archive(&archive);
+        assert_eq!(report.total_bytes, 512 * 9);
+        assert_eq!(report.header_bytes, 512 * 3);
+        assert_eq!(report.content_bytes, 1200);
+        assert_eq!(report.padding_bytes, 2 * 424);
+        assert_eq!(report.trailing_bytes, 1024);
+        assert_eq!(report.duplicate_entries, 1);
+        assert_eq!(report.duplicate_bytes, 1024);
+        assert_eq!(report.entries[2].duplicate_of, Some("a".to_owned()));
+        assert_eq!(report.entries[2].total_bytes(), 1536);
+        assert_eq!(report.compressed_bytes, None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_storage_test() {
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("zeroes"), &[0; 100000]).unwrap();
+        /* xorshift, which deflate cannot shrink */
+        let mut state = 0x2545f491u32;
+        let noise = (0..20000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect::<Vec<_>>();
+        b.append(&Header::new("noise"), &noise).unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+
+        let report = StorageReport::for_archive_gzip(&archive);
+        let zeroes = report.entries[0].compressed_bytes.unwrap();
+        let noise = report.entries[1].compressed_bytes.unwrap();
+        assert!(zeroes < 1000, "{}", zeroes);
+        assert!(noise > 10000, "{}", noise);
+        assert!(report.compressed_bytes.unwrap() >= zeroes + noise);
+    }
+
+    #[test]
+    fn path_report_test() {
+        let ascii = PathClass::classify("docs/readme.txt");
+        assert_eq!(ascii.encoding, PathEncoding::Ascii);
+        assert!(!ascii.requires_pax && ascii.is_portable());
+
+        let utf8 = PathClass::classify("docs/caf\u{e9}.txt");
+        assert_eq!(utf8.encoding, PathEncoding::Utf8);
+        assert!(utf8.requires_pax && utf8.is_portable());
+        assert!(PathClass::classify(&"a".repeat(120)).requires_pax);
+
+        assert_eq!(PathClass::classify("logs/12:00|x").issues, vec![
+            PortabilityIssue::WindowsInvalidChar(':'),
+            PortabilityIssue::WindowsInvalidChar('|'),
+            PortabilityIssue::MacInvalidChar(':')
+        ]);
+        assert_eq!(PathClass::classify("dev/Aux.c/file. ").issues, vec![
+            PortabilityIssue::WindowsReservedName("Aux.c".to_owned()),
+            PortabilityIssue::WindowsTrailingDotOrSpace("file. ".to_owned())
+        ]);
+
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("ok"), b"").unwrap();
+        b.append(&Header::new("con"), b"").unwrap();
+        let report = PathReport::for_archive(&Archive::new(b.finish().unwrap()).unwrap());
+        assert_eq!(report.non_portable().map(|p| &p.path[..]).collect::<Vec<_>>(), vec!["con"]);
+        assert_eq!(report.requires_pax().count(), 0);
+    }
+
+    #[test]
+    fn estimate_resources_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let archive = Archive::new(tar.to_vec()).unwrap();
+        let options = |operation| EstimateOptions {
+            operation: operation,
+            parse:     ParseOptions::default()
+        };
+
+        let parse = estimate_resources(archive.entries(), &options(Operation::ParseAll));
+        assert_eq!(parse.disk, 0);
+        assert!(parse.peak_memory > 4 * 512 + 4 * size_of::<EntryMetadata>() as u64);
+        assert_eq!(archive.estimated_peak_memory(&options(Operation::ParseAll)), parse.peak_memory);
+
+        let extract = estimate_resources(archive.entries(), &options(Operation::Extract));
+        assert_eq!(extract.disk, 4 * FS_BLOCK);
+        assert!(extract.peak_memory >= CHUNK_SIZE as u64);
+        assert_eq!(estimate_resources(archive.entries(), &options(Operation::Convert)).disk, 7 * 512 + 1024);
+        assert_eq!(estimate_resources(&[], &options(Operation::Extract)).disk, 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_reports_test() {
+        use serde_json;
+
+        let report = PathReport {
+            paths: vec![PathClass::classify("a:b")]
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains(r#""issues":[{"windows_invalid_char":":"},{"mac_invalid_char":":"}]"#), "{}", json);
+        assert_eq!(serde_json::from_str::<PathReport>(&json).unwrap(), report);
+
+        let digest = sha256(b"");
+        let json = serde_json::to_string(&digest).unwrap();
+        assert_eq!(json, format!("\"{}\"", digest));
+        assert_eq!(serde_json::from_str::<Digest>(&json).unwrap(), digest);
+    }
+}
@@ -0,0 +1,703 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::mem;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+
+use nom::IResult;
+
+use analysis::{estimate_resources, EstimateOptions};
+use digest::{sha256, Digest, PAX_DIGEST_KEY};
+use error::{EntryFailure, Error};
+use field::read_octal;
+use framing::{is_extension, is_zero_block, pax_number, Frame, Framer, ParseOptions, ParseWarning, Pending};
+use parser::{padding, parse_header, ExtraHeader, PosixHeader, TypeFlag};
+use paths::{self, glob_match};
+use sums::{copy, read_block};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Owned entry metadata
+ */
+
+/// Owned copy of an entry header, with the location of the entry in its archive.
+/// Entries sort by path first.
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EntryMetadata {
+    pub path:             String,
+    pub mode:             u64,
+    pub uid:              u64,
+    pub gid:              u64,
+    pub size:             u64,
+    pub mtime:            u64,
+    pub typeflag:         TypeFlag,
+    pub linkname:         String,
+    pub uname:            String,
+    pub gname:            String,
+    pub devmajor:         u64,
+    pub devminor:         u64,
+    /// Offset of the first PAX or GNU long name record applying to this
+    /// entry, equal to `header_offset` when there is none
+    pub extension_offset: u64,
+    pub header_offset:    u64,
+    pub data_offset:      u64,
+    /// Effective PAX records, global ones included
+    pub pax:              BTreeMap<String, Vec<u8>>
+}
+
+impl EntryMetadata {
+    pub fn from_header(h: &PosixHeader, header_offset: u64, data_offset: u64) -> Result<EntryMetadata, Error> {
+        let mode = read_octal(h.mode.as_bytes()).ok_or(Error::InvalidField { offset: header_offset, field: "mode" })?;
+        let (uname, gname, devmajor, devminor) = match h.ustar {
+            ExtraHeader::UStar(ref u) => (u.uname, u.gname, u.devmajor, u.devminor),
+            ExtraHeader::Padding => ("", "", 0, 0)
+        };
+        Ok(EntryMetadata {
+            path:             h.path().into_owned(),
+            mode:             mode,
+            uid:              h.uid,
+            gid:              h.gid,
+            size:             h.size,
+            mtime:            h.mtime,
+            typeflag:         h.typeflag,
+            linkname:         h.linkname.to_owned(),
+            uname:            uname.to_owned(),
+            gname:            gname.to_owned(),
+            devmajor:         devmajor,
+            devminor:         devminor,
+            extension_offset: header_offset,
+            header_offset:    header_offset,
+            data_offset:      data_offset,
+            pax:              BTreeMap::new()
+        })
+    }
+
+    /// Digest of the contents recorded when the archive was written, if any
+    pub fn recorded_digest(&self) -> Option<Digest> {
+        self.pax.get(PAX_DIGEST_KEY).and_then(|v| Digest::from_hex(&String::from_utf8_lossy(v)))
+    }
+
+    /// Access time from a PAX `atime` record, in whole seconds
+    pub fn atime(&self) -> Option<u64> {
+        self.pax.get("atime").and_then(|v| pax_number(v))
+    }
+
+    /// Status change time from a PAX `ctime` record, in whole seconds
+    pub fn ctime(&self) -> Option<u64> {
+        self.pax.get("ctime").and_then(|v| pax_number(v))
+    }
+}
+
+/*
+ * Backing storage
+ */
+
+enum Storage {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap)
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            Storage::Owned(ref v) => v,
+            #[cfg(feature = "mmap")]
+            Storage::Mapped(ref m) => m
+        }
+    }
+}
+
+/*
+ * Top level grouping
+ */
+
+/// Entries sharing the same first path component
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TopLevelGroup {
+    pub name:    String,
+    /// Whether this is a directory, stored or implied by deeper entries
+    pub is_dir:  bool,
+    pub entries: u64,
+    /// Contents size of all the entries of the group
+    pub bytes:   u64
+}
+
+/*
+ * Shared archive handle
+ */
+
+struct Inner {
+    data:     Storage,
+    volume:   Option<EntryMetadata>,
+    entries:  Vec<EntryMetadata>,
+    index:    HashMap<String, usize>,
+    warnings: Vec<ParseWarning>,
+    /* Start of the bytes after the terminator */
+    trailing: Option<usize>
+}
+
+/// A parsed archive sharing its buffer and entry table between clones,
+/// so it can be handed out to many threads without copying.
+#[derive(Clone)]
+pub struct Archive {
+    inner: Arc<Inner>
+}
+
+/* Directories are looked up with or without their trailing slash */
+pub(crate) fn index_key(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => path,
+        p => p
+    }
+}
+
+/* Where data the terminator starting at `pos` is followed by begins */
+fn trailing_start(data: &[u8], mut pos: usize) -> Option<usize> {
+    while data.len() - pos >= 512 && data[pos..pos + 512].iter().all(|b| *b == 0) {
+        pos += 512;
+    }
+    /* Record padding cut short is not data */
+    if data[pos..].iter().all(|b| *b == 0) { None } else { Some(pos) }
+}
+
+type Parsed = (Vec<EntryMetadata>, Vec<ParseWarning>, Option<usize>);
+
+fn parse_entries(data: &[u8], options: &ParseOptions) -> Result<Parsed, Error> {
+    let mut framer = Framer::new(options.clone());
+    let mut entries = Vec::new();
+    let mut warnings = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        match framer.next(data, pos, 0)? {
+            Frame::Entry { metadata, end } => {
+                entries.push(*metadata);
+                pos = end;
+            },
+            Frame::ZeroBlock if options.trailing_data => {
+                let trailing = trailing_start(data, pos);
+                if let Some(start) = trailing {
+                    warnings.push(ParseWarning::TrailingData { offset: start as u64, length: (data.len() - start) as u64 });
+                }
+                return Ok((entries, warnings, trailing));
+            },
+            /* Terminators end the archive, or pad its last record */
+            Frame::ZeroBlock => pos += 512,
+            Frame::Empty { offset, end } => {
+                warnings.push(ParseWarning::EmptyName { offset: offset });
+                pos = end;
+            },
+            Frame::Incomplete => return Err(Error::Truncated { offset: pos as u64 })
+        }
+    }
+    Ok((entries, warnings, None))
+}
+
+impl Archive {
+    fn from_storage(data: Storage, options: &ParseOptions) -> Result<Archive, Error> {
+        let (mut entries, warnings, trailing) = parse_entries(&data, options)?;
+        /* A volume label is not an entry, GNU tar only writes one first */
+        let volume = match entries.first() {
+            Some(e) if e.typeflag == TypeFlag::GnuVolumeHeader => Some(entries.remove(0)),
+            _ => None
+        };
+        if let Some(ref pattern) = options.label {
+            let label = volume.as_ref().map(|v| v.path.clone());
+            if !label.as_ref().map(|l| glob_match(pattern, l)).unwrap_or(false) {
+                return Err(Error::LabelMismatch { pattern: pattern.clone(), label: label });
+            }
+        }
+        /* Later entries win, like on extraction */
+        let index = entries.iter().enumerate().map(|(i, e)| (index_key(&e.path).to_owned(), i)).collect();
+        Ok(Archive {
+            inner: Arc::new(Inner {
+                data:     data,
+                volume:   volume,
+                entries:  entries,
+                index:    index,
+                warnings: warnings,
+                trailing: trailing
+            })
+        })
+    }
+
+    /// An archive without entries
+    pub fn empty() -> Archive {
+        Archive::from_storage(Storage::Owned(Vec::new()), &ParseOptions::default()).unwrap()
+    }
+
+    /// Parse an archive held in memory.
+    ///
+    /// Empty input and input made only of terminator blocks give an empty
+    /// archive, while input ending inside a block is `Truncated`.
+    pub fn new(data: Vec<u8>) -> Result<Archive, Error> {
+        Archive::with_options(data, &ParseOptions::default())
+    }
+
+    pub fn with_options(data: Vec<u8>, options: &ParseOptions) -> Result<Archive, Error> {
+        Archive::from_storage(Storage::Owned(data), options)
+    }
+
+    /// Read a whole archive file into memory and parse it
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Archive, Error> {
+        Archive::open_with_options(path, &ParseOptions::default())
+    }
+
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: &ParseOptions) -> Result<Archive, Error> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Archive::with_options(data, options)
+    }
+
+    /// Map an archive file into memory and parse it.
+    ///
+    /// The file must not be modified while the archive is alive.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<Archive, Error> {
+        Archive::open_mmap_with_options(path, &ParseOptions::default())
+    }
+
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap_with_options<P: AsRef<Path>>(path: P, options: &ParseOptions) -> Result<Archive, Error> {
+        let file = File::open(path)?;
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Archive::from_storage(Storage::Mapped(map), options)
+    }
+
+    /// The raw archive bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner.data
+    }
+
+    /// The GNU volume header, if the archive starts with one
+    pub fn volume_header(&self) -> Option<&EntryMetadata> {
+        self.inner.volume.as_ref()
+    }
+
+    /// The volume label set with `tar --label`
+    pub fn label(&self) -> Option<&str> {
+        self.volume_header().map(|v| &v.path[..])
+    }
+
+    /// Whether the volume label matches a glob pattern, like `tar --test-label`
+    pub fn label_matches(&self, pattern: &str) -> bool {
+        self.label().map(|l| glob_match(pattern, l)).unwrap_or(false)
+    }
+
+    /// All entries, in archive order, without the volume header
+    pub fn entries(&self) -> &[EntryMetadata] {
+        &self.inner.entries
+    }
+
+    /// Records skipped while parsing, see `ParseOptions::reject_empty_names`
+    /// and `ParseOptions::trailing_data`
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.inner.warnings
+    }
+
+    /// Offset and bytes following the terminator, when parsed with
+    /// `ParseOptions::trailing_data`, such as an appended signature
+    pub fn trailing_data(&self) -> Option<(u64, &[u8])> {
+        self.inner.trailing.map(|start| (start as u64, &self.as_bytes()[start..]))
+    }
+
+    /// The last entry stored under this path
+    pub fn get(&self, path: &str) -> Option<&EntryMetadata> {
+        self.inner.index.get(index_key(path)).map(|&i| &self.inner.entries[i])
+    }
+
+    /// Entries grouped by first path component, sorted by name. Entries for
+    /// the root itself and paths climbing above it belong to no group.
+    pub fn top_level(&self) -> Vec<TopLevelGroup> {
+        let mut groups: BTreeMap<String, TopLevelGroup> = BTreeMap::new();
+        for e in self.entries() {
+            let path = match paths::normalize(&e.path) {
+                Some(ref p) if !p.is_empty() => p.clone(),
+                _ => continue
+            };
+            let (name, nested) = match path.find('/') {
+                Some(i) => (&path[..i], true),
+                None => (&path[..], false)
+            };
+            let group = groups.entry(name.to_owned()).or_insert_with(|| TopLevelGroup {
+                name:    name.to_owned(),
+                is_dir:  false,
+                entries: 0,
+                bytes:   0
+            });
+            group.is_dir |= nested || e.typeflag == TypeFlag::Directory;
+            group.entries += 1;
+            group.bytes += e.size;
+        }
+        groups.into_values().collect()
+    }
+
+    /// The directory holding everything, for archives following the
+    /// "exactly one top level directory" convention
+    pub fn single_top_level_dir(&self) -> Option<String> {
+        let mut groups = self.top_level();
+        match groups.len() {
+            1 if groups[0].is_dir => groups.pop().map(|g| g.name),
+            _ => None
+        }
+    }
+
+    /// Contents of an entry of this archive.
+    ///
+    /// Panics if the entry does not fit in this archive.
+    pub fn contents(&self, entry: &EntryMetadata) -> &[u8] {
+        let start = entry.data_offset as usize;
+        &self.inner.data[start..start + entry.size as usize]
+    }
+
+    /// Check the contents of an entry against its recorded digest. Returns
+    /// whether there was a digest to check.
+    pub fn verify(&self, entry: &EntryMetadata) -> Result<bool, Error> {
+        let expected = match entry.recorded_digest() {
+            Some(d) => d,
+            None => return Ok(false)
+        };
+        let actual = sha256(self.contents(entry));
+        if actual != expected {
+            return Err(Error::DigestMismatch { path: entry.path.clone(), expected: expected, actual: actual });
+        }
+        Ok(true)
+    }
+
+    /// Verify every entry with a recorded digest, returning how many were checked
+    pub fn verify_digests(&self) -> Result<u64, Error> {
+        let mut verified = 0;
+        for e in self.entries() {
+            if self.verify(e)? {
+                verified += 1;
+            }
+        }
+        Ok(verified)
+    }
+
+    /// Like `verify_digests`, carrying on past mismatches. Gives how many
+    /// entries were checked and those that failed.
+    pub fn check_digests(&self) -> (u64, Vec<EntryFailure>) {
+        let mut checked = 0;
+        let mut failures = Vec::new();
+        for e in self.entries() {
+            match self.verify(e) {
+                Ok(false) => {},
+                Ok(true) => checked += 1,
+                Err(err) => {
+                    checked += 1;
+                    failures.push(EntryFailure::new(&e.path, e.extension_offset, &err));
+                }
+            }
+        }
+        (checked, failures)
+    }
+
+    /// Predicted peak memory of running an operation on this archive, see
+    /// `estimate_resources`
+    pub fn estimated_peak_memory(&self, options: &EstimateOptions) -> u64 {
+        estimate_resources(self.entries(), options).peak_memory
+    }
+
+    /// Contents of the last entry stored under this path
+    pub fn contents_of(&self, path: &str) -> Option<&[u8]> {
+        self.get(path).map(|e| self.contents(e))
+    }
+}
+
+/// Read only the volume label of an archive, without parsing its entries
+pub fn read_label<R: Read>(mut reader: R) -> Result<Option<String>, Error> {
+    let mut block = [0u8; 512];
+    let mut len = 0;
+    while len < block.len() {
+        match reader.read(&mut block[len..])? {
+            0 => break,
+            n => len += n
+        }
+    }
+    if len < block.len() {
+        return Ok(None);
+    }
+    match parse_header(&block) {
+        IResult::Done(_, h) if h.typeflag == TypeFlag::GnuVolumeHeader => Ok(Some(h.path().into_owned())),
+        IResult::Done(_, _) => Ok(None),
+        _ if block.iter().all(|b| *b == 0) => Ok(None),
+        _ => Err(Error::InvalidHeader { offset: 0 })
+    }
+}
+
+/// Entries of an archive read from `reader`, seeking over their contents
+/// instead of reading them. A volume label is left out, as by `Archive`.
+pub fn read_entries<R: Read + Seek>(mut reader: R, options: &ParseOptions) -> Result<Vec<EntryMetadata>, Error> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut framer = Framer::new(options.clone());
+    let mut pending = Pending::default();
+    let mut extension_offset = None;
+    let mut offset = 0;
+    let mut entries = Vec::new();
+    let mut block = [0u8; 512];
+    while read_block(&mut reader, &mut block, extension_offset.unwrap_or(offset))? {
+        if is_zero_block(&block, extension_offset)? {
+            offset += 512;
+            continue;
+        }
+        let header = match parse_header(&block) {
+            IResult::Done(_, h) => h,
+            _ => return Err(Error::InvalidHeader { offset: offset })
+        };
+        let first = *extension_offset.get_or_insert(offset);
+        let size = header.size;
+
+        if is_extension(header.typeflag) {
+            framer.check_extension(&mut pending, size, offset)?;
+            let mut record = Vec::with_capacity(size as usize);
+            copy(&mut reader, size + padding(size), &mut record, first)?;
+            framer.push_extension(&mut pending, header.typeflag, &record[..size as usize], offset)?;
+            offset += 512 + size + padding(size);
+            continue;
+        }
+
+        extension_offset = None;
+        let pending = mem::take(&mut pending);
+        let size = if framer.is_empty(&pending, &header, offset)? {
+            size
+        } else {
+            let metadata = framer.metadata(pending, &header, first, offset, offset + 512)?;
+            let size = metadata.size;
+            entries.push(metadata);
+            size
+        };
+        if offset + 512 + size > len {
+            return Err(Error::Truncated { offset: first });
+        }
+        offset += 512 + size + padding(size);
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+    if let Some(o) = extension_offset {
+        return Err(Error::Truncated { offset: o });
+    }
+    if entries.first().map(|e| e.typeflag == TypeFlag::GnuVolumeHeader).unwrap_or(false) {
+        entries.remove(0);
+    }
+    Ok(entries)
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn shared_lookups_test() {
+        assert_send_sync::<Archive>();
+
+        let archive = Archive::new(include_bytes!("../examples/simple/test.tar").to_vec()).unwrap();
+        assert_eq!(archive.entries().len(), 4);
+        assert_eq!(archive.get("test").unwrap().typeflag, TypeFlag::Directory);
+        assert_eq!(archive.get("test/bar").unwrap().mode, 0o644);
+
+        let handles = ["bar", "baz", "foo"].iter().map(|name| {
+            let archive = archive.clone();
+            thread::spawn(move || {
+                let contents = archive.contents_of(&format!("test/{}", name)).unwrap();
+                assert_eq!(contents, format!("This is {}\n", name).as_bytes());
+            })
+        }).collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(archive.get("test/qux").is_none());
+    }
+
+    #[test]
+    fn truncated_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        match Archive::new(tar[..1000].to_vec()) {
+            Err(Error::Truncated { offset: 512 }) => {},
+            r => panic!("unexpected result: {:?}", r.map(|a| a.entries().len()))
+        }
+    }
+
+    #[test]
+    fn empty_archives_test() {
+        assert!(Archive::empty().entries().is_empty());
+        assert!(Archive::new(Vec::new()).unwrap().entries().is_empty());
+        assert!(Archive::new(vec![0; 1024]).unwrap().entries().is_empty());
+        /* Some writers only emit a single terminator block */
+        assert!(Archive::new(vec![0; 512]).unwrap().entries().is_empty());
+        match Archive::new(vec![0; 1000]) {
+            Err(Error::Truncated { offset: 512 }) => {},
+            r => panic!("unexpected result: {:?}", r.map(|a| a.entries().len()))
+        }
+    }
+
+    #[test]
+    fn trailing_data_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let options = ParseOptions { trailing_data: true, ..ParseOptions::default() };
+        /* Padding cut short */
+        let archive = Archive::with_options(tar[..4700].to_vec(), &options).unwrap();
+        assert_eq!((archive.entries().len(), archive.trailing_data()), (4, None));
+        assert!(Archive::new(tar[..4700].to_vec()).is_err());
+
+        let mut data = tar.to_vec();
+        data.extend_from_slice(b"signature");
+        let archive = Archive::with_options(data.clone(), &options).unwrap();
+        assert_eq!(archive.entries().len(), 4);
+        assert_eq!(archive.trailing_data(), Some((10240, &b"signature"[..])));
+        assert_eq!(archive.warnings(), &[ParseWarning::TrailingData { offset: 10240, length: 9 }]);
+        match Archive::new(data) {
+            Err(Error::Truncated { offset: 10240 }) => {},
+            r => panic!("unexpected result: {:?}", r.map(|a| a.entries().len()))
+        }
+    }
+
+    #[test]
+    fn empty_names_test() {
+        let mut data = include_bytes!("../examples/simple/test.tar").to_vec();
+        /* Wipe the name of test/bar */
+        for b in &mut data[512..612] {
+            *b = 0;
+        }
+        let archive = Archive::new(data.clone()).unwrap();
+        assert_eq!(archive.entries().len(), 3);
+        assert_eq!(archive.warnings(), &[ParseWarning::EmptyName { offset: 512 }]);
+
+        let options = ParseOptions {
+            reject_empty_names: true,
+            ..ParseOptions::default()
+        };
+        match Archive::with_options(data, &options) {
+            Err(Error::InvalidField { offset: 512, field: "name" }) => {},
+            r => panic!("unexpected result: {:?}", r.map(|a| a.entries().len()))
+        }
+    }
+
+    #[test]
+    fn zero_size_entries_test() {
+        use builder::{Builder, Header};
+
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("empty"), b"").unwrap();
+        b.append(&Header::new("next"), b"x").unwrap();
+        let mut data = b.finish().unwrap();
+        let archive = Archive::new(data.clone()).unwrap();
+        let empty = archive.get("empty").unwrap();
+        assert_eq!((empty.size, empty.data_offset), (0, 512));
+        assert_eq!(archive.contents(empty), b"");
+        assert_eq!(archive.get("next").unwrap().header_offset, 512);
+
+        /* A zero size entry may be the very last block, without terminator */
+        data.truncate(512);
+        let archive = Archive::new(data).unwrap();
+        assert_eq!(archive.contents_of("empty"), Some(&b""[..]));
+    }
+
+    #[test]
+    fn label_test() {
+        use builder::{Builder, Header};
+
+        let mut b = Builder::new(Vec::new());
+        b.append_label("backup-2015-05-30").unwrap();
+        b.append(&Header::new("a"), b"x").unwrap();
+        assert!(b.append_label("late").is_err());
+        let data = b.finish().unwrap();
+
+        assert_eq!(read_label(&data[..]).unwrap(), Some("backup-2015-05-30".to_owned()));
+        let archive = Archive::new(data.clone()).unwrap();
+        assert_eq!(archive.label(), Some("backup-2015-05-30"));
+        assert!(archive.label_matches("backup-*"));
+        assert_eq!(archive.entries().len(), 1);
+
+        let options = ParseOptions {
+            label: Some("daily-*".to_owned()),
+            ..ParseOptions::default()
+        };
+        match Archive::with_options(data, &options) {
+            Err(Error::LabelMismatch { label: Some(ref l), .. }) if l == "backup-2015-05-30" => {},
+            r => panic!("unexpected result: {:?}", r.map(|a| a.entries().len()))
+        }
+        let tar = include_bytes!("../examples/simple/test.tar");
+        assert_eq!(read_label(&tar[..]).unwrap(), None);
+        assert!(Archive::with_options(tar.to_vec(), &options).is_err());
+    }
+
+    #[test]
+    fn read_entries_test() {
+        use std::io::Cursor;
+
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let entries = read_entries(Cursor::new(&tar[..]), &ParseOptions::default()).unwrap();
+        assert_eq!(&entries[..], Archive::new(tar.to_vec()).unwrap().entries());
+        match read_entries(Cursor::new(&tar[..1000]), &ParseOptions::default()) {
+            Err(Error::Truncated { offset: 512 }) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+
+    #[test]
+    fn top_level_test() {
+        use builder::{Builder, Header};
+
+        let archive = Archive::new(include_bytes!("../examples/simple/test.tar").to_vec()).unwrap();
+        assert_eq!(archive.top_level(), vec![TopLevelGroup {
+            name:    "test".to_owned(),
+            is_dir:  true,
+            entries: 4,
+            bytes:   36
+        }]);
+        assert_eq!(archive.single_top_level_dir(), Some("test".to_owned()));
+
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("./"), b"").unwrap();
+        b.append(&Header::new("./pkg/a"), b"ab").unwrap();
+        b.append(&Header::new("README"), b"abc").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        let groups = archive.top_level();
+        assert_eq!(groups.iter().map(|g| (&g.name[..], g.is_dir, g.bytes)).collect::<Vec<_>>(),
+                   vec![("README", false, 3), ("pkg", true, 2)]);
+        assert_eq!(archive.single_top_level_dir(), None);
+    }
+
+    #[test]
+    fn verify_digests_test() {
+        use builder::{Builder, Header};
+
+        let mut b = Builder::new(Vec::new());
+        b.record_digests(true);
+        b.append(&Header::new("a"), b"hello").unwrap();
+        b.append(&Header::new("empty"), b"").unwrap();
+        let mut data = b.finish().unwrap();
+        let archive = Archive::new(data.clone()).unwrap();
+        assert_eq!(archive.get("a").unwrap().recorded_digest(), Some(sha256(b"hello")));
+        assert_eq!(archive.verify_digests().unwrap(), 2);
+
+        let pos = archive.get("a").unwrap().data_offset as usize;
+        data[pos] = b'j';
+        let archive = Archive::new(data).unwrap();
+        match archive.verify_digests() {
+            Err(Error::DigestMismatch { ref path, actual, .. }) if path == "a" && actual == sha256(b"jello") => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        let (checked, failures) = archive.check_digests();
+        assert_eq!(checked, 2);
+        assert_eq!(failures.iter().map(|f| (&f.path[..], &f.kind[..])).collect::<Vec<_>>(), vec![("a", "digest_mismatch")]);
+    }
+}
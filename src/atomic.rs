@@ -0,0 +1,136 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+ * Atomic file replacement
+ */
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A file written under a temporary name next to its destination, and
+/// renamed over it only once complete. Dropping it without committing
+/// removes the temporary file, so the destination is never half written.
+pub struct AtomicFile {
+    file:      Option<File>,
+    temp:      PathBuf,
+    dest:      PathBuf,
+    committed: bool
+}
+
+impl AtomicFile {
+    pub fn create<P: AsRef<Path>>(dest: P) -> io::Result<AtomicFile> {
+        let dest = dest.as_ref().to_path_buf();
+        let name = dest.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "destination has no file name"))?;
+        loop {
+            /* Same directory, so the final rename never crosses filesystems */
+            let temp = dest.with_file_name(format!(".{}.{}.{}.tmp", name.to_string_lossy(), process::id(), COUNTER.fetch_add(1, Ordering::SeqCst)));
+            match OpenOptions::new().write(true).read(true).create_new(true).open(&temp) {
+                Ok(file) => return Ok(AtomicFile {
+                    file:      Some(file),
+                    temp:      temp,
+                    dest:      dest,
+                    committed: false
+                }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    /// Where the file will be once committed
+    pub fn path(&self) -> &Path {
+        &self.dest
+    }
+
+    fn file(&mut self) -> &mut File {
+        self.file.as_mut().expect("file used after commit")
+    }
+
+    /// Flush to disk and rename over the destination
+    pub fn commit(mut self) -> io::Result<()> {
+        let file = self.file.take().expect("file committed twice");
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.temp, &self.dest)?;
+        self.committed = true;
+        /* Make the rename itself durable */
+        #[cfg(unix)]
+        {
+            if let Some(dir) = self.dest.parent() {
+                let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+                File::open(dir)?.sync_all()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file().seek(pos)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        /* Also when committing failed half way */
+        if !self.committed {
+            drop(self.file.take());
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use builder::{Builder, Header};
+
+    #[test]
+    fn commit_or_discard_test() {
+        let dir = env::temp_dir().join(format!("tar-atomic-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("out.tar");
+
+        let mut builder = Builder::create_atomic(&dest).unwrap();
+        builder.append(&Header::new("a"), b"x").unwrap();
+        drop(builder);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::write(&dest, b"old").unwrap();
+        let mut builder = Builder::create_atomic(&dest).unwrap();
+        builder.append(&Header::new("a"), b"x").unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"old");
+        builder.commit().unwrap();
+        assert_eq!(fs::metadata(&dest).unwrap().len(), 512 * 4);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        /* A failed rename leaves nothing behind either */
+        let taken = dir.join("taken");
+        fs::create_dir_all(taken.join("inside")).unwrap();
+        let mut file = AtomicFile::create(&taken).unwrap();
+        file.write_all(b"x").unwrap();
+        assert!(file.commit().is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use archive::Archive;
+use error::Error;
+use framing::ParseOptions;
+
+/*
+ * Processing many archives with one configuration
+ */
+
+/// Where an archive of a batch comes from
+pub enum Source {
+    Path(PathBuf),
+    /// A named reader, read to its end before parsing
+    Reader(String, Box<dyn Read + Send>)
+}
+
+impl Source {
+    /// Name under which results are reported
+    pub fn name(&self) -> String {
+        match *self {
+            Source::Path(ref p) => p.display().to_string(),
+            Source::Reader(ref name, _) => name.clone()
+        }
+    }
+
+    fn load(self, options: &ParseOptions) -> Result<Archive, Error> {
+        let mut data = Vec::new();
+        match self {
+            Source::Path(p) => File::open(p)?.read_to_end(&mut data)?,
+            Source::Reader(_, mut r) => r.read_to_end(&mut data)?
+        };
+        Archive::with_options(data, options)
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct BatchOptions {
+    pub parse:   ParseOptions,
+    /// Archives processed at the same time, 1 processing them in order on
+    /// the calling thread
+    pub threads: usize
+}
+
+impl Default for BatchOptions {
+    fn default() -> BatchOptions {
+        BatchOptions {
+            parse:   ParseOptions::default(),
+            threads: 1
+        }
+    }
+}
+
+/// What happened to one archive of a batch
+#[derive(Debug)]
+pub struct BatchResult<T> {
+    pub name:    String,
+    /// The callback result, or why the archive could not be processed
+    pub outcome: Result<T, Error>
+}
+
+/// Results of a batch in source order, with aggregate statistics
+#[derive(Debug)]
+pub struct BatchReport<T> {
+    pub results:   Vec<BatchResult<T>>,
+    /// Archives that parsed and whose callback succeeded
+    pub succeeded: u64,
+    pub failed:    u64,
+    /// Entries and bytes of the archives that parsed
+    pub entries:   u64,
+    pub bytes:     u64
+}
+
+impl<T> BatchReport<T> {
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &Error)> {
+        self.results.iter().filter_map(|r| r.outcome.as_ref().err().map(|e| (&r.name[..], e)))
+    }
+}
+
+/* An archive parsed, and what the callback made of it */
+struct Processed<T> {
+    outcome: Result<T, Error>,
+    entries: u64,
+    bytes:   u64
+}
+
+fn process_one<T, F>(source: Source, options: &ParseOptions, f: &F) -> Processed<T>
+    where F: Fn(&str, &Archive) -> Result<T, Error> {
+    let name = source.name();
+    match source.load(options) {
+        Ok(archive) => Processed {
+            outcome: f(&name, &archive),
+            entries: archive.entries().len() as u64,
+            bytes:   archive.as_bytes().len() as u64
+        },
+        Err(e) => Processed {
+            outcome: Err(e),
+            entries: 0,
+            bytes:   0
+        }
+    }
+}
+
+/// Parse every source and hand it to `f` along with its name. A failing
+/// archive is recorded in the report and does not stop the batch.
+pub fn process<T, F>(sources: Vec<Source>, options: &BatchOptions, f: F) -> BatchReport<T>
+    where T: Send, F: Fn(&str, &Archive) -> Result<T, Error> + Sync {
+    let names = sources.iter().map(|s| s.name()).collect::<Vec<_>>();
+    let processed: Vec<Processed<T>> = if options.threads <= 1 {
+        sources.into_iter().map(|s| process_one(s, &options.parse, &f)).collect()
+    } else {
+        let queue = Mutex::new(sources.into_iter().map(Some).collect::<Vec<_>>());
+        let done = Mutex::new((0..names.len()).map(|_| None).collect::<Vec<_>>());
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for _ in 0..options.threads.min(names.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let source = match queue.lock().unwrap().get_mut(i) {
+                        Some(s) => s.take().unwrap(),
+                        None => break
+                    };
+                    let p = process_one(source, &options.parse, &f);
+                    done.lock().unwrap()[i] = Some(p);
+                });
+            }
+        });
+        done.into_inner().unwrap().into_iter().map(|p| p.unwrap()).collect()
+    };
+
+    let mut report = BatchReport {
+        results:   Vec::with_capacity(names.len()),
+        succeeded: 0,
+        failed:    0,
+        entries:   0,
+        bytes:     0
+    };
+    for (name, p) in names.into_iter().zip(processed) {
+        if p.outcome.is_ok() {
+            report.succeeded += 1;
+        } else {
+            report.failed += 1;
+        }
+        report.entries += p.entries;
+        report.bytes += p.bytes;
+        report.results.push(BatchResult {
+            name:    name,
+            outcome: p.outcome
+        });
+    }
+    report
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sources() -> Vec<Source> {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        vec![
+            Source::Path(PathBuf::from("examples/simple/test.tar")),
+            Source::Reader("truncated".to_owned(), Box::new(Cursor::new(tar[..1000].to_vec()))),
+            Source::Path(PathBuf::from("examples/simple/missing.tar")),
+            Source::Reader("empty".to_owned(), Box::new(Cursor::new(Vec::new())))
+        ]
+    }
+
+    #[test]
+    fn process_test() {
+        for threads in 1..4 {
+            let options = BatchOptions {
+                threads: threads,
+                ..BatchOptions::default()
+            };
+            let report = process(sources(), &options, |_, archive| Ok(archive.entries().len()));
+            let outcomes = report.results.iter().map(|r| r.outcome.as_ref().ok().cloned()).collect::<Vec<_>>();
+            assert_eq!(outcomes, vec![Some(4), None, None, Some(0)]);
+            assert_eq!((report.succeeded, report.failed, report.entries, report.bytes), (2, 2, 4, 10240));
+            let failures = report.failures().map(|(name, e)| (name, e.kind())).collect::<Vec<_>>();
+            assert_eq!(failures, vec![("truncated", "truncated"), ("examples/simple/missing.tar", "io")]);
+        }
+    }
+}
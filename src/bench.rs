@@ -0,0 +1,230 @@
+use std::cmp;
+use std::io;
+use std::time::{Duration, Instant};
+
+use archive::Archive;
+use builder::{split_path, Builder, Header};
+use digest::sha256;
+use error::Error;
+use parser::TypeFlag;
+use validate::{validate_stream, ValidateOptions};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Synthetic archives and throughput measurements
+ */
+
+/// How contents sizes are spread
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SizeDistribution {
+    Fixed(u64),
+    Uniform { min: u64, max: u64 },
+    /// Uniform over orders of magnitude, like real trees: mostly small
+    /// files and a few large ones
+    LogUniform { min: u64, max: u64 }
+}
+
+/// What a synthetic archive looks like
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ArchiveShape {
+    pub entries:      usize,
+    pub sizes:        SizeDistribution,
+    /// Shortest and longest path lengths. Paths over 255 bytes get GNU
+    /// long name records.
+    pub name_length:  (usize, usize),
+    /// Share of the contents made of zero filled 4 KiB holes, as in sparse
+    /// files stored in full
+    pub sparse_ratio: f64,
+    /// Same seed, same archive
+    pub seed:         u64
+}
+
+impl Default for ArchiveShape {
+    fn default() -> ArchiveShape {
+        ArchiveShape {
+            entries:      1000,
+            sizes:        SizeDistribution::LogUniform { min: 0, max: 1 << 20 },
+            name_length:  (8, 64),
+            sparse_ratio: 0.0,
+            seed:         1
+        }
+    }
+}
+
+/* xorshift64*, good enough to shape data and free of dependencies */
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /* Uniform in `min..=max` */
+    fn range(&mut self, min: u64, max: u64) -> u64 {
+        if max <= min { min } else { min + self.next() % (max - min + 1) }
+    }
+}
+
+fn size(rng: &mut Rng, sizes: SizeDistribution) -> u64 {
+    match sizes {
+        SizeDistribution::Fixed(size) => size,
+        SizeDistribution::Uniform { min, max } => rng.range(min, max),
+        SizeDistribution::LogUniform { min, max } => {
+            let (low, high) = (((min + 1) as f64).ln(), ((max + 1) as f64).ln());
+            let size = (low + rng.unit() * (high - low)).exp() as u64 - 1;
+            size.max(min).min(max)
+        }
+    }
+}
+
+/* A unique path of the given length, in directories of up to 16 bytes */
+fn name(rng: &mut Rng, i: usize, len: usize) -> String {
+    let mut name = format!("{:x}", i);
+    while name.len() < len {
+        let component = cmp::min(16, len - name.len());
+        if component == 1 {
+            name.push('_');
+            continue;
+        }
+        name.insert(0, '/');
+        for _ in 1..component {
+            name.insert(0, (b'a' + (rng.next() % 26) as u8) as char);
+        }
+    }
+    name
+}
+
+fn contents(rng: &mut Rng, size: u64, sparse_ratio: f64) -> Vec<u8> {
+    const HOLE: usize = 4096;
+    let mut data = Vec::with_capacity(size as usize);
+    while data.len() < size as usize {
+        let len = cmp::min(HOLE, size as usize - data.len());
+        if rng.unit() < sparse_ratio {
+            data.resize(data.len() + len, 0);
+        } else {
+            data.extend((0..len).map(|_| b'a' + (rng.next() % 26) as u8));
+        }
+    }
+    data
+}
+
+/// Build an archive of the given shape
+pub fn generate(shape: &ArchiveShape) -> Vec<u8> {
+    let mut rng = Rng::new(shape.seed);
+    let mut b = Builder::new(Vec::new());
+    for i in 0..shape.entries {
+        let len = rng.range(shape.name_length.0 as u64, shape.name_length.1 as u64) as usize;
+        let path = name(&mut rng, i, len);
+        if split_path(&path).is_err() {
+            let mut record = Header::new("././@LongLink");
+            record.typeflag = TypeFlag::GnuLongName;
+            b.append(&record, path.as_bytes()).expect("long name records always fit");
+        }
+        let size = size(&mut rng, shape.sizes);
+        let data = contents(&mut rng, size, shape.sparse_ratio);
+        /* The long name record supplies the real path */
+        let header = Header::new(if split_path(&path).is_ok() { &path } else { "long" });
+        b.append(&header, &data).expect("synthetic headers always fit");
+    }
+    b.finish().expect("writing to memory cannot fail")
+}
+
+/// Timing of one phase, the fastest of the measured runs
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Phase {
+    /// `parse_headers`, `digest_contents` or `validate_stream`
+    pub name:    String,
+    pub entries:      u64,
+    pub bytes:   u64,
+    pub elapsed: Duration
+}
+
+impl Phase {
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    pub fn entries_per_second(&self) -> f64 {
+        self.entries as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+fn best<T, F: FnMut() -> Result<T, Error>>(runs: u32, mut f: F) -> Result<(T, Duration), Error> {
+    let mut fastest = None;
+    for _ in 0..runs.max(1) {
+        let start = Instant::now();
+        let result = f()?;
+        let elapsed = start.elapsed();
+        if fastest.as_ref().map(|&(_, d)| elapsed < d).unwrap_or(true) {
+            fastest = Some((result, elapsed));
+        }
+    }
+    Ok(fastest.expect("at least one run"))
+}
+
+/// Time header parsing, content handling and streaming validation of an
+/// archive separately, keeping the fastest of `runs` runs of each
+pub fn measure(data: &[u8], runs: u32) -> Result<Vec<Phase>, Error> {
+    let (archive, parse) = best(runs, || Archive::new(data.to_vec()))?;
+    let entries = archive.entries().len() as u64;
+    let content_bytes = archive.entries().iter().map(|e| e.size).sum();
+    let (_, digest) = best(runs, || Ok(archive.entries().iter().map(|e| sha256(archive.contents(e))).collect::<Vec<_>>()))?;
+    let (report, validate) = best(runs, || validate_stream(io::Cursor::new(data), &ValidateOptions::default()))?;
+    Ok(vec![
+        Phase { name: "parse_headers".to_owned(), entries: entries, bytes: data.len() as u64, elapsed: parse },
+        Phase { name: "digest_contents".to_owned(), entries: entries, bytes: content_bytes, elapsed: digest },
+        Phase { name: "validate_stream".to_owned(), entries: report.entries, bytes: report.bytes, elapsed: validate }
+    ])
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_test() {
+        let shape = ArchiveShape {
+            entries:      50,
+            sizes:        SizeDistribution::Uniform { min: 100, max: 20000 },
+            name_length:  (1, 300),
+            sparse_ratio: 0.5,
+            seed:         7
+        };
+        let data = generate(&shape);
+        assert_eq!(data, generate(&shape));
+        let archive = Archive::new(data.clone()).unwrap();
+        assert_eq!(archive.entries().len(), 50);
+        for e in archive.entries() {
+            assert!(e.size >= 100 && e.size <= 20000);
+            assert!(!e.path.is_empty() && e.path.len() <= 300);
+        }
+        assert!(archive.entries().iter().any(|e| e.path.len() > 255));
+        let zeroes = archive.entries().iter().map(|e| archive.contents(e).iter().filter(|b| **b == 0).count()).sum::<usize>();
+        let total = archive.entries().iter().map(|e| e.size as usize).sum::<usize>();
+        assert!(zeroes > total / 4 && zeroes < total * 3 / 4);
+
+        let phases = measure(&data, 2).unwrap();
+        assert_eq!(phases.iter().map(|p| &p.name[..]).collect::<Vec<_>>(), vec!["parse_headers", "digest_contents", "validate_stream"]);
+        assert!(phases.iter().all(|p| p.entries == 50 && p.bytes_per_second() > 0.0));
+    }
+}
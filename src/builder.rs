@@ -0,0 +1,869 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use archive::EntryMetadata;
+use atomic::AtomicFile;
+use digest::{sha256, Digest, DigestWriter, PAX_DIGEST_KEY};
+use field::{write_checksum, write_number, write_octal, write_str};
+use framing::pax_number;
+use parser::{padding, TypeFlag};
+use provenance::{BuildReport, Provenance, ReportEntry};
+use transform::{Chain, Transform};
+
+pub use field::checksum;
+
+/*
+ * Builder input
+ */
+
+/// Metadata of an entry to be written, the size is taken from its contents
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct Header {
+    pub path:     String,
+    pub mode:     u64,
+    pub uid:      u64,
+    pub gid:      u64,
+    pub mtime:    u64,
+    pub typeflag: TypeFlag,
+    pub linkname: String,
+    pub uname:    String,
+    pub gname:    String,
+    pub devmajor: u64,
+    pub devminor: u64
+}
+
+impl Header {
+    /// A regular file with mode 0644 owned by root
+    pub fn new(path: &str) -> Header {
+        Header {
+            path:     path.to_owned(),
+            mode:     0o644,
+            uid:      0,
+            gid:      0,
+            mtime:    0,
+            typeflag: TypeFlag::NormalFile,
+            linkname: String::new(),
+            uname:    String::new(),
+            gname:    String::new(),
+            devmajor: 0,
+            devminor: 0
+        }
+    }
+
+    /// Serialize as a ustar header block for contents of the given size.
+    /// Numbers too large for octal are stored in GNU base-256.
+    pub fn to_block(&self, size: u64) -> io::Result<[u8; 512]> {
+        let mut block = [0u8; 512];
+        let (prefix, name) = split_path(&self.path)?;
+
+        write_str(&mut block[0..100], name)?;
+        write_octal(&mut block[100..108], self.mode)?;
+        write_number(&mut block[108..116], self.uid)?;
+        write_number(&mut block[116..124], self.gid)?;
+        write_number(&mut block[124..136], size)?;
+        write_number(&mut block[136..148], self.mtime)?;
+        block[156] = type_flag_to_byte(self.typeflag)?;
+        write_str(&mut block[157..257], &self.linkname)?;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        write_str(&mut block[265..297], &self.uname)?;
+        write_str(&mut block[297..329], &self.gname)?;
+        write_number(&mut block[329..337], self.devmajor)?;
+        write_number(&mut block[337..345], self.devminor)?;
+        write_str(&mut block[345..500], prefix)?;
+
+        write_checksum(&mut block);
+
+        Ok(block)
+    }
+}
+
+impl From<&EntryMetadata> for Header {
+    fn from(e: &EntryMetadata) -> Header {
+        Header {
+            path:     e.path.clone(),
+            mode:     e.mode,
+            uid:      e.uid,
+            gid:      e.gid,
+            mtime:    e.mtime,
+            typeflag: e.typeflag,
+            linkname: e.linkname.clone(),
+            uname:    e.uname.clone(),
+            gname:    e.gname.clone(),
+            devmajor: e.devmajor,
+            devminor: e.devminor
+        }
+    }
+}
+
+/*
+ * Field encoding
+ */
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_owned())
+}
+
+pub(crate) fn type_flag_to_byte(flag: TypeFlag) -> io::Result<u8> {
+    match flag {
+        TypeFlag::NormalFile => Ok(b'0'),
+        TypeFlag::HardLink => Ok(b'1'),
+        TypeFlag::SymbolicLink => Ok(b'2'),
+        TypeFlag::CharacterSpecial => Ok(b'3'),
+        TypeFlag::BlockSpecial => Ok(b'4'),
+        TypeFlag::Directory => Ok(b'5'),
+        TypeFlag::FIFO => Ok(b'6'),
+        TypeFlag::ContiguousFile => Ok(b'7'),
+        TypeFlag::PaxInterexchangeFormat => Ok(b'g'),
+        TypeFlag::PaxExtendedAttributes => Ok(b'x'),
+        TypeFlag::GnuLongName => Ok(b'L'),
+        TypeFlag::GnuLongLink => Ok(b'K'),
+        TypeFlag::GnuVolumeHeader => Ok(b'V'),
+        TypeFlag::VendorSpecific => Err(invalid_input("vendor specific type flag cannot be written"))
+    }
+}
+
+/* Split a path into ustar (prefix, name), keeping the name non-empty */
+pub(crate) fn split_path(path: &str) -> io::Result<(&str, &str)> {
+    if path.is_empty() {
+        return Err(invalid_input("empty path"));
+    }
+    if path.len() < 100 {
+        return Ok(("", path));
+    }
+    path.match_indices('/')
+        .filter(|&(i, _)| i + 1 < path.len())
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|&(prefix, name)| prefix.len() < 155 && name.len() < 100)
+        .ok_or_else(|| invalid_input("path too long for a ustar header"))
+}
+
+/// Encode one PAX extended header record: `"<len> <key>=<value>\n"`,
+/// the length counting its own digits
+pub fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while (rest + len.to_string().len()) != len {
+        len = rest + len.to_string().len();
+    }
+    let mut record = format!("{} {}=", len, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/*
+ * Archive writer
+ */
+
+/// Contents `append_from` holds in memory before spooling them to a file
+pub const SPOOL_LIMIT: usize = 16 << 20;
+
+static SPOOL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/* A temporary file, removed when dropped */
+struct TempFile {
+    file: File,
+    path: PathBuf
+}
+
+impl TempFile {
+    fn create() -> io::Result<TempFile> {
+        loop {
+            let name = format!("tar-spool.{}.{}", process::id(), SPOOL_COUNTER.fetch_add(1, Ordering::SeqCst));
+            let path = env::temp_dir().join(name);
+            match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok(TempFile { file: file, path: path }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e)
+            }
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/* Contents whose size is not known yet, moved to a file past SPOOL_LIMIT.
+ * Read back from the start after `rewind`. */
+pub(crate) struct Spool {
+    memory: Vec<u8>,
+    file:   Option<TempFile>,
+    size:   u64,
+    /* Where reads are in `memory` */
+    pos:    usize
+}
+
+impl Spool {
+    pub(crate) fn new() -> Spool {
+        Spool {
+            memory: Vec::new(),
+            file:   None,
+            size:   0,
+            pos:    0
+        }
+    }
+
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn len(&self) -> u64 {
+        self.size
+    }
+
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn rewind(&mut self) -> io::Result<()> {
+        self.pos = 0;
+        if let Some(ref mut f) = self.file {
+            f.file.seek(SeekFrom::Start(0))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && self.memory.len() + data.len() > SPOOL_LIMIT {
+            let mut file = TempFile::create()?;
+            file.file.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.file = Some(file);
+        }
+        match self.file {
+            Some(ref mut f) => f.file.write_all(data)?,
+            None => self.memory.extend_from_slice(data)
+        }
+        self.size += data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Spool {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.file {
+            Some(ref mut f) => f.file.read(buf),
+            None => {
+                let n = (&self.memory[self.pos..]).read(buf)?;
+                self.pos += n;
+                Ok(n)
+            }
+        }
+    }
+}
+
+/* Picks the transform to apply to a regular file, if any */
+type TransformFactory = Box<dyn Fn(&Header) -> Option<Box<dyn Transform + Send>> + Send>;
+
+const CHUNK_SIZE: usize = 64 << 10;
+
+/// Writes ustar entries to an underlying writer
+pub struct Builder<W: Write> {
+    inner:      W,
+    started:    bool,
+    digests:    bool,
+    transforms: Vec<TransformFactory>,
+    /* Bytes written so far, where the next header goes */
+    offset:     u64,
+    report:     Option<BuildReport>,
+    /* Origin of the entry being appended by `append_traced` */
+    provenance: Option<Provenance>
+}
+
+impl<W: Write> Builder<W> {
+    pub fn new(inner: W) -> Builder<W> {
+        Builder {
+            inner:      inner,
+            started:    false,
+            digests:    false,
+            transforms: Vec::new(),
+            offset:     0,
+            report:     None,
+            provenance: None
+        }
+    }
+
+    /* Continue an archive whose next header goes at `offset` */
+    pub(crate) fn resume(inner: W, offset: u64) -> Builder<W> {
+        let mut builder = Builder::new(inner);
+        builder.started = offset > 0;
+        builder.offset = offset;
+        builder
+    }
+
+    /* Where the next entry is written */
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Rewrite the contents of the regular files `f` returns a transform
+    /// for. Sizes are computed after transformation, and transforms
+    /// registered first run first.
+    pub fn transform<F>(&mut self, f: F)
+        where F: Fn(&Header) -> Option<Box<dyn Transform + Send>> + Send + 'static {
+        self.transforms.push(Box::new(f));
+    }
+
+    /// Record the SHA-256 digest of each regular file in a PAX record in
+    /// front of it, for `Archive::verify` and extraction to check
+    pub fn record_digests(&mut self, enabled: bool) {
+        self.digests = enabled;
+    }
+
+    /// Keep a report of every entry appended from now on, with where it
+    /// lands in the output and, for `append_traced`, where it came from
+    pub fn track_provenance(&mut self, enabled: bool) {
+        self.report = if enabled { Some(self.report.take().unwrap_or_default()) } else { None };
+    }
+
+    /// Entries written while provenance was tracked
+    pub fn report(&self) -> Option<&BuildReport> {
+        self.report.as_ref()
+    }
+
+    /// Append an entry copied from another archive. The names of the
+    /// transforms the builder applies are added to those of `provenance`.
+    pub fn append_traced(&mut self, header: &Header, contents: &[u8], provenance: Provenance) -> io::Result<()> {
+        self.provenance = Some(provenance);
+        let result = self.append(header, contents);
+        self.provenance = None;
+        result
+    }
+
+    fn record(&mut self, header: &Header, offset: u64, chain: Option<&Chain>) {
+        let provenance = self.provenance.take();
+        if let Some(ref mut report) = self.report {
+            let provenance = provenance.map(|mut p| {
+                p.transforms.extend(chain.iter().flat_map(|c| c.names()).map(|n| n.to_owned()));
+                p
+            });
+            report.entries.push(ReportEntry { path: header.path.clone(), offset: offset, provenance: provenance });
+        }
+    }
+
+    fn transforms_for(&self, header: &Header) -> Option<Chain> {
+        if !is_regular(header) {
+            return None;
+        }
+        let transforms = self.transforms.iter().filter_map(|f| f(header)).collect::<Vec<_>>();
+        if transforms.is_empty() { None } else { Some(Chain::new(transforms)) }
+    }
+
+    /// Write a GNU volume label, like `tar --label`. It must come first.
+    pub fn append_label(&mut self, label: &str) -> io::Result<()> {
+        if self.started {
+            return Err(invalid_input("volume label must be the first entry"));
+        }
+        /* GNU volume headers have no prefix field to spill into */
+        if label.len() >= 100 {
+            return Err(invalid_input("volume label too long"));
+        }
+        let mut header = Header::new(label);
+        header.typeflag = TypeFlag::GnuVolumeHeader;
+        header.mode = 0;
+        self.append(&header, b"")
+    }
+
+    /// Append an entry header followed by its padded contents
+    pub fn append(&mut self, header: &Header, contents: &[u8]) -> io::Result<()> {
+        match self.transforms_for(header) {
+            Some(mut chain) => {
+                let mut out = Vec::with_capacity(contents.len());
+                chain.transform(contents, &mut out);
+                chain.finish(&mut out);
+                self.write_contents(header, &out, Some(&chain))
+            },
+            None => self.write_contents(header, contents, None)
+        }
+    }
+
+    /// Append an entry whose contents are read from `reader` and transformed
+    /// chunk by chunk. The result is held until its size is known: in
+    /// memory up to `SPOOL_LIMIT`, in a temporary file beyond. On seekable
+    /// outputs `append_streaming` writes it directly instead.
+    pub fn append_from<R: Read>(&mut self, header: &Header, mut reader: R) -> io::Result<()> {
+        let mut chain = self.transforms_for(header);
+        let mut spool = Spool::new();
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut out = Vec::new();
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            out.clear();
+            match chain {
+                Some(ref mut c) if n == 0 => c.finish(&mut out),
+                Some(ref mut c) => c.transform(&buf[..n], &mut out),
+                None => out.extend_from_slice(&buf[..n])
+            }
+            spool.write_all(&out)?;
+            if n == 0 {
+                break;
+            }
+        }
+        self.write_spool(header, spool, chain.as_ref())
+    }
+
+    /* Append contents read whole beforehand, as `append_from` would */
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn append_spool(&mut self, header: &Header, mut spool: Spool) -> io::Result<()> {
+        match self.transforms_for(header) {
+            None => self.write_spool(header, spool, None),
+            Some(_) => {
+                spool.rewind()?;
+                self.append_from(header, spool)
+            }
+        }
+    }
+
+    fn write_spool(&mut self, header: &Header, spool: Spool, chain: Option<&Chain>) -> io::Result<()> {
+        let mut temp = match spool.file {
+            None => return self.write_contents(header, &spool.memory, chain),
+            Some(temp) => temp
+        };
+        /* Nothing is written unless the header is valid */
+        let block = header.to_block(spool.size)?;
+        if self.digests && is_regular(header) {
+            let mut hasher = DigestWriter::new();
+            temp.file.seek(SeekFrom::Start(0))?;
+            io::copy(&mut temp.file, &mut hasher)?;
+            self.write_entry(&pax_header(), &digest_record(&hasher.finish()))?;
+        }
+        temp.file.seek(SeekFrom::Start(0))?;
+        let offset = self.offset;
+        self.write_block(&block, (&mut temp.file).take(spool.size), spool.size)?;
+        self.record(header, offset, chain);
+        Ok(())
+    }
+
+    fn write_contents(&mut self, header: &Header, contents: &[u8], chain: Option<&Chain>) -> io::Result<()> {
+        let size = contents.len() as u64;
+        let block = header.to_block(size)?;
+        if self.digests && is_regular(header) {
+            self.write_entry(&pax_header(), &digest_record(&sha256(contents)))?;
+        }
+        let offset = self.offset;
+        self.write_block(&block, contents, size)?;
+        self.record(header, offset, chain);
+        Ok(())
+    }
+
+    fn write_entry(&mut self, header: &Header, contents: &[u8]) -> io::Result<()> {
+        let block = header.to_block(contents.len() as u64)?;
+        self.write_block(&block, contents, contents.len() as u64)
+    }
+
+    /* Write a header block then the `size` bytes of `contents` and their padding */
+    fn write_block<R: Read>(&mut self, block: &[u8; 512], mut contents: R, size: u64) -> io::Result<()> {
+        self.started = true;
+        self.inner.write_all(block)?;
+        if io::copy(&mut contents, &mut self.inner)? != size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "entry contents ended early"));
+        }
+        self.inner.write_all(&[0u8; 512][..padding(size) as usize])?;
+        self.offset += 512 + size + padding(size);
+        Ok(())
+    }
+
+    /// Append an entry read from an archive, as a filter or merge left it.
+    /// What a ustar header cannot hold, such as long paths or numbers too
+    /// large for octal, goes in a PAX record in front of it, along with the
+    /// PAX records of the entry that still agree with its metadata.
+    pub fn append_entry(&mut self, entry: &EntryMetadata, contents: &[u8]) -> io::Result<()> {
+        match self.transforms_for(&Header::from(entry)) {
+            Some(mut chain) => {
+                let mut out = Vec::with_capacity(contents.len());
+                chain.transform(contents, &mut out);
+                chain.finish(&mut out);
+                self.write_copy(entry, &out, Some(&chain))
+            },
+            None => self.write_copy(entry, contents, None)
+        }
+    }
+
+    /// `append_entry` with the origin of the entry, as `append_traced`
+    pub fn append_entry_traced(&mut self, entry: &EntryMetadata, contents: &[u8], provenance: Provenance) -> io::Result<()> {
+        self.provenance = Some(provenance);
+        let result = self.append_entry(entry, contents);
+        self.provenance = None;
+        result
+    }
+
+    fn write_copy(&mut self, entry: &EntryMetadata, contents: &[u8], chain: Option<&Chain>) -> io::Result<()> {
+        let (header, mut records) = copy_header(entry, contents);
+        if self.digests && is_regular(&header) {
+            records.insert(PAX_DIGEST_KEY.to_owned(), sha256(contents).to_hex().into_bytes());
+        }
+        let size = contents.len() as u64;
+        let block = header.to_block(size)?;
+        if !records.is_empty() {
+            let data = records.iter().flat_map(|(k, v)| pax_record(k, v)).collect::<Vec<_>>();
+            self.write_entry(&pax_header(), &data)?;
+        }
+        let offset = self.offset;
+        self.write_block(&block, contents, size)?;
+        self.record(&Header::from(entry), offset, chain);
+        Ok(())
+    }
+
+    /// Write the two terminator blocks and give back the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0u8; 1024])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl Builder<AtomicFile> {
+    /// Write an archive to a temporary file replacing `path` on `commit`
+    pub fn create_atomic<P: AsRef<Path>>(path: P) -> io::Result<Builder<AtomicFile>> {
+        Ok(Builder::new(AtomicFile::create(path)?))
+    }
+
+    /// Finish the archive and atomically move it into place
+    pub fn commit(self) -> io::Result<()> {
+        self.finish()?.commit()
+    }
+}
+
+/* Largest size the octal ustar field holds */
+const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+/* Largest uid or gid the octal ustar fields hold */
+const MAX_OCTAL_ID: u64 = 0o7777777;
+
+/* The longest start of `s` shorter than `len` bytes, left in a ustar
+ * field whose value is in a PAX record */
+fn truncated(s: &str, len: usize) -> &str {
+    let mut end = cmp::min(s.len(), len - 1);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/* The ustar header and PAX records to write an entry read from an archive
+ * again. Records standing for header fields are kept while they agree
+ * with the metadata, a filter may have changed it, and added for fields
+ * the header cannot hold. A digest is kept while it matches `contents`. */
+fn copy_header(entry: &EntryMetadata, contents: &[u8]) -> (Header, BTreeMap<String, Vec<u8>>) {
+    let number = |v: &[u8], n: u64| pax_number(v) == Some(n);
+    let size = contents.len() as u64;
+    let mut records = entry.pax.iter().filter(|&(key, value)| match key.as_str() {
+        "path" => value[..] == *entry.path.as_bytes(),
+        "linkpath" => value[..] == *entry.linkname.as_bytes(),
+        "uname" => value[..] == *entry.uname.as_bytes(),
+        "gname" => value[..] == *entry.gname.as_bytes(),
+        "uid" => number(value, entry.uid),
+        "gid" => number(value, entry.gid),
+        "mtime" => number(value, entry.mtime),
+        "size" => number(value, size),
+        PAX_DIGEST_KEY => entry.recorded_digest() == Some(sha256(contents)),
+        _ => true
+    }).map(|(k, v)| (k.clone(), v.clone())).collect::<BTreeMap<_, _>>();
+
+    let mut header = Header::from(entry);
+    let mut overflow = |key: &str, value: &[u8]| {
+        records.entry(key.to_owned()).or_insert_with(|| value.to_vec());
+    };
+    if !entry.path.is_empty() && split_path(&entry.path).is_err() {
+        overflow("path", entry.path.as_bytes());
+        header.path = truncated(&entry.path, 100).to_owned();
+    }
+    if entry.linkname.len() >= 100 {
+        overflow("linkpath", entry.linkname.as_bytes());
+        header.linkname = truncated(&entry.linkname, 100).to_owned();
+    }
+    if entry.uname.len() >= 32 {
+        overflow("uname", entry.uname.as_bytes());
+        header.uname = truncated(&entry.uname, 32).to_owned();
+    }
+    if entry.gname.len() >= 32 {
+        overflow("gname", entry.gname.as_bytes());
+        header.gname = truncated(&entry.gname, 32).to_owned();
+    }
+    if entry.uid > MAX_OCTAL_ID {
+        overflow("uid", entry.uid.to_string().as_bytes());
+        header.uid = 0;
+    }
+    if entry.gid > MAX_OCTAL_ID {
+        overflow("gid", entry.gid.to_string().as_bytes());
+        header.gid = 0;
+    }
+    if entry.mtime > MAX_OCTAL_SIZE {
+        overflow("mtime", entry.mtime.to_string().as_bytes());
+        header.mtime = 0;
+    }
+    if size > MAX_OCTAL_SIZE {
+        overflow("size", size.to_string().as_bytes());
+    }
+    (header, records)
+}
+
+fn is_regular(header: &Header) -> bool {
+    header.typeflag == TypeFlag::NormalFile || header.typeflag == TypeFlag::ContiguousFile
+}
+
+fn pax_header() -> Header {
+    let mut pax = Header::new("././@PaxHeader");
+    pax.typeflag = TypeFlag::PaxExtendedAttributes;
+    pax
+}
+
+fn digest_record(digest: &Digest) -> Vec<u8> {
+    pax_record(PAX_DIGEST_KEY, digest.to_hex().as_bytes())
+}
+
+/* PAX records of fixed width, so they can be rewritten in place */
+fn streaming_records(size: u64, digest: Option<&Digest>) -> Vec<u8> {
+    let mut records = pax_record("size", format!("{:020}", size).as_bytes());
+    if let Some(d) = digest {
+        records.extend(digest_record(d));
+    }
+    records
+}
+
+impl<W: Write + Seek> Builder<W> {
+    /// Append an entry of unknown length, streaming `reader` straight to the
+    /// output, then seek back to fill in the size. A PAX size record is
+    /// reserved in front of the header, so any size can be stored.
+    /// Returns the size of the contents written.
+    pub fn append_streaming<R: Read>(&mut self, header: &Header, mut reader: R) -> io::Result<u64> {
+        let start = self.inner.stream_position()?;
+        /* Fail on bad fields before writing anything */
+        let block = header.to_block(0)?;
+        let mut hasher = if self.digests && is_regular(header) { Some(DigestWriter::new()) } else { None };
+        let placeholder = Digest([0; 32]);
+        self.write_entry(&pax_header(), &streaming_records(0, hasher.as_ref().map(|_| &placeholder)))?;
+        let offset = self.offset;
+        self.inner.write_all(&block)?;
+
+        let mut chain = self.transforms_for(header);
+        let mut size = 0;
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut out = Vec::new();
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            out.clear();
+            match chain {
+                Some(ref mut c) if n == 0 => c.finish(&mut out),
+                Some(ref mut c) => c.transform(&buf[..n], &mut out),
+                None => out.extend_from_slice(&buf[..n])
+            }
+            self.inner.write_all(&out)?;
+            if let Some(ref mut h) = hasher {
+                h.update(&out);
+            }
+            size += out.len() as u64;
+            if n == 0 {
+                break;
+            }
+        }
+        self.inner.write_all(&[0u8; 512][..padding(size) as usize])?;
+
+        let end = self.inner.stream_position()?;
+        self.inner.seek(SeekFrom::Start(start))?;
+        let digest = hasher.map(|h| h.finish());
+        self.write_entry(&pax_header(), &streaming_records(size, digest.as_ref()))?;
+        /* Readers ignoring PAX still get the size, in base-256 when octal is too short */
+        self.inner.write_all(&header.to_block(size)?)?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.offset = offset + 512 + size + padding(size);
+        self.record(header, offset, chain.as_ref());
+        Ok(size)
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::*;
+    use nom::IResult;
+
+    #[test]
+    fn round_trip_test() {
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let mut dir = Header::new("dir/");
+        dir.typeflag = TypeFlag::Directory;
+        dir.mode = 0o755;
+
+        let mut builder = Builder::new(Vec::new());
+        builder.append(&dir, b"").unwrap();
+        builder.append(&Header::new(&long), b"hello").unwrap();
+        let tar = builder.finish().unwrap();
+        assert_eq!(tar.len(), 512 * 5);
+
+        match parse_tar(&tar[..]) {
+            IResult::Done(_, entries) => {
+                assert_eq!(entries.len(), 2);
+                assert_eq!(entries[0].header.typeflag, TypeFlag::Directory);
+                assert_eq!(entries[0].header.mode, "0000755");
+                assert_eq!(entries[1].header.path(), long);
+                assert_eq!(entries[1].contents, b"hello");
+            },
+            e => panic!("cannot parse built archive: {:?}", e)
+        }
+    }
+
+    #[test]
+    fn field_limits_test() {
+        assert!(Header::new("").to_block(0).is_err());
+        assert!(Header::new(&"a".repeat(100)).to_block(0).is_err());
+        assert!(Header::new("a").to_block(0o77777777777).is_ok());
+        assert!(Header::new("a").to_block(u64::MAX).is_ok());
+        let mut large = Header::new("a");
+        large.mode = 0o10000000;
+        assert!(large.to_block(0).is_err());
+    }
+
+    #[test]
+    fn failed_append_test() {
+        use archive::Archive;
+
+        /* A refused header leaves the output as it was, digest record included */
+        let long = Header::new(&"a".repeat(300));
+        let mut dir = Header::new("dir/");
+        dir.typeflag = TypeFlag::Directory;
+        let mut b = Builder::new(Vec::new());
+        b.record_digests(true);
+        assert!(b.append(&long, b"x").is_err());
+        assert!(b.append_from(&long, &b"x"[..]).is_err());
+        assert!(b.inner.is_empty());
+        b.append(&dir, b"").unwrap();
+        b.append(&Header::new("file"), b"x").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        assert_eq!(archive.entries()[0].path, "dir/");
+        assert_eq!(archive.verify_digests().unwrap(), 1);
+    }
+
+    #[test]
+    fn large_numbers_test() {
+        let mut header = Header::new("big");
+        header.uid = 1 << 32;
+        header.mtime = 1 << 40;
+        let size = 8 << 30;
+        let block = header.to_block(size).unwrap();
+        assert_eq!(block[124], 0x80);
+        match parse_header(&block[..]) {
+            IResult::Done(_, h) => assert_eq!((h.size, h.uid, h.mtime), (size, 1 << 32, 1 << 40)),
+            e => panic!("cannot parse built header: {:?}", e)
+        }
+        let block = Header::new("small").to_block(0o77777777777).unwrap();
+        assert_eq!(&block[124..136], b"77777777777\0");
+    }
+
+    #[test]
+    fn append_entry_test() {
+        use archive::Archive;
+
+        let long = format!("{}/{}", "d".repeat(150), "f".repeat(120));
+        let mut pax = Header::new("././@PaxHeader");
+        pax.typeflag = TypeFlag::PaxExtendedAttributes;
+        let mut records = pax_record("path", long.as_bytes());
+        records.extend(pax_record("mtime", b"1000000000.5"));
+        records.extend(pax_record("comment", b"kept"));
+        let mut builder = Builder::new(Vec::new());
+        builder.append(&pax, &records).unwrap();
+        builder.append(&Header::new("short"), b"data").unwrap();
+        let source = Archive::new(builder.finish().unwrap()).unwrap();
+
+        let mut entry = source.entries()[0].clone();
+        assert_eq!((&entry.path[..], entry.mtime), (&long[..], 1000000000));
+        entry.uid = 1 << 40;
+        entry.uname = "u".repeat(40);
+        let mut builder = Builder::new(Vec::new());
+        builder.append_entry(&entry, b"data").unwrap();
+        entry.path = "renamed".to_owned();
+        builder.append_entry(&entry, b"other").unwrap();
+        let copy = Archive::new(builder.finish().unwrap()).unwrap();
+
+        let e = &copy.entries()[0];
+        assert_eq!((&e.path[..], e.uid, &e.uname[..], e.mtime), (&long[..], 1 << 40, &entry.uname[..], 1000000000));
+        assert_eq!(e.pax.get("mtime"), Some(&b"1000000000.5".to_vec()));
+        assert_eq!(e.pax.get("comment"), Some(&b"kept".to_vec()));
+        /* The path record no longer applies once the entry is renamed */
+        assert_eq!(copy.entries()[1].path, "renamed");
+        assert_eq!(copy.contents_of("renamed"), Some(&b"other"[..]));
+    }
+
+    #[test]
+    fn append_streaming_test() {
+        use std::io::Cursor;
+        use archive::Archive;
+        use transform::Replace;
+
+        let mut builder = Builder::new(Cursor::new(Vec::new()));
+        builder.transform(|_| Some(Box::new(Replace::new(b"x", b"yy"))));
+        builder.record_digests(true);
+        assert_eq!(builder.append_streaming(&Header::new("piped"), &[b'x'; 700][..]).unwrap(), 1400);
+        builder.append(&Header::new("after"), b"z").unwrap();
+        let tar = builder.finish().unwrap().into_inner();
+
+        let archive = Archive::new(tar).unwrap();
+        assert_eq!(archive.entries().len(), 2);
+        assert_eq!(archive.contents_of("piped"), Some(&[b'y'; 1400][..]));
+        assert_eq!(archive.get("piped").unwrap().pax.get("size"), Some(&b"00000000000000001400".to_vec()));
+        assert_eq!(archive.contents_of("after"), Some(&b"z"[..]));
+        assert_eq!(archive.verify_digests().unwrap(), 2);
+    }
+
+    #[test]
+    fn append_from_spool_test() {
+        use archive::Archive;
+
+        let size = SPOOL_LIMIT as u64 + 1000;
+        let mut builder = Builder::new(Vec::new());
+        builder.record_digests(true);
+        builder.append_from(&Header::new("big"), io::repeat(b'x').take(size)).unwrap();
+        builder.append_from(&Header::new("small"), &b"small"[..]).unwrap();
+        let archive = Archive::new(builder.finish().unwrap()).unwrap();
+        assert_eq!(archive.get("big").unwrap().size, size);
+        assert!(archive.contents_of("big").unwrap().iter().all(|b| *b == b'x'));
+        assert_eq!(archive.contents_of("small"), Some(&b"small"[..]));
+        assert_eq!(archive.verify_digests().unwrap(), 2);
+    }
+
+    #[test]
+    fn transform_test() {
+        use transform::{LineEndings, Replace};
+
+        let mut builder = Builder::new(Vec::new());
+        builder.transform(|h| if h.path.ends_with(".txt") { Some(Box::new(LineEndings::new())) } else { None });
+        builder.transform(|_| Some(Box::new(Replace::new(b"{{v}}", b"1.0"))));
+        builder.append(&Header::new("a.txt"), b"v{{v}}\r\n").unwrap();
+        builder.append_from(&Header::new("b.bin"), &b"v{{v}}\r\n"[..]).unwrap();
+        let mut link = Header::new("c.txt");
+        link.typeflag = TypeFlag::SymbolicLink;
+        builder.append(&link, b"").unwrap();
+        let tar = builder.finish().unwrap();
+
+        match parse_tar(&tar[..]) {
+            IResult::Done(_, entries) => {
+                assert_eq!(entries[0].contents, b"v1.0\n");
+                assert_eq!(entries[0].header.size, 5);
+                assert_eq!(entries[1].contents, b"v1.0\r\n");
+                assert_eq!(entries[2].header.typeflag, TypeFlag::SymbolicLink);
+            },
+            e => panic!("cannot parse built archive: {:?}", e)
+        }
+    }
+}
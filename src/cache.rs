@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Bounded LRU cache for decoded entry contents
+ */
+
+/// Counters describing how a cache has been used so far
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CacheStats {
+    pub hits:      u64,
+    pub misses:    u64,
+    pub evictions: u64,
+    /// Number of cached entries
+    pub entries:   usize,
+    /// Total size of the cached contents
+    pub bytes:     usize
+}
+
+struct Slot {
+    contents: Arc<Vec<u8>>,
+    tick:     u64
+}
+
+#[derive(Default)]
+struct State {
+    slots: HashMap<String, Slot>,
+    /* Last access tick to path, oldest first */
+    lru:   BTreeMap<u64, String>,
+    tick:  u64,
+    stats: CacheStats
+}
+
+impl State {
+    fn touch(&mut self, path: &str) -> Option<Arc<Vec<u8>>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let slot = self.slots.get_mut(path)?;
+        self.lru.remove(&slot.tick);
+        self.lru.insert(tick, path.to_owned());
+        slot.tick = tick;
+        Some(slot.contents.clone())
+    }
+
+    fn remove_oldest(&mut self) {
+        let oldest = self.lru.keys().next().cloned();
+        if let Some(tick) = oldest {
+            let path = self.lru.remove(&tick).expect("tick listed in lru");
+            let slot = self.slots.remove(&path).expect("lru entry without slot");
+            self.stats.bytes -= slot.contents.len();
+            self.stats.entries -= 1;
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+/// Content cache keyed by entry path, evicting the least recently used
+/// entries once the total cached size would exceed `max_bytes`.
+///
+/// The cache can be shared between threads, loaders run without holding the lock.
+pub struct ContentCache {
+    max_bytes: usize,
+    state:     Mutex<State>
+}
+
+impl ContentCache {
+    pub fn new(max_bytes: usize) -> ContentCache {
+        ContentCache {
+            max_bytes: max_bytes,
+            state:     Mutex::new(State::default())
+        }
+    }
+
+    /// Cached contents for this path, if any
+    pub fn get(&self, path: &str) -> Option<Arc<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        let found = state.touch(path);
+        match found {
+            Some(_) => state.stats.hits += 1,
+            None => state.stats.misses += 1
+        }
+        found
+    }
+
+    /// Cached contents for this path, or the result of `load` which is then
+    /// cached if it fits
+    pub fn get_or_load<F, E>(&self, path: &str, load: F) -> Result<Arc<Vec<u8>>, E>
+        where F: FnOnce() -> Result<Vec<u8>, E> {
+        if let Some(contents) = self.get(path) {
+            return Ok(contents);
+        }
+        let contents = Arc::new(load()?);
+        self.insert(path, contents.clone());
+        Ok(contents)
+    }
+
+    /// Cache contents for this path, replacing any previous version.
+    /// Contents larger than the whole cache are not kept.
+    pub fn insert(&self, path: &str, contents: Arc<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap();
+        self.remove_locked(&mut state, path);
+        if contents.len() > self.max_bytes {
+            return;
+        }
+        while state.stats.bytes + contents.len() > self.max_bytes {
+            state.remove_oldest();
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.stats.bytes += contents.len();
+        state.stats.entries += 1;
+        state.lru.insert(tick, path.to_owned());
+        state.slots.insert(path.to_owned(), Slot {
+            contents: contents,
+            tick:     tick
+        });
+    }
+
+    /// Drop the cached contents for this path
+    pub fn remove(&self, path: &str) {
+        let mut state = self.state.lock().unwrap();
+        self.remove_locked(&mut state, path);
+    }
+
+    fn remove_locked(&self, state: &mut State, path: &str) {
+        if let Some(slot) = state.slots.remove(path) {
+            state.lru.remove(&slot.tick);
+            state.stats.bytes -= slot.contents.len();
+            state.stats.entries -= 1;
+        }
+    }
+
+    /// Drop everything, keeping the statistics
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.slots.clear();
+        state.lru.clear();
+        state.stats.entries = 0;
+        state.stats.bytes = 0;
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(n: usize) -> Result<Vec<u8>, ()> {
+        Ok(vec![0; n])
+    }
+
+    #[test]
+    fn lru_eviction_test() {
+        let cache = ContentCache::new(10);
+        cache.get_or_load("a", || load(4)).unwrap();
+        cache.get_or_load("b", || load(4)).unwrap();
+        /* Touch a so that b is the least recently used */
+        assert!(cache.get("a").is_some());
+        cache.get_or_load("c", || load(4)).unwrap();
+
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.stats(), CacheStats {
+            hits:      3,
+            misses:    4,
+            evictions: 1,
+            entries:   2,
+            bytes:     8
+        });
+    }
+
+    #[test]
+    fn oversized_test() {
+        let cache = ContentCache::new(10);
+        assert_eq!(cache.get_or_load("big", || load(11)).unwrap().len(), 11);
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.get_or_load("err", || Err("boom")), Err("boom"));
+    }
+}
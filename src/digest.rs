@@ -0,0 +1,133 @@
+extern crate sha2;
+
+use std::fmt;
+use std::io::{self, Write};
+
+use self::sha2::{Digest as Sha2Digest, Sha256};
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/*
+ * SHA-256 digests of entry contents
+ */
+
+/// Vendor PAX keyword holding the digest of an entry's contents, written
+/// by `Builder::record_digests`
+pub const PAX_DIGEST_KEY: &str = "TARPARSER.sha256";
+
+/// A SHA-256 digest
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+pub struct Digest(pub [u8; 32]);
+
+impl Digest {
+    /// Lowercase hexadecimal representation
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+
+    /// Parse 64 hexadecimal digits, in either case
+    pub fn from_hex(hex: &str) -> Option<Digest> {
+        if hex.len() != 64 || !hex.is_ascii() {
+            return None;
+        }
+        let mut digest = [0u8; 32];
+        for (i, b) in digest.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+        }
+        Some(Digest(digest))
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/* Serialized as its hex representation */
+#[cfg(feature = "serde")]
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Digest, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        Digest::from_hex(&hex).ok_or_else(|| de::Error::custom("expected 64 hexadecimal digits"))
+    }
+}
+
+/// Digest of a buffer
+pub fn sha256(data: &[u8]) -> Digest {
+    let mut hasher = DigestWriter::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Streaming digest, fed either directly or as an `io::Write`
+#[derive(Clone,Default)]
+pub struct DigestWriter {
+    hasher: Sha256,
+    len:    u64
+}
+
+impl DigestWriter {
+    pub fn new() -> DigestWriter {
+        DigestWriter::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.len += data.len() as u64;
+    }
+
+    /// Number of bytes digested so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn finish(self) -> Digest {
+        Digest(self.hasher.finalize().into())
+    }
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_test() {
+        assert_eq!(sha256(b"").to_hex(), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+        let mut w = DigestWriter::new();
+        w.write_all(b"ab").unwrap();
+        w.write_all(b"c").unwrap();
+        assert_eq!(w.len(), 3);
+        assert_eq!(w.finish().to_hex(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
@@ -0,0 +1,217 @@
+use std::convert::TryInto;
+use std::fmt;
+
+use archive::{Archive, EntryMetadata};
+use field::{checksum, read_checksum, read_number, until_nul};
+use parser::{char_to_type_flag, padding};
+
+/*
+ * Field by field dumps of header blocks, for debugging broken writers
+ */
+
+#[derive(Clone,Copy,PartialEq,Eq)]
+enum Kind {
+    Text,
+    Octal,
+    Decimal,
+    Checksum,
+    Type,
+    Raw
+}
+
+/* Name, offset, length and interpretation of each ustar field */
+const FIELDS: &[(&str, usize, usize, Kind)] = &[
+    ("name", 0, 100, Kind::Text),
+    ("mode", 100, 8, Kind::Octal),
+    ("uid", 108, 8, Kind::Decimal),
+    ("gid", 116, 8, Kind::Decimal),
+    ("size", 124, 12, Kind::Decimal),
+    ("mtime", 136, 12, Kind::Decimal),
+    ("chksum", 148, 8, Kind::Checksum),
+    ("typeflag", 156, 1, Kind::Type),
+    ("linkname", 157, 100, Kind::Text),
+    ("magic", 257, 6, Kind::Text),
+    ("version", 263, 2, Kind::Text),
+    ("uname", 265, 32, Kind::Text),
+    ("gname", 297, 32, Kind::Text),
+    ("devmajor", 329, 8, Kind::Decimal),
+    ("devminor", 337, 8, Kind::Decimal),
+    ("prefix", 345, 155, Kind::Text),
+    ("pad", 500, 12, Kind::Raw)
+];
+
+/// Value of a numeric field in either encoding, see `field::read_number`
+pub fn field_number(field: &[u8]) -> Option<u64> {
+    read_number(field)
+}
+
+fn text(field: &[u8]) -> String {
+    String::from_utf8_lossy(until_nul(field)).into_owned()
+}
+
+/* Hex bytes, trailing zeroes summed up */
+fn hex(field: &[u8]) -> String {
+    let len = field.iter().rposition(|b| *b != 0).map(|p| p + 1).unwrap_or(0);
+    let mut out = field[..len].iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+    if len < field.len() {
+        if len > 0 {
+            out.push(' ');
+        }
+        let zeroes = field.len() - len;
+        out.push_str(&format!("({} zero byte{})", zeroes, if zeroes == 1 { "" } else { "s" }));
+    }
+    out
+}
+
+/// A header block laid out field by field, each with its interpretation
+/// and raw bytes, and the stored checksum next to the computed one
+pub struct HeaderDump<'a> {
+    block:  &'a [u8; 512],
+    offset: u64
+}
+
+impl<'a> HeaderDump<'a> {
+    /// Dump of `block`, found at `offset` in its archive
+    pub fn new(block: &'a [u8; 512], offset: u64) -> HeaderDump<'a> {
+        HeaderDump {
+            block:  block,
+            offset: offset
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Checksum stored in the header, if readable
+    pub fn stored_checksum(&self) -> Option<u64> {
+        read_checksum(self.block)
+    }
+
+    pub fn computed_checksum(&self) -> u64 {
+        checksum(self.block)
+    }
+
+    fn value(&self, kind: Kind, field: &[u8]) -> String {
+        let number = |radix_octal: bool| match field_number(field) {
+            Some(n) if radix_octal => format!("0o{:o}", n),
+            Some(n) => n.to_string(),
+            None => "invalid".to_owned()
+        };
+        match kind {
+            Kind::Text => format!("{:?}", text(field)),
+            Kind::Octal => number(true),
+            Kind::Decimal => number(false),
+            Kind::Checksum => {
+                let computed = self.computed_checksum();
+                match self.stored_checksum() {
+                    Some(stored) if stored == computed => format!("0o{:o}, matches", stored),
+                    Some(stored) => format!("0o{:o}, MISMATCH: computed 0o{:o}", stored, computed),
+                    None => format!("invalid, computed 0o{:o}", computed)
+                }
+            },
+            Kind::Type => format!("{:?} {:?}", field[0] as char, char_to_type_flag(field[0] as char)),
+            Kind::Raw => String::new()
+        }
+    }
+}
+
+impl<'a> fmt::Display for HeaderDump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "header at offset {}", self.offset)?;
+        for &(name, start, len, kind) in FIELDS {
+            let field = &self.block[start..start + len];
+            let line = format!("  {:<8} @{:<3}  {}", name, start, self.value(kind, field));
+            writeln!(f, "{}", line.trim_end())?;
+            writeln!(f, "  {:<8}       {}", "", hex(field))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for HeaderDump<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("HeaderDump");
+        s.field("offset", &self.offset);
+        for &(name, start, len, kind) in FIELDS.iter().filter(|&&(_, _, _, k)| k != Kind::Raw) {
+            s.field(name, &format_args!("{}", self.value(kind, &self.block[start..start + len])));
+        }
+        s.finish()
+    }
+}
+
+impl Archive {
+    /// Dump of the header block of an entry
+    pub fn dump_header(&self, entry: &EntryMetadata) -> HeaderDump<'_> {
+        let start = entry.header_offset as usize;
+        let block = self.as_bytes()[start..start + 512].try_into().expect("headers are whole blocks");
+        HeaderDump::new(block, entry.header_offset)
+    }
+}
+
+/// Dumps of every header in raw archive bytes, extension records included.
+/// Bad checksums and fields are shown rather than refused, and headers are
+/// found by trusting their size fields; the walk ends at a size that makes
+/// no sense.
+pub fn dump_headers(data: &[u8]) -> Vec<HeaderDump<'_>> {
+    let mut dumps = Vec::new();
+    let mut offset = 0;
+    while offset + 512 <= data.len() {
+        let block: &[u8; 512] = data[offset..offset + 512].try_into().expect("slice is a block");
+        if block.iter().all(|b| *b == 0) {
+            offset += 512;
+            continue;
+        }
+        dumps.push(HeaderDump::new(block, offset as u64));
+        let size = match field_number(&block[124..136]) {
+            Some(size) => size,
+            None => break
+        };
+        match (size + padding(size)).checked_add(offset as u64 + 512) {
+            Some(next) if next <= data.len() as u64 => offset = next as usize,
+            _ => break
+        }
+    }
+    dumps
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let dumps = dump_headers(&tar[..]);
+        assert_eq!(dumps.iter().map(|d| d.offset()).collect::<Vec<_>>(), vec![0, 512, 1536, 2560]);
+
+        let text = dumps[1].to_string();
+        assert!(text.starts_with("header at offset 512\n  name     @0    \"test/bar\"\n  "));
+        assert!(text.contains("\n  mode     @100  0o644\n                 30 30 30 30 36 34 34 (1 zero byte)\n"));
+        assert!(text.contains("chksum   @148  0o"));
+        assert!(text.contains(", matches\n"));
+        assert!(text.contains("\n  typeflag @156  '0' NormalFile\n"));
+        assert!(text.contains("\n  pad      @500\n                 (12 zero bytes)\n"));
+        assert!(format!("{:?}", dumps[1]).contains("size: 12, mtime: 1432983484"));
+
+        let archive = Archive::new(tar.to_vec()).unwrap();
+        assert_eq!(archive.dump_header(&archive.entries()[1]).to_string(), text);
+
+        let mut broken = tar.to_vec();
+        broken[512] = b'T';
+        assert!(dump_headers(&broken)[1].to_string().contains("MISMATCH"));
+    }
+
+    #[test]
+    fn field_number_test() {
+        assert_eq!(field_number(b"0000644 \0"), Some(0o644));
+        assert_eq!(field_number(b"\0\0\0\0"), Some(0));
+        assert_eq!(field_number(b"12x4"), None);
+        assert_eq!(field_number(&[0x80, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]), Some(1 << 32));
+        assert_eq!(field_number(&[0x80, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
+}
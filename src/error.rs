@@ -0,0 +1,195 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use digest::Digest;
+
+#[cfg(feature = "serde")]
+use serde::ser::{SerializeMap, Serializer};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Errors raised while reading an archive into owned structures
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying source could not be read
+    Io(io::Error),
+    /// The header block at this offset could not be parsed
+    InvalidHeader { offset: u64 },
+    /// A header field at this offset holds an invalid value
+    InvalidField { offset: u64, field: &'static str },
+    /// The archive ends in the middle of the entry starting at this offset
+    Truncated { offset: u64 },
+    /// A PAX or GNU long name record at this offset exceeds the configured limit
+    MetadataTooLarge { offset: u64, size: u64, limit: u64 },
+    /// The volume label does not match the required pattern
+    LabelMismatch { pattern: String, label: Option<String> },
+    /// Extracting this entry would write outside the destination or through a link
+    UnsafeEntry { path: String, reason: &'static str },
+    /// This entry would take extraction past a configured size limit
+    LimitExceeded { path: String, limit: u64 },
+    /// The contents of this entry do not match the digest recorded for them
+    DigestMismatch { path: String, expected: Digest, actual: Digest },
+    /// The uncompressed layer does not have the expected OCI diff_id
+    DiffIdMismatch { expected: String, actual: String },
+    /// An entry filter refused this entry
+    Rejected { path: String, reason: String },
+    /// This entry is nested deeper than the configured limit
+    TooDeep { path: String, depth: usize, limit: usize }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::InvalidHeader { offset } => write!(f, "invalid header at offset {}", offset),
+            Error::InvalidField { offset, field } => write!(f, "invalid {} field in header at offset {}", field, offset),
+            Error::Truncated { offset } => write!(f, "archive truncated in entry at offset {}", offset),
+            Error::MetadataTooLarge { offset, size, limit } => {
+                write!(f, "metadata record of {} bytes at offset {} exceeds the {} bytes limit", size, offset, limit)
+            },
+            Error::LabelMismatch { ref pattern, label: Some(ref label) } => {
+                write!(f, "volume label {:?} does not match {:?}", label, pattern)
+            },
+            Error::LabelMismatch { ref pattern, label: None } => write!(f, "archive has no volume label to match {:?}", pattern),
+            Error::UnsafeEntry { ref path, reason } => write!(f, "refusing to extract {:?}: {}", path, reason),
+            Error::LimitExceeded { ref path, limit } => write!(f, "extracting {:?} exceeds the {} bytes limit", path, limit),
+            Error::DigestMismatch { ref path, ref expected, ref actual } => {
+                write!(f, "contents of {:?} have digest {} instead of {}", path, actual, expected)
+            },
+            Error::DiffIdMismatch { ref expected, ref actual } => write!(f, "layer has diff_id {} instead of {}", actual, expected),
+            Error::Rejected { ref path, ref reason } => write!(f, "{:?} was rejected: {}", path, reason),
+            Error::TooDeep { ref path, depth, limit } => write!(f, "{:?} is {} levels deep, more than the limit of {}", path, depth, limit)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl Error {
+    /// Stable snake_case name of the variant
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Error::Io(_) => "io",
+            Error::InvalidHeader { .. } => "invalid_header",
+            Error::InvalidField { .. } => "invalid_field",
+            Error::Truncated { .. } => "truncated",
+            Error::MetadataTooLarge { .. } => "metadata_too_large",
+            Error::LabelMismatch { .. } => "label_mismatch",
+            Error::UnsafeEntry { .. } => "unsafe_entry",
+            Error::LimitExceeded { .. } => "limit_exceeded",
+            Error::DigestMismatch { .. } => "digest_mismatch",
+            Error::DiffIdMismatch { .. } => "diff_id_mismatch",
+            Error::Rejected { .. } => "rejected",
+            Error::TooDeep { .. } => "too_deep"
+        }
+    }
+}
+
+/* A map holding the kind, the message and the fields of the variant */
+#[cfg(feature = "serde")]
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match *self {
+            Error::Io(_) => {},
+            Error::InvalidHeader { offset } | Error::Truncated { offset } => map.serialize_entry("offset", &offset)?,
+            Error::InvalidField { offset, field } => {
+                map.serialize_entry("offset", &offset)?;
+                map.serialize_entry("field", field)?;
+            },
+            Error::MetadataTooLarge { offset, size, limit } => {
+                map.serialize_entry("offset", &offset)?;
+                map.serialize_entry("size", &size)?;
+                map.serialize_entry("limit", &limit)?;
+            },
+            Error::LabelMismatch { ref pattern, ref label } => {
+                map.serialize_entry("pattern", pattern)?;
+                map.serialize_entry("label", label)?;
+            },
+            Error::UnsafeEntry { ref path, reason } => {
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("reason", reason)?;
+            },
+            Error::LimitExceeded { ref path, limit } => {
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("limit", &limit)?;
+            },
+            Error::DigestMismatch { ref path, ref expected, ref actual } => {
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("expected", &expected.to_hex())?;
+                map.serialize_entry("actual", &actual.to_hex())?;
+            },
+            Error::DiffIdMismatch { ref expected, ref actual } => {
+                map.serialize_entry("expected", expected)?;
+                map.serialize_entry("actual", actual)?;
+            },
+            Error::Rejected { ref path, ref reason } => {
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("reason", reason)?;
+            },
+            Error::TooDeep { ref path, depth, limit } => {
+                map.serialize_entry("path", path)?;
+                map.serialize_entry("depth", &depth)?;
+                map.serialize_entry("limit", &limit)?;
+            }
+        }
+        map.end()
+    }
+}
+
+/// An error confined to one entry, recorded instead of raised when
+/// processing carries on past bad entries
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EntryFailure {
+    pub path:    String,
+    /// Offset of the first header of the entry
+    pub offset:  u64,
+    /// `Error::kind` of the error
+    pub kind:    String,
+    pub message: String
+}
+
+impl EntryFailure {
+    pub fn new(path: &str, offset: u64, error: &Error) -> EntryFailure {
+        EntryFailure {
+            path:    path.to_owned(),
+            offset:  offset,
+            kind:    error.kind().to_owned(),
+            message: error.to_string()
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn serialize_test() {
+        let e = Error::InvalidField { offset: 512, field: "mode" };
+        assert_eq!(serde_json::to_string(&e).unwrap(),
+                   r#"{"kind":"invalid_field","message":"invalid mode field in header at offset 512","offset":512,"field":"mode"}"#);
+    }
+}
@@ -0,0 +1,1433 @@
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "async")]
+extern crate tokio;
+
+use std::cmp;
+use std::fs::{self, File, FileTimes, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use nom::IResult;
+
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "async")]
+use self::tokio::io::{AsyncRead, ReadBuf};
+use archive::EntryMetadata;
+use digest::{Digest, DigestWriter};
+use error::{EntryFailure, Error};
+use filter::{admit, EntryFilter, FilterChain, Verdict};
+use index::{ArchiveIndex, IndexEntry};
+use framing::{is_extension, is_zero_block, Frame, Framer, ParseOptions, Pending};
+use oci::check_diff_id;
+use parser::{padding, parse_header, TypeFlag};
+use paths;
+use sniff::{content_type, is_executable};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Streaming extraction to a directory
+ */
+
+/// Size of the reads feeding an extraction
+pub const CHUNK_SIZE: usize = 64 << 10;
+
+/// What an extraction is allowed to do. Paths climbing out of the
+/// destination are always refused, as are writes through symbolic links.
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExtractPolicy {
+    pub parse:                ParseOptions,
+    /// Create symbolic links, as long as they point inside the destination
+    pub symlinks:             bool,
+    /// Create hard links to entries inside the destination
+    pub hardlinks:            bool,
+    /// Apply the stored permission bits, without setuid, setgid and sticky
+    pub preserve_permissions: bool,
+    pub preserve_mtime:       bool,
+    /// Restore access times from PAX `atime` records, for forensic copies.
+    /// Status change times cannot be set and are left alone.
+    pub preserve_atime:       bool,
+    /// Replace files already present in the destination
+    pub overwrite:            bool,
+    /// Check contents against digests recorded by `Builder::record_digests`,
+    /// removing files that do not match
+    pub verify_digests:       bool,
+    /// OCI diff_id the uncompressed stream must have, checked once it ends
+    pub diff_id:              Option<String>,
+    pub max_entry_size:       Option<u64>,
+    /// Limit on the contents size of all the entries together
+    pub max_total_size:       Option<u64>,
+    /// What to do with regular files by content type, the first matching
+    /// rule deciding. Files matching none are extracted.
+    pub content_rules:        Vec<ContentRule>,
+    /// Refuse entries nested deeper than this with `Error::TooDeep`
+    pub max_depth:            Option<usize>,
+    /// Record errors confined to one entry in the summary and carry on with
+    /// the next, leaving out what the entry would have created. Errors in
+    /// the archive framing still stop the extraction.
+    pub continue_on_error:    bool
+}
+
+impl Default for ExtractPolicy {
+    fn default() -> ExtractPolicy {
+        ExtractPolicy {
+            parse:                ParseOptions::default(),
+            symlinks:             true,
+            hardlinks:            true,
+            preserve_permissions: true,
+            preserve_mtime:       true,
+            preserve_atime:       false,
+            overwrite:            true,
+            verify_digests:       true,
+            diff_id:              None,
+            max_entry_size:       None,
+            max_total_size:       None,
+            content_rules:        Vec::new(),
+            max_depth:            None,
+            continue_on_error:    false
+        }
+    }
+}
+
+/// Which regular files a `ContentRule` applies to
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ContentMatch {
+    /// File name extension, without the dot, in any case
+    Extension(String),
+    /// Detected content type without its parameters, like `image/png`, or
+    /// a whole family when ending with a slash, like `image/`
+    Type(String),
+    /// Native executables, Java classes, scripts and files with an
+    /// executable permission bit
+    Executable
+}
+
+/// What becomes of the files a `ContentRule` matches
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ContentAction {
+    Allow,
+    /// Leave the file out and carry on
+    Skip,
+    /// Stop the extraction with `Error::UnsafeEntry`
+    Refuse,
+    /// Extract below this directory of the destination instead
+    Quarantine(String)
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ContentRule {
+    pub matches: ContentMatch,
+    pub action:  ContentAction
+}
+
+/// Leading bytes of a file looked at to detect its type
+pub const SNIFF_SIZE: usize = 512;
+
+impl ContentMatch {
+    /// Whether a file matches, given its first `SNIFF_SIZE` bytes
+    pub fn matches(&self, path: &str, mode: u64, head: &[u8]) -> bool {
+        match *self {
+            ContentMatch::Extension(ref ext) => {
+                let name = paths::file_name(path.trim_end_matches('/'));
+                match name.rfind('.') {
+                    Some(i) if i > 0 => name[i + 1..].eq_ignore_ascii_case(ext),
+                    _ => false
+                }
+            },
+            ContentMatch::Type(ref t) => {
+                let detected = content_type(path, head).split(';').next().unwrap_or("");
+                match t.ends_with('/') {
+                    true => detected.starts_with(&t[..]),
+                    false => detected == t
+                }
+            },
+            ContentMatch::Executable => mode & 0o111 != 0 || is_executable(head)
+        }
+    }
+}
+
+/// What an extraction created
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExtractSummary {
+    pub files:       u64,
+    pub directories: u64,
+    pub symlinks:    u64,
+    pub hardlinks:   u64,
+    /// Contents written to files
+    pub bytes:       u64,
+    /// Entries not extracted: devices, FIFOs and links the policy refuses
+    pub skipped:     Vec<String>,
+    /// Entries that failed, with `continue_on_error`
+    pub failures:    Vec<EntryFailure>
+}
+
+/// Compression applied to the whole archive stream
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+pub enum Compression {
+    None,
+    /// One or more concatenated gzip members
+    #[cfg(feature = "gzip")]
+    Gzip
+}
+
+/* A regular file being written */
+struct Output {
+    file:   File,
+    path:   PathBuf,
+    name:   String,
+    offset: u64,
+    mode:   u64,
+    mtime:  u64,
+    atime:  Option<u64>,
+    digest: Option<(Digest, DigestWriter)>
+}
+
+enum State {
+    /// Collecting the next header block
+    Header,
+    /// Buffering a PAX or GNU long name record
+    Extension { typeflag: TypeFlag, offset: u64, remaining: u64, data: Vec<u8> },
+    /// Copying contents, to a file or nowhere
+    Contents { output: Option<Box<Output>>, remaining: u64 },
+    /// Collecting the first bytes of a file to apply content rules
+    Sniff { metadata: Box<EntryMetadata>, head: Vec<u8>, remaining: u64 },
+    /// Skipping the zeroes up to the next block
+    Padding { remaining: u64 }
+}
+
+fn after_contents(size: u64) -> State {
+    match padding(size) {
+        0 => State::Header,
+        p => State::Padding { remaining: p }
+    }
+}
+
+fn is_regular(m: &EntryMetadata) -> bool {
+    m.typeflag == TypeFlag::NormalFile || m.typeflag == TypeFlag::ContiguousFile
+}
+
+fn unsafe_entry(path: &str, reason: &'static str) -> Error {
+    Error::UnsafeEntry { path: path.to_owned(), reason: reason }
+}
+
+/// Push-based extractor: archive bytes are fed in chunks of any size and
+/// entries are written as soon as their contents arrive, so memory use is
+/// bounded by the metadata size limit whatever the archive size.
+///
+/// Extraction must be abandoned after an error, unless the policy allows
+/// continuing past it and the extractor carried on by itself.
+pub struct Extractor {
+    dest:             PathBuf,
+    policy:           ExtractPolicy,
+    framer:           Framer,
+    pending:          Pending,
+    /// Offset of the first extension record of the entry being read
+    extension_offset: Option<u64>,
+    /// Offset of the entry whose contents are being read
+    entry_offset:     u64,
+    block:            Vec<u8>,
+    state:            State,
+    offset:           u64,
+    total:            u64,
+    /* Directory metadata is applied last, in case it forbids writes */
+    directories:      Vec<(PathBuf, u64, u64, Option<u64>)>,
+    /// Digest of the whole stream, when checking a diff_id
+    stream_digest:    Option<DigestWriter>,
+    filters:          FilterChain,
+    /// Whether the filters saw the header of an entry but not its end yet
+    filtering:        bool,
+    summary:          ExtractSummary
+}
+
+impl Extractor {
+    pub fn new<P: AsRef<Path>>(dest: P, policy: ExtractPolicy) -> Result<Extractor, Error> {
+        create_dirs(dest.as_ref())?;
+        let stream_digest = policy.diff_id.as_ref().map(|_| DigestWriter::new());
+        Ok(Extractor {
+            dest:             dest.as_ref().to_path_buf(),
+            framer:           Framer::new(policy.parse.clone()),
+            policy:           policy,
+            pending:          Pending::default(),
+            extension_offset: None,
+            entry_offset:     0,
+            block:            Vec::with_capacity(512),
+            state:            State::Header,
+            offset:           0,
+            total:            0,
+            directories:      Vec::new(),
+            stream_digest:    stream_digest,
+            filters:          FilterChain::new(),
+            filtering:        false,
+            summary:          ExtractSummary::default()
+        })
+    }
+
+    /// Run entries through `filter` before they are written, after any
+    /// filter added before it. Digests are checked on the original contents.
+    pub fn filter<F: EntryFilter + Send + 'static>(&mut self, filter: F) {
+        self.filters.push(filter);
+    }
+
+    /// Consume the next bytes of the archive
+    pub fn feed(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        if let Some(ref mut hasher) = self.stream_digest {
+            hasher.update(data);
+        }
+        while !data.is_empty() {
+            let n = self.step(data)?;
+            self.offset += n as u64;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    /// Check the archive ended on an entry boundary and apply directory metadata
+    pub fn finish(mut self) -> Result<ExtractSummary, Error> {
+        let incomplete = match self.state {
+            State::Header if self.block.is_empty() && self.extension_offset.is_none() => None,
+            State::Header => Some(self.extension_offset.unwrap_or(self.offset - self.block.len() as u64)),
+            State::Extension { .. } => self.extension_offset,
+            State::Contents { .. } | State::Sniff { .. } | State::Padding { .. } => Some(self.entry_offset)
+        };
+        if let Some(offset) = incomplete {
+            return Err(Error::Truncated { offset: offset });
+        }
+        if let (Some(expected), Some(hasher)) = (self.policy.diff_id.as_ref(), self.stream_digest.take()) {
+            check_diff_id(expected, &hasher.finish())?;
+        }
+        for (path, mode, mtime, atime) in mem::take(&mut self.directories).into_iter().rev() {
+            self.set_metadata(&path, None, mode, mtime, atime)?;
+        }
+        Ok(self.summary)
+    }
+
+    /// Extract one entry whose contents are already at hand, as with an
+    /// `Archive`, instead of feeding its blocks
+    pub fn extract_entry(&mut self, metadata: &EntryMetadata, contents: &[u8]) -> Result<(), Error> {
+        let mut metadata = metadata.clone();
+        let head = &contents[..cmp::min(SNIFF_SIZE, contents.len())];
+        let output = self.admit(&mut metadata, head);
+        if let Some(mut o) = self.isolate(&metadata.path, metadata.extension_offset, output)?.and_then(|o| o) {
+            match self.write_chunk(&mut o, contents) {
+                Ok(()) => self.finish_output(o)?,
+                Err(e) => self.abandon(o, e)?
+            }
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, data: &[u8]) -> Result<usize, Error> {
+        match mem::replace(&mut self.state, State::Header) {
+            State::Header => {
+                let n = cmp::min(512 - self.block.len(), data.len());
+                self.block.extend_from_slice(&data[..n]);
+                if self.block.len() == 512 {
+                    let block = mem::replace(&mut self.block, Vec::with_capacity(512));
+                    self.header(&block, self.offset + n as u64 - 512)?;
+                }
+                Ok(n)
+            },
+            State::Extension { typeflag, offset, remaining, data: mut record } => {
+                let n = cmp::min(remaining, data.len() as u64) as usize;
+                record.extend_from_slice(&data[..n]);
+                if n as u64 == remaining {
+                    self.framer.push_extension(&mut self.pending, typeflag, &record, offset)?;
+                    self.state = after_contents(record.len() as u64);
+                } else {
+                    self.state = State::Extension { typeflag: typeflag, offset: offset, remaining: remaining - n as u64, data: record };
+                }
+                Ok(n)
+            },
+            State::Contents { mut output, remaining } => {
+                let n = cmp::min(remaining, data.len() as u64) as usize;
+                output = self.write_output(output, &data[..n])?;
+                if n as u64 == remaining {
+                    if let Some(o) = output {
+                        self.finish_output(o)?;
+                    }
+                    self.state = after_contents(self.offset + n as u64 - self.entry_offset);
+                } else {
+                    self.state = State::Contents { output: output, remaining: remaining - n as u64 };
+                }
+                Ok(n)
+            },
+            State::Sniff { mut metadata, mut head, remaining } => {
+                let n = cmp::min(cmp::min(SNIFF_SIZE - head.len(), data.len()) as u64, remaining) as usize;
+                head.extend_from_slice(&data[..n]);
+                let remaining = remaining - n as u64;
+                if head.len() < SNIFF_SIZE && remaining > 0 {
+                    self.state = State::Sniff { metadata: metadata, head: head, remaining: remaining };
+                    return Ok(n);
+                }
+                /* Filters may rewrite the metadata, not what the archive holds */
+                let size = metadata.size;
+                let output = self.admit(&mut metadata, &head);
+                let output = self.isolate(&metadata.path, metadata.extension_offset, output)?.and_then(|o| o);
+                let output = self.write_output(output, &head)?;
+                if remaining > 0 {
+                    self.state = State::Contents { output: output, remaining: remaining };
+                } else {
+                    if let Some(o) = output {
+                        self.finish_output(o)?;
+                    }
+                    self.state = after_contents(size);
+                }
+                Ok(n)
+            },
+            State::Padding { remaining } => {
+                let n = cmp::min(remaining, data.len() as u64);
+                if n < remaining {
+                    self.state = State::Padding { remaining: remaining - n };
+                }
+                Ok(n as usize)
+            }
+        }
+    }
+
+    fn header(&mut self, block: &[u8], offset: u64) -> Result<(), Error> {
+        /* Terminators, and zeroes padding the last record */
+        if is_zero_block(block, self.extension_offset)? {
+            return Ok(());
+        }
+        let header = match parse_header(block) {
+            IResult::Done(_, h) => h,
+            _ => return Err(Error::InvalidHeader { offset: offset })
+        };
+        let extension_offset = *self.extension_offset.get_or_insert(offset);
+
+        if is_extension(header.typeflag) {
+            self.framer.check_extension(&mut self.pending, header.size, offset)?;
+            if header.size == 0 {
+                self.framer.push_extension(&mut self.pending, header.typeflag, b"", offset)?;
+            } else {
+                self.state = State::Extension { typeflag: header.typeflag, offset: offset, remaining: header.size, data: Vec::new() };
+            }
+            return Ok(());
+        }
+
+        self.extension_offset = None;
+        self.entry_offset = offset + 512;
+        let pending = mem::take(&mut self.pending);
+        let (output, size) = if self.framer.is_empty(&pending, &header, offset)? {
+            (None, header.size)
+        } else {
+            let mut metadata = self.framer.metadata(pending, &header, extension_offset, offset, offset + 512)?;
+            let size = metadata.size;
+            if size > 0 && is_regular(&metadata) && !self.policy.content_rules.is_empty() {
+                self.state = State::Sniff { metadata: Box::new(metadata), head: Vec::new(), remaining: size };
+                return Ok(());
+            }
+            let output = self.admit(&mut metadata, b"");
+            (self.isolate(&metadata.path, extension_offset, output)?.and_then(|o| o), size)
+        };
+        self.state = State::Contents { output: output, remaining: size };
+        if size == 0 {
+            /* Nothing more will be fed for this entry */
+            if let State::Contents { output: Some(o), .. } = mem::replace(&mut self.state, State::Header) {
+                self.finish_output(o)?;
+            }
+        }
+        Ok(())
+    }
+
+    /* Record an error confined to one entry when the policy allows
+     * continuing, giving `None` in place of the result */
+    fn isolate<T>(&mut self, path: &str, offset: u64, result: Result<T, Error>) -> Result<Option<T>, Error> {
+        match result {
+            Ok(t) => Ok(Some(t)),
+            Err(e) if self.policy.continue_on_error => {
+                self.summary.failures.push(EntryFailure::new(path, offset, &e));
+                Ok(None)
+            },
+            Err(e) => Err(e)
+        }
+    }
+
+    /* Give up on a file after an error, removing what was written */
+    fn abandon(&mut self, o: Box<Output>, error: Error) -> Result<(), Error> {
+        self.reset_filters();
+        drop(o.file);
+        let _ = fs::remove_file(&o.path);
+        self.summary.files -= 1;
+        self.isolate(&o.name, o.offset, Err::<(), _>(error)).map(|_| ())
+    }
+
+    fn finish_filters(&mut self, out: &mut Vec<u8>) -> Verdict {
+        self.filtering = false;
+        self.filters.finish(out)
+    }
+
+    /* Finish the filters of an entry given up on, so none keeps its state
+     * over to the next entry */
+    fn reset_filters(&mut self) {
+        if self.filtering {
+            self.finish_filters(&mut Vec::new());
+        }
+    }
+
+    /* Write a chunk of the file being extracted, if any and still going */
+    fn write_output(&mut self, output: Option<Box<Output>>, chunk: &[u8]) -> Result<Option<Box<Output>>, Error> {
+        match output {
+            Some(mut o) => match self.write_chunk(&mut o, chunk) {
+                Ok(()) => Ok(Some(o)),
+                Err(e) => self.abandon(o, e).map(|_| None)
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn finish_output(&mut self, o: Box<Output>) -> Result<(), Error> {
+        let (name, offset) = (o.name.clone(), o.offset);
+        let result = self.finish_file(o);
+        self.isolate(&name, offset, result).map(|_| ())
+    }
+
+    /* Filter an entry, then start it if kept. Entries without contents are
+     * decided on before anything is created for them. */
+    fn admit(&mut self, m: &mut EntryMetadata, head: &[u8]) -> Result<Option<Box<Output>>, Error> {
+        let output = self.admit_entry(m, head);
+        if !matches!(output, Ok(Some(_))) {
+            self.reset_filters();
+        }
+        output
+    }
+
+    fn admit_entry(&mut self, m: &mut EntryMetadata, head: &[u8]) -> Result<Option<Box<Output>>, Error> {
+        self.filtering = true;
+        let verdict = self.filters.header(m);
+        let mut kept = admit(&m.path, verdict)?;
+        if kept && !is_regular(m) {
+            let verdict = self.finish_filters(&mut Vec::new());
+            kept = admit(&m.path, verdict)?;
+        }
+        if kept && is_regular(m) {
+            let rule = self.policy.content_rules.iter().find(|r| r.matches.matches(&m.path, m.mode, head));
+            match rule.map(|r| &r.action) {
+                None | Some(ContentAction::Allow) => {},
+                Some(ContentAction::Skip) => kept = false,
+                Some(ContentAction::Refuse) => return Err(unsafe_entry(&m.path, "content type is refused by the policy")),
+                Some(ContentAction::Quarantine(dir)) => m.path = format!("{}/{}", dir.trim_end_matches('/'), m.path)
+            }
+        }
+        if !kept {
+            self.summary.skipped.push(m.path.clone());
+            return Ok(None);
+        }
+        self.start(m)
+    }
+
+    fn write_chunk(&mut self, o: &mut Output, chunk: &[u8]) -> Result<(), Error> {
+        if let Some((_, ref mut hasher)) = o.digest {
+            hasher.update(chunk);
+        }
+        if self.filters.is_empty() {
+            o.file.write_all(chunk)?;
+            self.summary.bytes += chunk.len() as u64;
+        } else {
+            let mut out = Vec::with_capacity(chunk.len());
+            self.filters.contents(chunk, &mut out);
+            o.file.write_all(&out)?;
+            self.summary.bytes += out.len() as u64;
+        }
+        Ok(())
+    }
+
+    /* Destination of an entry, `None` for the destination itself */
+    fn target(&self, path: &str) -> Result<Option<PathBuf>, Error> {
+        let rel = paths::normalize(path).ok_or_else(|| unsafe_entry(path, "path escapes the destination"))?;
+        if rel.is_empty() {
+            return Ok(None);
+        }
+        let depth = paths::components(&rel).len();
+        match self.policy.max_depth {
+            Some(limit) if depth > limit => return Err(Error::TooDeep { path: path.to_owned(), depth: depth, limit: limit }),
+            _ => {}
+        }
+        if cfg!(windows) && rel.contains(['\\', ':']) {
+            return Err(unsafe_entry(path, "path is not portable to this platform"));
+        }
+        /* Never write through a link planted by an earlier entry */
+        let mut target = self.dest.clone();
+        for c in paths::components(&rel) {
+            if let Ok(m) = fs::symlink_metadata(&target) {
+                if m.file_type().is_symlink() {
+                    return Err(unsafe_entry(path, "path traverses a symbolic link"));
+                }
+            }
+            target.push(c);
+        }
+        Ok(Some(target))
+    }
+
+    /* Resolve a symbolic link target against the destination. Climbing out
+     * of a component with `..` is only allowed from the parents of the link
+     * itself, which are real directories, or from a real directory already
+     * extracted: a link there, now or later, would send the climb elsewhere. */
+    fn check_link_target(&self, path: &str, linkname: &str) -> Result<(), Error> {
+        if linkname.starts_with('/') {
+            return Err(unsafe_entry(path, "symbolic link target is absolute"));
+        }
+        let rel = paths::normalize(path).unwrap_or_default();
+        let mut resolved = paths::components(paths::parent(&rel)).into_iter().map(|c| (c, true)).collect::<Vec<_>>();
+        for c in paths::components(linkname) {
+            if c != ".." {
+                resolved.push((c, false));
+                continue;
+            }
+            match resolved.pop() {
+                None => return Err(unsafe_entry(path, "symbolic link escapes the destination")),
+                Some((_, true)) => {},
+                Some((c, false)) => {
+                    let mut dir = self.dest.clone();
+                    dir.extend(resolved.iter().map(|&(c, _)| c));
+                    dir.push(c);
+                    match fs::symlink_metadata(&dir) {
+                        Ok(ref m) if m.is_dir() => {},
+                        _ => return Err(unsafe_entry(path, "symbolic link target climbs out of a path that is not a directory"))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* Make room for a new non-directory, refusing to replace one unless allowed */
+    fn prepare(&self, path: &str, target: &Path) -> Result<(), Error> {
+        if let Some(parent) = target.parent() {
+            create_dirs(parent)?;
+        }
+        match fs::symlink_metadata(target) {
+            Ok(ref m) if m.is_dir() => Err(Error::Io(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is a directory", path)))),
+            Ok(_) if !self.policy.overwrite => Err(Error::Io(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path)))),
+            Ok(_) => Ok(fs::remove_file(target)?),
+            Err(_) => Ok(())
+        }
+    }
+
+    fn check_limits(&mut self, m: &EntryMetadata) -> Result<(), Error> {
+        if let Some(limit) = self.policy.max_entry_size {
+            if m.size > limit {
+                return Err(Error::LimitExceeded { path: m.path.clone(), limit: limit });
+            }
+        }
+        self.total = self.total.saturating_add(m.size);
+        if let Some(limit) = self.policy.max_total_size {
+            if self.total > limit {
+                return Err(Error::LimitExceeded { path: m.path.clone(), limit: limit });
+            }
+        }
+        Ok(())
+    }
+
+    /* Create whatever an entry describes, giving back the file to write its contents to */
+    fn start(&mut self, m: &EntryMetadata) -> Result<Option<Box<Output>>, Error> {
+        let target = match self.target(&m.path)? {
+            Some(t) => t,
+            None => return Ok(None)
+        };
+        match m.typeflag {
+            TypeFlag::Directory => {
+                if fs::symlink_metadata(&target).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+                    return Err(unsafe_entry(&m.path, "path is a symbolic link"));
+                }
+                create_dirs(&target)?;
+                self.directories.push((target, m.mode, m.mtime, m.atime()));
+                self.summary.directories += 1;
+                Ok(None)
+            },
+            TypeFlag::NormalFile | TypeFlag::ContiguousFile => {
+                self.check_limits(m)?;
+                self.prepare(&m.path, &target)?;
+                let file = OpenOptions::new().write(true).create_new(true).open(&target)?;
+                self.summary.files += 1;
+                let digest = match m.recorded_digest() {
+                    Some(d) if self.policy.verify_digests => Some((d, DigestWriter::new())),
+                    _ => None
+                };
+                Ok(Some(Box::new(Output {
+                    file:   file,
+                    path:   target,
+                    name:   m.path.clone(),
+                    offset: m.extension_offset,
+                    mode:   m.mode,
+                    mtime:  m.mtime,
+                    atime:  m.atime(),
+                    digest: digest
+                })))
+            },
+            TypeFlag::SymbolicLink if self.policy.symlinks && cfg!(unix) => {
+                self.check_link_target(&m.path, &m.linkname)?;
+                self.prepare(&m.path, &target)?;
+                symlink(&m.linkname, &target)?;
+                self.summary.symlinks += 1;
+                Ok(None)
+            },
+            TypeFlag::HardLink if self.policy.hardlinks => {
+                let source = match self.target(&m.linkname)? {
+                    Some(s) => s,
+                    None => return Err(unsafe_entry(&m.path, "hard link to the destination"))
+                };
+                self.prepare(&m.path, &target)?;
+                /* A hard link to a symbolic link is one more symbolic link,
+                 * its target now resolving from where the new link is */
+                if fs::symlink_metadata(&source).map(|s| s.file_type().is_symlink()).unwrap_or(false) {
+                    let linkname = fs::read_link(&source)?;
+                    self.check_link_target(&m.path, &linkname.to_string_lossy())?;
+                }
+                fs::hard_link(source, &target)?;
+                self.summary.hardlinks += 1;
+                Ok(None)
+            },
+            _ => {
+                self.summary.skipped.push(m.path.clone());
+                Ok(None)
+            }
+        }
+    }
+
+    fn finish_file(&mut self, mut o: Box<Output>) -> Result<(), Error> {
+        if let Some((expected, hasher)) = o.digest.take() {
+            let actual = hasher.finish();
+            if actual != expected {
+                let error = Error::DigestMismatch { path: o.name.clone(), expected: expected, actual: actual };
+                return self.abandon(o, error);
+            }
+        }
+        if !self.filters.is_empty() {
+            let mut out = Vec::new();
+            let verdict = self.finish_filters(&mut out);
+            if let Err(e) = o.file.write_all(&out) {
+                return self.abandon(o, e.into());
+            }
+            self.summary.bytes += out.len() as u64;
+            match admit(&o.name, verdict) {
+                Ok(true) => {},
+                r => {
+                    drop(o.file);
+                    fs::remove_file(&o.path)?;
+                    self.summary.files -= 1;
+                    r?;
+                    self.summary.skipped.push(o.name);
+                    return Ok(());
+                }
+            }
+        }
+        self.set_metadata(&o.path, Some(o.file), o.mode, o.mtime, o.atime)
+    }
+
+    fn set_metadata(&self, path: &Path, file: Option<File>, mode: u64, mtime: u64, atime: Option<u64>) -> Result<(), Error> {
+        let atime = atime.filter(|_| self.policy.preserve_atime);
+        let times_set = self.policy.preserve_mtime || atime.is_some();
+        let times = {
+            let mut times = FileTimes::new();
+            if self.policy.preserve_mtime {
+                times = times.set_modified(UNIX_EPOCH + Duration::from_secs(mtime));
+            }
+            if let Some(t) = atime {
+                times = times.set_accessed(UNIX_EPOCH + Duration::from_secs(t));
+            }
+            times
+        };
+        if !times_set && !self.policy.preserve_permissions {
+            return Ok(());
+        }
+        /* Directories are reopened, skipping any a symbolic link replaced,
+         * and everything goes through the handle so no link is followed */
+        let file = match file {
+            Some(f) => f,
+            None if fs::symlink_metadata(path)?.file_type().is_symlink() => return Ok(()),
+            None => File::open(path)?
+        };
+        if times_set {
+            /* Both times go in one utimensat call */
+            file.set_times(times)?;
+        }
+        if self.policy.preserve_permissions {
+            set_mode(&file, mode)?;
+        }
+        Ok(())
+    }
+}
+
+/* Like `fs::create_dir_all`, without recursing once per missing level */
+fn create_dirs(path: &Path) -> io::Result<()> {
+    let missing = path.ancestors().take_while(|p| !p.as_os_str().is_empty() && !p.is_dir()).collect::<Vec<_>>();
+    for dir in missing.into_iter().rev() {
+        match fs::create_dir(dir) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => {},
+            r => r?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn symlink(_: &str, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symbolic links are not supported"))
+}
+
+#[cfg(unix)]
+fn set_mode(file: &File, mode: u64) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode((mode & 0o777) as u32))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &File, _: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/* Extraction errors cross io::Write as custom io::Errors */
+#[cfg(feature = "gzip")]
+fn from_io(e: io::Error) -> Error {
+    if e.get_ref().map(|inner| inner.is::<Error>()).unwrap_or(false) {
+        *e.into_inner().unwrap().downcast::<Error>().unwrap()
+    } else {
+        Error::Io(e)
+    }
+}
+
+impl Write for Extractor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.feed(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/*
+ * Decompressing front end
+ */
+
+enum Sink {
+    Plain(Extractor),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::MultiGzDecoder<Extractor>)
+}
+
+impl Sink {
+    fn new<P: AsRef<Path>>(dest: P, compression: Compression, policy: &ExtractPolicy) -> Result<Sink, Error> {
+        let extractor = Extractor::new(dest, policy.clone())?;
+        Ok(match compression {
+            Compression::None => Sink::Plain(extractor),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Sink::Gzip(flate2::write::MultiGzDecoder::new(extractor))
+        })
+    }
+
+    fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        match *self {
+            Sink::Plain(ref mut e) => e.feed(data),
+            #[cfg(feature = "gzip")]
+            Sink::Gzip(ref mut d) => d.write_all(data).map_err(from_io)
+        }
+    }
+
+    fn finish(self) -> Result<ExtractSummary, Error> {
+        match self {
+            Sink::Plain(e) => e.finish(),
+            #[cfg(feature = "gzip")]
+            Sink::Gzip(d) => d.finish().map_err(from_io)?.finish()
+        }
+    }
+}
+
+/// Extract an archive read from `reader` into `dest`
+pub fn extract<R: Read, P: AsRef<Path>>(mut reader: R, compression: Compression, dest: P, policy: &ExtractPolicy) -> Result<ExtractSummary, Error> {
+    let mut sink = Sink::new(dest, compression, policy)?;
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => sink.feed(&buf[..n])?,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(Error::Io(e))
+        }
+    }
+    sink.finish()
+}
+
+/* Gives the entry extracted in place of a hard link the link's path */
+struct Rename(String);
+
+impl EntryFilter for Rename {
+    fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+        entry.path = self.0.clone();
+        Verdict::Keep
+    }
+}
+
+/* The bytes of an indexed entry, from its first extension record to the end of its padding */
+fn read_indexed<'a, R: Read + Seek>(source: &'a mut R, entry: &IndexEntry) -> io::Result<io::Take<&'a mut R>> {
+    let end = entry.data_offset + entry.size + padding(entry.size);
+    source.seek(SeekFrom::Start(entry.extension_offset))?;
+    Ok(source.take(end.saturating_sub(entry.extension_offset)))
+}
+
+/// Extract the entry stored under `path` from a seekable archive, reading
+/// only that entry: `index` tells where its extension records, header and
+/// contents are, so the rest of the archive is never read. The usual
+/// safety checks and limits hold. A hard link is written as a copy of its
+/// target, which is not extracted alongside it.
+///
+/// The entry's own PAX and GNU long name records are applied, global PAX
+/// records earlier in the archive are not. `policy.diff_id` is ignored,
+/// since only part of the layer is read.
+pub fn extract_one<R: Read + Seek, P: AsRef<Path>>(mut source: R, index: &ArchiveIndex, path: &str, dest: P, policy: &ExtractPolicy) -> Result<ExtractSummary, Error> {
+    let mut entry = index.get(path)
+        .ok_or_else(|| Error::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the archive", path))))?;
+    let policy = ExtractPolicy {
+        diff_id: None,
+        ..policy.clone()
+    };
+    let mut extractor = Extractor::new(dest, policy)?;
+    if entry.typeflag == TypeFlag::HardLink {
+        let mut data = Vec::new();
+        read_indexed(&mut source, entry)?.read_to_end(&mut data)?;
+        let linkname = match Framer::new(extractor.policy.parse.clone()).next(&data, 0, entry.extension_offset)? {
+            Frame::Entry { metadata, .. } => metadata.linkname,
+            _ => return Err(Error::Truncated { offset: entry.extension_offset })
+        };
+        let target = index.get(&linkname)
+            .filter(|t| t.typeflag == TypeFlag::NormalFile || t.typeflag == TypeFlag::ContiguousFile);
+        if let Some(target) = target {
+            extractor.filter(Rename(entry.path.clone()));
+            entry = target;
+        }
+    }
+    let mut reader = read_indexed(&mut source, entry)?;
+    extractor.offset = entry.extension_offset;
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => extractor.feed(&buf[..n])?,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(Error::Io(e))
+        }
+    }
+    extractor.finish()
+}
+
+/// Future returned by `extract_async`
+#[cfg(feature = "async")]
+pub struct ExtractAsync<R> {
+    reader: R,
+    /* Taken on completion, holding the error if the destination could not be created */
+    sink:   Option<Result<Sink, Error>>,
+    buf:    Vec<u8>
+}
+
+/// Extract an archive from an asynchronous body, such as an HTTP response,
+/// as it downloads. Filesystem writes are blocking, run this on a runtime
+/// thread that may block.
+#[cfg(feature = "async")]
+pub fn extract_async<R: AsyncRead + Unpin, P: AsRef<Path>>(reader: R, compression: Compression, dest: P, policy: &ExtractPolicy) -> ExtractAsync<R> {
+    ExtractAsync {
+        reader: reader,
+        sink:   Some(Sink::new(dest, compression, policy)),
+        buf:    vec![0; CHUNK_SIZE]
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> Future for ExtractAsync<R> {
+    type Output = Result<ExtractSummary, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<ExtractSummary, Error>> {
+        let this = self.get_mut();
+        loop {
+            let sink = match this.sink {
+                Some(Ok(ref mut sink)) => sink,
+                Some(Err(_)) => return Poll::Ready(Err(this.sink.take().unwrap().err().unwrap())),
+                None => panic!("extraction polled after completion")
+            };
+            let mut buf = ReadBuf::new(&mut this.buf);
+            match Pin::new(&mut this.reader).poll_read(cx, &mut buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => {
+                    this.sink = None;
+                    return Poll::Ready(Err(Error::Io(e)));
+                },
+                Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                    return Poll::Ready(this.sink.take().unwrap().and_then(|s| s.finish()));
+                },
+                Poll::Ready(Ok(())) => {
+                    if let Err(e) = sink.feed(buf.filled()) {
+                        this.sink = None;
+                        return Poll::Ready(Err(e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Cursor;
+    use std::process;
+    use archive::Archive;
+    use builder::{pax_record, Builder, Header};
+    use filter::Verdict;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tar-extract-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn link(path: &str, typeflag: TypeFlag, target: &str) -> Header {
+        let mut h = Header::new(path);
+        h.typeflag = typeflag;
+        h.linkname = target.to_owned();
+        h
+    }
+
+    #[test]
+    fn extract_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let dest = scratch("simple");
+        /* Feeding odd sized chunks exercises every state boundary */
+        let mut extractor = Extractor::new(&dest, ExtractPolicy::default()).unwrap();
+        for chunk in tar.chunks(7) {
+            extractor.feed(chunk).unwrap();
+        }
+        let summary = extractor.finish().unwrap();
+        assert_eq!((summary.files, summary.directories, summary.bytes), (3, 1, 36));
+        assert_eq!(fs::read(dest.join("test/foo")).unwrap(), b"This is foo\n");
+        let mtime = fs::metadata(dest.join("test/bar")).unwrap().modified().unwrap();
+        assert_eq!(mtime, UNIX_EPOCH + Duration::from_secs(1432983484));
+
+        fs::remove_dir_all(&dest).unwrap();
+        match extract(&tar[..1000], Compression::None, &dest, &ExtractPolicy::default()) {
+            Err(Error::Truncated { offset: 512 }) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    fn extract_indexed(archive: &Archive, path: &str, dest: &Path, policy: &ExtractPolicy) -> Result<ExtractSummary, Error> {
+        extract_one(Cursor::new(archive.as_bytes()), &ArchiveIndex::from_archive(archive), path, dest, policy)
+    }
+
+    #[test]
+    fn extract_one_test() {
+        let dest = scratch("one");
+        let archive = Archive::new(include_bytes!("../examples/simple/test.tar").to_vec()).unwrap();
+        let summary = extract_indexed(&archive, "test/baz", &dest, &ExtractPolicy::default()).unwrap();
+        assert_eq!((summary.files, summary.bytes), (1, 12));
+        assert_eq!(fs::read(dest.join("test/baz")).unwrap(), b"This is baz\n");
+        assert!(!dest.join("test/foo").exists());
+        match extract_indexed(&archive, "test/missing", &dest, &ExtractPolicy::default()) {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+
+        /* A hard link comes out as a copy of its target */
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("target"), b"linked").unwrap();
+        b.append(&link("alias", TypeFlag::HardLink, "target"), b"").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        extract_indexed(&archive, "alias", &dest, &ExtractPolicy::default()).unwrap();
+        assert_eq!(fs::read(dest.join("alias")).unwrap(), b"linked");
+        assert!(!dest.join("target").exists());
+
+        /* Only the entry is read, so damage elsewhere goes unnoticed */
+        let mut b = Builder::new(Vec::new());
+        let mut contiguous = Header::new("target");
+        contiguous.typeflag = TypeFlag::ContiguousFile;
+        b.append(&contiguous, b"contiguous").unwrap();
+        b.append(&Header::new("damaged"), b"").unwrap();
+        b.append(&link("other", TypeFlag::HardLink, "target"), b"").unwrap();
+        let mut data = b.finish().unwrap();
+        let index = ArchiveIndex::from_archive(&Archive::new(data.clone()).unwrap());
+        data[index.get("damaged").unwrap().header_offset as usize + 124] = b'x';
+        assert!(Archive::new(data.clone()).is_err());
+        let summary = extract_one(Cursor::new(&data), &index, "other", &dest, &ExtractPolicy::default()).unwrap();
+        assert_eq!(summary.files, 1);
+        assert_eq!(fs::read(dest.join("other")).unwrap(), b"contiguous");
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn continue_on_error_test() {
+        let mut b = Builder::new(Vec::new());
+        b.record_digests(true);
+        b.append(&Header::new("first"), b"one").unwrap();
+        b.append(&link("escape", TypeFlag::SymbolicLink, "/etc"), b"").unwrap();
+        b.append(&Header::new("big"), &[b'x'; 100]).unwrap();
+        b.append(&Header::new("corrupt"), b"hello").unwrap();
+        b.append(&Header::new("last"), b"two").unwrap();
+        let mut data = b.finish().unwrap();
+        let pos = Archive::new(data.clone()).unwrap().get("corrupt").unwrap().data_offset as usize;
+        data[pos] = b'j';
+
+        let dest = scratch("continue");
+        let policy = ExtractPolicy { max_entry_size: Some(10), ..ExtractPolicy::default() };
+        match extract(&data[..], Compression::None, &dest, &policy) {
+            Err(Error::UnsafeEntry { ref path, .. }) if path == "escape" => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        fs::remove_dir_all(&dest).unwrap();
+
+        let policy = ExtractPolicy { continue_on_error: true, ..policy };
+        let mut extractor = Extractor::new(&dest, policy).unwrap();
+        for chunk in data.chunks(7) {
+            extractor.feed(chunk).unwrap();
+        }
+        let summary = extractor.finish().unwrap();
+        assert_eq!((summary.files, summary.bytes), (2, 11));
+        assert_eq!(summary.failures.iter().map(|f| (&f.path[..], &f.kind[..])).collect::<Vec<_>>(),
+                   vec![("escape", "unsafe_entry"), ("big", "limit_exceeded"), ("corrupt", "digest_mismatch")]);
+        assert_eq!(fs::read(dest.join("last")).unwrap(), b"two");
+        assert!(!dest.join("corrupt").exists() && !dest.join("big").exists());
+
+        /* Framing errors still stop everything */
+        let policy = ExtractPolicy { continue_on_error: true, ..ExtractPolicy::default() };
+        match extract(&data[..1000], Compression::None, &dest, &policy) {
+            Err(Error::Truncated { .. }) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    /* Appends the path and size of each file, panicking if an entry starts
+     * before the previous one finished */
+    #[derive(Default)]
+    struct Tally {
+        path:  Option<String>,
+        bytes: usize
+    }
+
+    impl EntryFilter for Tally {
+        fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+            assert_eq!(self.path, None, "filter state left over from the previous entry");
+            self.path = Some(entry.path.clone());
+            Verdict::Keep
+        }
+
+        fn contents(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+            self.bytes += chunk.len();
+            out.extend_from_slice(chunk);
+        }
+
+        fn finish(&mut self, out: &mut Vec<u8>) -> Verdict {
+            out.extend(format!("\n{} {}", self.path.take().unwrap_or_default(), self.bytes).into_bytes());
+            self.bytes = 0;
+            Verdict::Keep
+        }
+    }
+
+    #[test]
+    fn filter_state_test() {
+        let mut b = Builder::new(Vec::new());
+        b.record_digests(true);
+        b.append(&Header::new("first"), b"one").unwrap();
+        b.append(&Header::new("big"), &[b'x'; 100]).unwrap();
+        b.append(&Header::new("run.exe"), b"MZ").unwrap();
+        b.append(&Header::new("corrupt"), b"hello").unwrap();
+        b.append(&Header::new("last"), b"two").unwrap();
+        let mut data = b.finish().unwrap();
+        let pos = Archive::new(data.clone()).unwrap().get("corrupt").unwrap().data_offset as usize;
+        data[pos] = b'j';
+
+        let dest = scratch("filter-state");
+        let policy = ExtractPolicy {
+            max_entry_size:    Some(10),
+            content_rules:     vec![ContentRule { matches: ContentMatch::Extension("exe".to_owned()), action: ContentAction::Refuse }],
+            continue_on_error: true,
+            ..ExtractPolicy::default()
+        };
+        let mut extractor = Extractor::new(&dest, policy).unwrap();
+        extractor.filter(Tally::default());
+        for chunk in data.chunks(7) {
+            extractor.feed(chunk).unwrap();
+        }
+        let summary = extractor.finish().unwrap();
+        assert_eq!(summary.failures.iter().map(|f| &f.path[..]).collect::<Vec<_>>(), vec!["big", "run.exe", "corrupt"]);
+        assert_eq!(summary.files, 2);
+        assert_eq!(fs::read(dest.join("first")).unwrap(), b"one\nfirst 3");
+        assert_eq!(fs::read(dest.join("last")).unwrap(), b"two\nlast 3");
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    /* Skips `bar`, upper cases contents and refuses any symbolic link */
+    struct Vet;
+
+    impl EntryFilter for Vet {
+        fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+            match entry.typeflag {
+                _ if entry.path.ends_with("bar") => Verdict::Skip,
+                TypeFlag::SymbolicLink => Verdict::Reject("links are not allowed".to_owned()),
+                _ => Verdict::Keep
+            }
+        }
+
+        fn contents(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+            out.extend(chunk.to_ascii_uppercase());
+        }
+    }
+
+    #[test]
+    fn max_depth_test() {
+        let dest = scratch("depth");
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("a/b/c"), b"").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        let policy = ExtractPolicy { max_depth: Some(2), ..ExtractPolicy::default() };
+        match extract_indexed(&archive, "a/b/c", &dest, &policy) {
+            Err(Error::TooDeep { ref path, depth: 3, limit: 2 }) if path == "a/b/c" => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        assert!(!dest.join("a").exists());
+        extract_indexed(&archive, "a/b/c", &dest, &ExtractPolicy::default()).unwrap();
+        assert!(dest.join("a/b/c").is_file());
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn atime_test() {
+        let mut b = Builder::new(Vec::new());
+        let mut pax = Header::new("././@PaxHeader");
+        pax.typeflag = TypeFlag::PaxExtendedAttributes;
+        let mut records = pax_record("atime", b"1000000000.25");
+        records.extend(pax_record("ctime", b"1100000000"));
+        b.append(&pax, &records).unwrap();
+        b.append(&Header::new("accessed"), b"a").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        let entry = &archive.entries()[0];
+        assert_eq!((entry.atime(), entry.ctime()), (Some(1000000000), Some(1100000000)));
+
+        let dest = scratch("atime");
+        let policy = ExtractPolicy { preserve_atime: true, ..ExtractPolicy::default() };
+        extract_indexed(&archive, "accessed", &dest, &policy).unwrap();
+        let metadata = fs::metadata(dest.join("accessed")).unwrap();
+        assert_eq!(metadata.accessed().unwrap(), UNIX_EPOCH + Duration::from_secs(1000000000));
+        assert_eq!(metadata.modified().unwrap(), UNIX_EPOCH);
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn filter_test() {
+        let dest = scratch("filter");
+        let mut extractor = Extractor::new(&dest, ExtractPolicy::default()).unwrap();
+        extractor.filter(Vet);
+        extractor.feed(include_bytes!("../examples/simple/test.tar")).unwrap();
+        let summary = extractor.finish().unwrap();
+        assert_eq!((summary.files, summary.skipped), (2, vec!["test/bar".to_owned()]));
+        assert_eq!(fs::read(dest.join("test/foo")).unwrap(), b"THIS IS FOO\n");
+        assert!(!dest.join("test/bar").exists());
+
+        let mut b = Builder::new(Vec::new());
+        b.append(&link("l", TypeFlag::SymbolicLink, "test/foo"), b"").unwrap();
+        let mut extractor = Extractor::new(&dest, ExtractPolicy::default()).unwrap();
+        extractor.filter(Vet);
+        match extractor.feed(&b.finish().unwrap()) {
+            Err(Error::Rejected { ref path, .. }) if path == "l" => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn content_rules_test() {
+        let dest = scratch("content");
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("lib/libfoo.so"), b"\x7fELF shared object").unwrap();
+        b.append(&Header::new("logo.dat"), b"\x89PNG\r\n\x1a\n and pixels").unwrap();
+        b.append(&Header::new("a.txt"), b"abc").unwrap();
+        let tar = b.finish().unwrap();
+        let rule = |matches, action| ContentRule { matches: matches, action: action };
+        let policy = ExtractPolicy {
+            content_rules: vec![
+                rule(ContentMatch::Extension("SO".to_owned()), ContentAction::Quarantine("quarantine/".to_owned())),
+                rule(ContentMatch::Type("image/".to_owned()), ContentAction::Skip),
+                rule(ContentMatch::Executable, ContentAction::Refuse)
+            ],
+            ..ExtractPolicy::default()
+        };
+
+        let mut extractor = Extractor::new(&dest, policy.clone()).unwrap();
+        for chunk in tar.chunks(7) {
+            extractor.feed(chunk).unwrap();
+        }
+        let summary = extractor.finish().unwrap();
+        assert_eq!(summary.skipped, vec!["logo.dat".to_owned()]);
+        assert_eq!(fs::read(dest.join("quarantine/lib/libfoo.so")).unwrap(), b"\x7fELF shared object");
+        assert!(!dest.join("lib").exists() && !dest.join("logo.dat").exists());
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"abc");
+
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("run.sh"), b"#!/bin/sh\n").unwrap();
+        match extract(&b.finish().unwrap()[..], Compression::None, &dest, &policy) {
+            Err(Error::UnsafeEntry { ref path, .. }) if path == "run.sh" => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        assert!(!dest.join("run.sh").exists());
+
+        /* Text is told from binary past the first few bytes, and a
+         * character cut off by the sniffed prefix keeps it text */
+        let policy = ExtractPolicy {
+            content_rules: vec![rule(ContentMatch::Type("text/plain".to_owned()), ContentAction::Skip)],
+            ..ExtractPolicy::default()
+        };
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("binary"), format!("{}\0", "a".repeat(100)).as_bytes()).unwrap();
+        b.append(&Header::new("text"), format!("{}\u{e9}", "a".repeat(SNIFF_SIZE - 1)).as_bytes()).unwrap();
+        let summary = extract(&b.finish().unwrap()[..], Compression::None, &dest, &policy).unwrap();
+        assert_eq!(summary.skipped, vec!["text".to_owned()]);
+        assert!(dest.join("binary").exists());
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unsafe_entries_test() {
+        let dest = scratch("unsafe");
+        let cases = vec![
+            (Header::new("../evil"), "path escapes the destination"),
+            (link("abs", TypeFlag::SymbolicLink, "/etc/passwd"), "symbolic link target is absolute"),
+            (link("a/up", TypeFlag::SymbolicLink, "../../x"), "symbolic link escapes the destination")
+        ];
+        for (header, reason) in cases {
+            let mut b = Builder::new(Vec::new());
+            b.append(&header, b"").unwrap();
+            match extract(&b.finish().unwrap()[..], Compression::None, &dest, &ExtractPolicy::default()) {
+                Err(Error::UnsafeEntry { reason: r, .. }) => assert_eq!(r, reason),
+                r => panic!("unexpected result: {:?}", r)
+            }
+        }
+
+        /* Climbing out of a link is refused, whichever is extracted first */
+        for &(first, second) in &[(("s", "."), ("t", "s/../outside")), (("t", "s/../outside"), ("s", "."))] {
+            let mut b = Builder::new(Vec::new());
+            b.append(&link(first.0, TypeFlag::SymbolicLink, first.1), b"").unwrap();
+            b.append(&link(second.0, TypeFlag::SymbolicLink, second.1), b"").unwrap();
+            match extract(&b.finish().unwrap()[..], Compression::None, &dest, &ExtractPolicy::default()) {
+                Err(Error::UnsafeEntry { ref path, reason: "symbolic link target climbs out of a path that is not a directory" }) if path == "t" => {},
+                r => panic!("unexpected result: {:?}", r)
+            }
+            fs::remove_dir_all(&dest).unwrap();
+        }
+        let mut dir = Header::new("d");
+        dir.typeflag = TypeFlag::Directory;
+        let mut b = Builder::new(Vec::new());
+        b.append(&dir, b"").unwrap();
+        b.append(&link("d/up", TypeFlag::SymbolicLink, "../d/../inside"), b"").unwrap();
+        extract(&b.finish().unwrap()[..], Compression::None, &dest, &ExtractPolicy::default()).unwrap();
+        assert_eq!(fs::read_link(dest.join("d/up")).unwrap(), Path::new("../d/../inside"));
+
+        /* A link inside the destination is fine, writing through it is not */
+        let mut b = Builder::new(Vec::new());
+        b.append(&link("dir", TypeFlag::SymbolicLink, "."), b"").unwrap();
+        b.append(&Header::new("dir/file"), b"x").unwrap();
+        match extract(&b.finish().unwrap()[..], Compression::None, &dest, &ExtractPolicy::default()) {
+            Err(Error::UnsafeEntry { reason: "path traverses a symbolic link", .. }) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+
+        /* Nor is a hard link moving a symbolic link where it climbs out */
+        let mut b = Builder::new(Vec::new());
+        b.append(&link("a/b/l", TypeFlag::SymbolicLink, "../../escaped"), b"").unwrap();
+        b.append(&link("a/b/same", TypeFlag::HardLink, "a/b/l"), b"").unwrap();
+        b.append(&link("l2", TypeFlag::HardLink, "a/b/l"), b"").unwrap();
+        match extract(&b.finish().unwrap()[..], Compression::None, &dest, &ExtractPolicy::default()) {
+            Err(Error::UnsafeEntry { ref path, reason: "symbolic link escapes the destination" }) if path == "l2" => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        assert_eq!(fs::read_link(dest.join("a/b/same")).unwrap(), Path::new("../../escaped"));
+        assert!(fs::symlink_metadata(dest.join("l2")).is_err());
+
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("big"), &[0; 100]).unwrap();
+        let policy = ExtractPolicy {
+            max_total_size: Some(99),
+            ..ExtractPolicy::default()
+        };
+        match extract(&b.finish().unwrap()[..], Compression::None, &dest, &policy) {
+            Err(Error::LimitExceeded { limit: 99, .. }) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+
+        let mut b = Builder::new(Vec::new());
+        b.record_digests(true);
+        b.append(&Header::new("corrupt"), b"hello").unwrap();
+        let mut data = b.finish().unwrap();
+        data[1536] = b'j';
+        match extract(&data[..], Compression::None, &dest, &ExtractPolicy::default()) {
+            Err(Error::DigestMismatch { ref path, .. }) if path == "corrupt" => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        assert!(!dest.join("corrupt").exists());
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_test() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(include_bytes!("../examples/simple/test.tar")).unwrap();
+        let dest = scratch("gzip");
+        let summary = extract(&encoder.finish().unwrap()[..], Compression::Gzip, &dest, &ExtractPolicy::default()).unwrap();
+        assert_eq!(summary.files, 3);
+        assert_eq!(fs::read(dest.join("test/baz")).unwrap(), b"This is baz\n");
+
+        /* The diff_id covers the decompressed stream */
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(include_bytes!("../examples/simple/test.tar")).unwrap();
+        let policy = ExtractPolicy {
+            diff_id: Some("sha256:90d1ea2f67a023d784d2aa6a06ffb2ccefb0ab8d8fb9a4ef09f040289f5ce2cc".to_owned()),
+            ..ExtractPolicy::default()
+        };
+        extract(&encoder.finish().unwrap()[..], Compression::Gzip, &dest, &policy).unwrap();
+        fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn extract_async_test() {
+        use std::task::Waker;
+
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let dest = scratch("async");
+        /* Reading from a slice is always ready, no runtime needed */
+        let mut future = extract_async(&tar[..], Compression::None, &dest, &ExtractPolicy::default());
+        let summary = match Pin::new(&mut future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(r) => r.unwrap(),
+            Poll::Pending => panic!("extraction from a slice is pending")
+        };
+        assert_eq!(summary.files, 3);
+        fs::remove_dir_all(dest).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use std::io;
+use std::str::{from_utf8, Utf8Error};
+
+use parser::octal_to_u64;
+
+/*
+ * Header field codecs: the encodings the builder writes and the parsers
+ * read, for tools handling related formats or repairing headers by hand
+ */
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.to_owned())
+}
+
+/*
+ * Numbers
+ */
+
+/// Octal digits, padded with spaces and ended by a NUL or the end of the
+/// field, anything after the NUL being ignored. An empty or all padding
+/// field is zero.
+pub fn read_octal(field: &[u8]) -> Option<u64> {
+    let text = from_utf8(until_nul(field)).ok()?;
+    octal_to_u64(text.trim_matches(' ')).ok()
+}
+
+/// Zero padded octal digits filling all but the last byte, which is a NUL.
+/// Fails if the value needs more digits.
+pub fn write_octal(field: &mut [u8], value: u64) -> io::Result<()> {
+    let digits = field.len().saturating_sub(1);
+    let s = format!("{:01$o}", value, digits);
+    if field.is_empty() || s.len() > digits {
+        return Err(invalid_input("value too large for header field"));
+    }
+    field[..digits].copy_from_slice(s.as_bytes());
+    field[digits] = 0;
+    Ok(())
+}
+
+/// GNU base-256: the high bit of the first byte set, then a big endian
+/// number. Negative values and values beyond 64 bits are refused.
+pub fn read_base256(field: &[u8]) -> Option<u64> {
+    match field.first() {
+        Some(&0x80) => {
+            let digits = &field[1..];
+            let start = digits.len().saturating_sub(8);
+            if digits[..start].iter().any(|b| *b != 0) {
+                return None;
+            }
+            Some(digits[start..].iter().fold(0, |n, &b| n << 8 | b as u64))
+        },
+        _ => None
+    }
+}
+
+/// GNU base-256 over the whole field. Fails if the field is too short for
+/// the value.
+pub fn write_base256(field: &mut [u8], value: u64) -> io::Result<()> {
+    let bytes = value.to_be_bytes();
+    let used = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    if field.len() < 1 + bytes.len() - used {
+        return Err(invalid_input("value too large for header field"));
+    }
+    let start = field.len() - (bytes.len() - used);
+    for b in field[..start].iter_mut() {
+        *b = 0;
+    }
+    field[0] = 0x80;
+    field[start..].copy_from_slice(&bytes[used..]);
+    Ok(())
+}
+
+/// A number field in either encoding, as told by the high bit of its first
+/// byte
+pub fn read_number(field: &[u8]) -> Option<u64> {
+    match field.first() {
+        Some(&b) if b & 0x80 != 0 => read_base256(field),
+        _ => read_octal(field)
+    }
+}
+
+/// Octal when the value fits, base-256 otherwise, as GNU tar does for
+/// large sizes and ids
+pub fn write_number(field: &mut [u8], value: u64) -> io::Result<()> {
+    match write_octal(field, value) {
+        Ok(()) => Ok(()),
+        Err(_) => write_base256(field, value)
+    }
+}
+
+/*
+ * Strings
+ */
+
+/// The bytes of a string field up to its first NUL. Fields may be filled
+/// completely, with no NUL at all.
+pub fn until_nul(field: &[u8]) -> &[u8] {
+    &field[..field.iter().position(|b| *b == 0).unwrap_or(field.len())]
+}
+
+/// A string field as UTF-8, see `until_nul`
+pub fn read_str(field: &[u8]) -> Result<&str, Utf8Error> {
+    from_utf8(until_nul(field))
+}
+
+/// A string followed by NUL padding. The builder always leaves at least
+/// one NUL, so the string must be shorter than the field.
+pub fn write_str(field: &mut [u8], s: &str) -> io::Result<()> {
+    if s.len() >= field.len() {
+        return Err(invalid_input("string too long for header field"));
+    }
+    if s.contains('\0') {
+        return Err(invalid_input("string contains a NUL byte"));
+    }
+    field[..s.len()].copy_from_slice(s.as_bytes());
+    for b in field[s.len()..].iter_mut() {
+        *b = 0;
+    }
+    Ok(())
+}
+
+/*
+ * Checksum
+ */
+
+/// Sum of all header bytes, the checksum field itself counting as spaces
+pub fn checksum(block: &[u8; 512]) -> u64 {
+    block.iter().enumerate().map(|(i, b)| {
+        if (148..156).contains(&i) { b' ' as u64 } else { *b as u64 }
+    }).sum()
+}
+
+/// The checksum stored in a header, if readable
+pub fn read_checksum(block: &[u8; 512]) -> Option<u64> {
+    read_octal(&block[148..156])
+}
+
+/// Store the checksum of the rest of the header: six digits, a NUL and a
+/// space, like most writers
+pub fn write_checksum(block: &mut [u8; 512]) {
+    let sum = checksum(block);
+    write_octal(&mut block[148..155], sum).expect("header sums fit in six octal digits");
+    block[155] = b' ';
+}
+
+/// Whether the checksum stored in a header matches its contents
+pub fn verify_checksum(block: &[u8; 512]) -> bool {
+    read_checksum(block) == Some(checksum(block))
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn number_test() {
+        let mut field = [0xffu8; 8];
+        write_octal(&mut field, 0o644).unwrap();
+        assert_eq!(&field, b"0000644\0");
+        assert_eq!(read_octal(&field), Some(0o644));
+        assert_eq!(read_octal(b"  644 \0"), Some(0o644));
+        assert_eq!(read_octal(b"\0\0\0\0"), Some(0));
+        assert_eq!(read_octal(b"12x4"), None);
+        assert_eq!(read_octal(b"12\0x4"), Some(0o12));
+        assert!(write_octal(&mut field, 0o100000000).is_err());
+
+        let mut field = [0xffu8; 12];
+        write_number(&mut field, 1 << 40).unwrap();
+        assert_eq!(field, [0x80, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(read_number(&field), Some(1 << 40));
+        write_number(&mut field, 0o644).unwrap();
+        assert_eq!(&field, b"00000000644\0");
+        assert_eq!(read_base256(&field), None);
+        assert_eq!(read_base256(&[0x80, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+        assert_eq!(read_base256(&[0xff, 0xff]), None);
+        assert!(write_base256(&mut [0u8; 4], 1 << 24).is_err());
+
+        let mut field = [0u8; 9];
+        write_base256(&mut field, u64::MAX).unwrap();
+        assert_eq!(read_number(&field), Some(u64::MAX));
+    }
+
+    #[test]
+    fn str_test() {
+        let mut field = [b'x'; 8];
+        write_str(&mut field, "abc").unwrap();
+        assert_eq!(&field, b"abc\0\0\0\0\0");
+        assert_eq!(read_str(&field), Ok("abc"));
+        assert_eq!(read_str(b"fullname"), Ok("fullname"));
+        assert!(write_str(&mut field, "fullname").is_err());
+        assert!(write_str(&mut field, "a\0b").is_err());
+        assert!(read_str(b"\xff\0").is_err());
+    }
+
+    #[test]
+    fn checksum_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let mut block = [0u8; 512];
+        block.copy_from_slice(&tar[512..1024]);
+        assert!(verify_checksum(&block));
+        assert_eq!(read_checksum(&block), Some(0o13743));
+
+        block[0] = b'T';
+        assert!(!verify_checksum(&block));
+        write_checksum(&mut block);
+        assert!(verify_checksum(&block));
+        /* Written the way GNU tar does */
+        block[0] = b't';
+        write_checksum(&mut block);
+        assert_eq!(&block[..], &tar[512..1024]);
+    }
+}
@@ -0,0 +1,328 @@
+use std::io::Write;
+
+use archive::{Archive, EntryMetadata};
+use builder::Builder;
+use error::Error;
+use parser::TypeFlag;
+use provenance::Provenance;
+
+/*
+ * Entry filters: inspect, rewrite or refuse entries as they are processed
+ */
+
+/// What becomes of an entry
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub enum Verdict {
+    Keep,
+    /// Leave the entry out, carrying on with the next one
+    Skip,
+    /// Stop the whole operation with `Error::Rejected`
+    Reject(String)
+}
+
+/// A step entries go through when listing, extracting or converting.
+///
+/// `header` sees every entry first. Regular files then have their contents
+/// fed through `contents` in chunks, and every entry ends with `finish`,
+/// where scanners give their verdict once everything was seen. Entries
+/// left out by `header` still reach `finish`, so no filter carries state
+/// over to the next entry, but its verdict no longer matters.
+pub trait EntryFilter {
+    /// Inspect and possibly rewrite the metadata. The size is not taken
+    /// from here: entries are written with the size of their filtered
+    /// contents.
+    fn header(&mut self, _entry: &mut EntryMetadata) -> Verdict {
+        Verdict::Keep
+    }
+
+    /// Pass on the next chunk of contents, appending it or a rewrite to `out`
+    fn contents(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(chunk);
+    }
+
+    /// Emit anything held back and decide on the entry
+    fn finish(&mut self, _out: &mut Vec<u8>) -> Verdict {
+        Verdict::Keep
+    }
+
+    /// Short name recorded in build reports
+    fn name(&self) -> &str {
+        "filter"
+    }
+}
+
+/// Filters applied one after the other, each seeing the output of the last
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn EntryFilter + Send>>
+}
+
+impl FilterChain {
+    pub fn new() -> FilterChain {
+        FilterChain::default()
+    }
+
+    pub fn push<F: EntryFilter + Send + 'static>(&mut self, filter: F) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Names of the filters, in the order they run
+    pub fn names(&self) -> Vec<&str> {
+        self.filters.iter().map(|f| f.name()).collect()
+    }
+}
+
+impl EntryFilter for FilterChain {
+    /* The first filter not keeping an entry decides, later ones never see it */
+    fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+        for f in &mut self.filters {
+            match f.header(entry) {
+                Verdict::Keep => {},
+                v => return v
+            }
+        }
+        Verdict::Keep
+    }
+
+    fn contents(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+        let mut data = chunk.to_vec();
+        for f in &mut self.filters {
+            let mut next = Vec::with_capacity(data.len());
+            f.contents(&data, &mut next);
+            data = next;
+        }
+        out.extend_from_slice(&data);
+    }
+
+    /* Every filter finishes, so none keeps state over to the next entry */
+    fn finish(&mut self, out: &mut Vec<u8>) -> Verdict {
+        let mut verdict = Verdict::Keep;
+        let mut data = Vec::new();
+        for f in &mut self.filters {
+            let mut next = Vec::new();
+            if !data.is_empty() {
+                f.contents(&data, &mut next);
+            }
+            match f.finish(&mut next) {
+                Verdict::Keep => {},
+                v => if verdict == Verdict::Keep {
+                    verdict = v;
+                }
+            }
+            data = next;
+        }
+        out.extend_from_slice(&data);
+        verdict
+    }
+
+    fn name(&self) -> &str {
+        "chain"
+    }
+}
+
+fn is_regular(entry: &EntryMetadata) -> bool {
+    entry.typeflag == TypeFlag::NormalFile || entry.typeflag == TypeFlag::ContiguousFile
+}
+
+/* Whether an entry goes on, or the error stopping the operation */
+pub(crate) fn admit(path: &str, verdict: Verdict) -> Result<bool, Error> {
+    match verdict {
+        Verdict::Keep => Ok(true),
+        Verdict::Skip => Ok(false),
+        Verdict::Reject(reason) => Err(Error::Rejected { path: path.to_owned(), reason: reason })
+    }
+}
+
+/* Run a filter over an entry with all its contents at hand */
+fn run<F: EntryFilter + ?Sized>(filter: &mut F, archive: &Archive, entry: &EntryMetadata) -> Result<Option<(EntryMetadata, Vec<u8>)>, Error> {
+    let contents = archive.contents(entry);
+    let mut entry = entry.clone();
+    let verdict = filter.header(&mut entry);
+    if verdict != Verdict::Keep {
+        filter.finish(&mut Vec::new());
+        return admit(&entry.path, verdict).map(|_| None);
+    }
+    let mut out = Vec::new();
+    if is_regular(&entry) {
+        filter.contents(contents, &mut out);
+    }
+    let verdict = filter.finish(&mut out);
+    Ok(if admit(&entry.path, verdict)? { Some((entry, out)) } else { None })
+}
+
+/// Entries of an archive left after filtering, as they would be listed
+pub fn filter_listing<F: EntryFilter + ?Sized>(archive: &Archive, filter: &mut F) -> Result<Vec<EntryMetadata>, Error> {
+    let mut kept = Vec::new();
+    for e in archive.entries() {
+        if let Some((entry, _)) = run(filter, archive, e)? {
+            kept.push(entry);
+        }
+    }
+    Ok(kept)
+}
+
+/// Write the entries of an archive left after filtering to a new one,
+/// returning how many were written
+pub fn convert<F: EntryFilter + ?Sized, W: Write>(archive: &Archive, filter: &mut F, builder: &mut Builder<W>) -> Result<u64, Error> {
+    let mut written = 0;
+    for e in archive.entries() {
+        if let Some((entry, contents)) = run(filter, archive, e)? {
+            builder.append_entry(&entry, &contents)?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/// Like `convert`, recording in the build report of `builder` that each
+/// entry comes from `source` and went through the filters of the chain
+pub fn convert_traced<W: Write>(archive: &Archive, source: &str, filter: &mut FilterChain, builder: &mut Builder<W>) -> Result<u64, Error> {
+    let mut written = 0;
+    for e in archive.entries() {
+        if let Some((entry, contents)) = run(filter, archive, e)? {
+            let mut provenance = Provenance::new(source, e);
+            provenance.transforms.extend(filter.names().into_iter().map(|n| n.to_owned()));
+            builder.append_entry_traced(&entry, &contents, provenance)?;
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::Header;
+    use transform::{Replace, Transform};
+
+    /* Upper cases contents and refuses entries whose contents mention a word */
+    struct Shout {
+        seen: Vec<u8>
+    }
+
+    impl EntryFilter for Shout {
+        fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+            if entry.path.ends_with(".skip") {
+                return Verdict::Skip;
+            }
+            entry.path = entry.path.to_uppercase();
+            Verdict::Keep
+        }
+
+        fn contents(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+            self.seen.extend_from_slice(chunk);
+            out.extend(chunk.to_ascii_uppercase());
+        }
+
+        fn finish(&mut self, _out: &mut Vec<u8>) -> Verdict {
+            let seen = String::from_utf8_lossy(&self.seen).into_owned();
+            self.seen.clear();
+            match seen.contains("virus") {
+                true => Verdict::Reject("found a virus".to_owned()),
+                false => Verdict::Keep
+            }
+        }
+    }
+
+    /* A transform holding output back until `finish` */
+    struct Substitute(Replace);
+
+    impl EntryFilter for Substitute {
+        fn contents(&mut self, chunk: &[u8], out: &mut Vec<u8>) {
+            self.0.transform(chunk, out);
+        }
+
+        fn finish(&mut self, out: &mut Vec<u8>) -> Verdict {
+            self.0.finish(out);
+            Verdict::Keep
+        }
+    }
+
+    /* Counts the entries started and finished */
+    #[derive(Default)]
+    struct Count {
+        started:  usize,
+        finished: usize
+    }
+
+    impl EntryFilter for Count {
+        fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+            self.started += 1;
+            match entry.path.ends_with(".skip") {
+                true => Verdict::Skip,
+                false => Verdict::Keep
+            }
+        }
+
+        fn finish(&mut self, _out: &mut Vec<u8>) -> Verdict {
+            self.finished += 1;
+            Verdict::Keep
+        }
+    }
+
+    fn archive(entries: &[(&str, &[u8])]) -> Archive {
+        let mut b = Builder::new(Vec::new());
+        for &(path, contents) in entries {
+            b.append(&Header::new(path), contents).unwrap();
+        }
+        Archive::new(b.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn filter_chain_test() {
+        let source = archive(&[("a", b"{{name}} here"), ("b.skip", b"")]);
+        let mut chain = FilterChain::new();
+        chain.push(Substitute(Replace::new(b"{{name}}", b"tar")));
+        chain.push(Shout { seen: Vec::new() });
+
+        let listed = filter_listing(&source, &mut chain).unwrap();
+        assert_eq!(listed.iter().map(|e| &e.path[..]).collect::<Vec<_>>(), vec!["A"]);
+
+        let mut b = Builder::new(Vec::new());
+        assert_eq!(convert(&source, &mut chain, &mut b).unwrap(), 1);
+        let converted = Archive::new(b.finish().unwrap()).unwrap();
+        assert_eq!(converted.contents_of("A"), Some(&b"TAR HERE"[..]));
+
+        match filter_listing(&archive(&[("ok", b""), ("bad", b"a virus")]), &mut chain) {
+            Err(Error::Rejected { ref path, ref reason }) if path == "BAD" && reason == "found a virus" => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+
+        /* Entries skipped by their header are finished too */
+        let mut count = Count::default();
+        let listed = filter_listing(&archive(&[("a", b"1"), ("b.skip", b"2"), ("c", b"")]), &mut count).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!((count.started, count.finished), (3, 3));
+    }
+
+    #[test]
+    fn convert_pax_test() {
+        use builder::pax_record;
+
+        let long = format!("{}/{}", "d".repeat(150), "f".repeat(120));
+        let mut pax = Header::new("././@PaxHeader");
+        pax.typeflag = TypeFlag::PaxExtendedAttributes;
+        let mut records = pax_record("path", long.as_bytes());
+        records.extend(pax_record("comment", b"kept"));
+        let mut b = Builder::new(Vec::new());
+        b.append(&pax, &records).unwrap();
+        b.append(&Header::new("short"), b"data").unwrap();
+        let source = Archive::new(b.finish().unwrap()).unwrap();
+
+        let mut b = Builder::new(Vec::new());
+        assert_eq!(convert(&source, &mut Shout { seen: Vec::new() }, &mut b).unwrap(), 1);
+        let converted = Archive::new(b.finish().unwrap()).unwrap();
+        let entry = &converted.entries()[0];
+        assert_eq!(entry.path, long.to_uppercase());
+        assert_eq!(entry.pax.get("comment"), Some(&b"kept".to_vec()));
+        assert_eq!(converted.contents(entry), b"DATA");
+    }
+}
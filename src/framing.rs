@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+use std::str::from_utf8;
+
+use nom::IResult;
+
+use archive::EntryMetadata;
+use error::Error;
+use parser::{padding, parse_entry, parse_header, PosixHeader, TypeFlag};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Parse options
+ */
+
+/// Default cap on a single PAX or GNU long name record, and on all the
+/// records applying to one entry
+pub const DEFAULT_MAX_METADATA_SIZE: u64 = 4 << 20;
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ParseOptions {
+    /// Largest metadata record accepted before failing with `MetadataTooLarge`
+    pub max_metadata_size:  u64,
+    /// Glob pattern the volume label must match, like `tar --label`
+    pub label:              Option<String>,
+    /// Fail with `InvalidField` on headers with an empty name instead of
+    /// skipping them with a `ParseWarning`
+    pub reject_empty_names: bool,
+    /// End the archive at the first zero block, keeping anything after the
+    /// terminator, a final partial block included, as trailing data instead
+    /// of parsing on
+    pub trailing_data:      bool
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            max_metadata_size:  DEFAULT_MAX_METADATA_SIZE,
+            label:              None,
+            reject_empty_names: false,
+            trailing_data:      false
+        }
+    }
+}
+
+/// Something odd skipped or left over while parsing
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ParseWarning {
+    /// A header at this offset with an empty name, skipped with its contents
+    EmptyName { offset: u64 },
+    /// Bytes after the terminator, see `ParseOptions::trailing_data`
+    TrailingData { offset: u64, length: u64 }
+}
+
+/*
+ * PAX records
+ */
+
+/// Split PAX extended header data into its `key=value` records
+pub fn parse_pax_records(data: &[u8]) -> Option<Vec<(String, Vec<u8>)>> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    /* Writers pad the last record with NULs */
+    while !rest.is_empty() && rest[0] != 0 {
+        let space = rest.iter().position(|b| *b == b' ')?;
+        let len = from_utf8(&rest[..space]).ok()?.parse::<usize>().ok()?;
+        if len <= space + 1 || len > rest.len() || rest[len - 1] != b'\n' {
+            return None;
+        }
+        let record = &rest[space + 1..len - 1];
+        let eq = record.iter().position(|b| *b == b'=')?;
+        records.push((from_utf8(&record[..eq]).ok()?.to_owned(), record[eq + 1..].to_vec()));
+        rest = &rest[len..];
+    }
+    Some(records)
+}
+
+pub(crate) fn pax_number(value: &[u8]) -> Option<u64> {
+    let s = from_utf8(value).ok()?;
+    /* Times may carry a fractional part */
+    let integer = s.split('.').next()?;
+    integer.parse().ok()
+}
+
+fn pax_string(value: &[u8]) -> Option<String> {
+    from_utf8(value).ok().map(|s| s.to_owned())
+}
+
+/*
+ * Entry framing: headers, their contents and the extension records before them
+ */
+
+pub enum Frame {
+    /// A complete entry, the next one starting at `end`
+    Entry { metadata: Box<EntryMetadata>, end: usize },
+    /// An all-zero block where the entry was expected
+    ZeroBlock,
+    /// A header with an empty name at `offset`, to be skipped unless
+    /// `reject_empty_names` is set
+    Empty { offset: u64, end: usize },
+    /// The data ends before the entry being framed is complete
+    Incomplete
+}
+
+/* Extension records collected for the next entry */
+#[derive(Default)]
+pub(crate) struct Pending {
+    path:     Option<String>,
+    linkname: Option<String>,
+    pax:      BTreeMap<String, Vec<u8>>,
+    size:     u64
+}
+
+/// Whether entries of this type carry metadata for the next entry
+pub fn is_extension(typeflag: TypeFlag) -> bool {
+    matches!(typeflag, TypeFlag::PaxExtendedAttributes | TypeFlag::PaxInterexchangeFormat |
+                       TypeFlag::GnuLongName | TypeFlag::GnuLongLink)
+}
+
+/* Whether a block is all zeroes, ending the archive. Extension records
+ * still waiting for their entry make it the end of a truncated one. */
+pub(crate) fn is_zero_block(block: &[u8], extension_offset: Option<u64>) -> Result<bool, Error> {
+    if !block.iter().all(|b| *b == 0) {
+        return Ok(false);
+    }
+    match extension_offset {
+        Some(offset) => Err(Error::Truncated { offset: offset }),
+        None => Ok(true)
+    }
+}
+
+/// Walks entries of a buffer, applying PAX and GNU long name records to the
+/// entries they describe. Global PAX records persist from one call to the next.
+pub struct Framer {
+    options: ParseOptions,
+    globals: BTreeMap<String, Vec<u8>>
+}
+
+impl Framer {
+    pub fn new(options: ParseOptions) -> Framer {
+        Framer {
+            options: options,
+            globals: BTreeMap::new()
+        }
+    }
+
+    pub fn options(&self) -> &ParseOptions {
+        &self.options
+    }
+
+    /// Frame the entry starting at `data[pos..]`, `base` being the archive
+    /// offset of `data[0]`
+    pub fn next(&mut self, data: &[u8], pos: usize, base: u64) -> Result<Frame, Error> {
+        let start = pos;
+        let mut pos = pos;
+        let mut pending = Pending::default();
+        loop {
+            let offset = base + pos as u64;
+            if data.len() - pos < 512 {
+                return Ok(Frame::Incomplete);
+            }
+            let extension_offset = if pos == start { None } else { Some(base + start as u64) };
+            if is_zero_block(&data[pos..pos + 512], extension_offset)? {
+                return Ok(Frame::ZeroBlock);
+            }
+            let header = match parse_header(&data[pos..]) {
+                IResult::Done(_, h) => h,
+                IResult::Incomplete(_) => return Ok(Frame::Incomplete),
+                IResult::Error(_) => return Err(Error::InvalidHeader { offset: offset })
+            };
+            if is_extension(header.typeflag) {
+                self.check_extension(&mut pending, header.size, offset)?;
+            }
+
+            let entry = match parse_entry(&data[pos..]) {
+                IResult::Done(_, e) => e,
+                IResult::Incomplete(_) => return Ok(Frame::Incomplete),
+                IResult::Error(_) => return Err(Error::InvalidHeader { offset: offset })
+            };
+            let data_pos = entry.contents.as_ptr() as usize - data.as_ptr() as usize;
+            let after = data_pos + entry.contents.len() + padding(entry.contents.len() as u64) as usize;
+
+            if is_extension(header.typeflag) {
+                self.push_extension(&mut pending, header.typeflag, entry.contents, offset)?;
+                pos = after;
+                continue;
+            }
+            if self.is_empty(&pending, &header, offset)? {
+                return Ok(Frame::Empty { offset: offset, end: after });
+            }
+
+            let metadata = self.metadata(pending, &header, base + start as u64, offset, base + data_pos as u64)?;
+            let end = (data_pos as u64).checked_add(metadata.size).and_then(|e| e.checked_add(padding(metadata.size)))
+                .ok_or(Error::InvalidField { offset: offset, field: "size" })?;
+            if end > data.len() as u64 {
+                return Ok(Frame::Incomplete);
+            }
+            return Ok(Frame::Entry { metadata: Box::new(metadata), end: end as usize });
+        }
+    }
+
+    /* Enforce the metadata size limit before buffering an extension record */
+    pub(crate) fn check_extension(&self, pending: &mut Pending, size: u64, offset: u64) -> Result<(), Error> {
+        pending.size = pending.size.saturating_add(size);
+        if size > self.options.max_metadata_size || pending.size > self.options.max_metadata_size {
+            return Err(Error::MetadataTooLarge { offset: offset, size: size, limit: self.options.max_metadata_size });
+        }
+        Ok(())
+    }
+
+    /* Record the contents of an extension entry for the next entry */
+    pub(crate) fn push_extension(&mut self, pending: &mut Pending, typeflag: TypeFlag, contents: &[u8], offset: u64) -> Result<(), Error> {
+        match typeflag {
+            TypeFlag::GnuLongName | TypeFlag::GnuLongLink => {
+                let name = from_utf8(contents).map_err(|_| Error::InvalidField { offset: offset, field: "long name" })?;
+                let name = name.trim_end_matches('\0').to_owned();
+                if typeflag == TypeFlag::GnuLongName {
+                    pending.path = Some(name);
+                } else {
+                    pending.linkname = Some(name);
+                }
+            },
+            _ => {
+                let records = parse_pax_records(contents).ok_or(Error::InvalidField { offset: offset, field: "pax records" })?;
+                for (key, value) in records {
+                    if typeflag == TypeFlag::PaxExtendedAttributes {
+                        pending.pax.insert(key, value);
+                    } else if value.is_empty() {
+                        self.globals.remove(&key);
+                    } else {
+                        self.globals.insert(key, value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* Whether a header names nothing, which is an error if so configured */
+    pub(crate) fn is_empty(&self, pending: &Pending, header: &PosixHeader, offset: u64) -> Result<bool, Error> {
+        let empty = header.name.is_empty() && pending.path.is_none() && !pending.pax.contains_key("path");
+        if empty && self.options.reject_empty_names {
+            return Err(Error::InvalidField { offset: offset, field: "name" });
+        }
+        Ok(empty)
+    }
+
+    /* Metadata of a regular entry, with its extension records applied */
+    pub(crate) fn metadata(&self, pending: Pending, header: &PosixHeader, extension_offset: u64, offset: u64, data_offset: u64) -> Result<EntryMetadata, Error> {
+        let mut metadata = EntryMetadata::from_header(header, offset, data_offset)?;
+        metadata.extension_offset = extension_offset;
+        if let Some(path) = pending.path {
+            metadata.path = path;
+        }
+        if let Some(linkname) = pending.linkname {
+            metadata.linkname = linkname;
+        }
+        let mut pax = self.globals.clone();
+        pax.extend(pending.pax);
+        apply_pax(&mut metadata, &pax, offset)?;
+        metadata.pax = pax;
+        Ok(metadata)
+    }
+}
+
+/* Override header fields with PAX records */
+fn apply_pax(m: &mut EntryMetadata, pax: &BTreeMap<String, Vec<u8>>, offset: u64) -> Result<(), Error> {
+    for (key, value) in pax {
+        let invalid = Error::InvalidField { offset: offset, field: "pax records" };
+        match key.as_str() {
+            "path" => m.path = pax_string(value).ok_or(invalid)?,
+            "linkpath" => m.linkname = pax_string(value).ok_or(invalid)?,
+            "uname" => m.uname = pax_string(value).ok_or(invalid)?,
+            "gname" => m.gname = pax_string(value).ok_or(invalid)?,
+            "uid" => m.uid = pax_number(value).ok_or(invalid)?,
+            "gid" => m.gid = pax_number(value).ok_or(invalid)?,
+            "mtime" => m.mtime = pax_number(value).ok_or(invalid)?,
+            "size" => m.size = pax_number(value).ok_or(invalid)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archive::Archive;
+    use builder::{pax_record, Builder, Header};
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_warning_test() {
+        use serde_json;
+
+        let warning = ParseWarning::TrailingData { offset: 3584, length: 512 };
+        let json = serde_json::to_string(&warning).unwrap();
+        assert_eq!(json, r#"{"trailing_data":{"offset":3584,"length":512}}"#);
+        assert_eq!(serde_json::from_str::<ParseWarning>(&json).unwrap(), warning);
+    }
+
+    #[test]
+    fn pax_records_test() {
+        let mut data = pax_record("path", b"a/b");
+        data.extend(pax_record("mtime", b"1432983484.5"));
+        data.extend_from_slice(&[0; 5]);
+        let records = parse_pax_records(&data).unwrap();
+        assert_eq!(records, vec![("path".to_owned(), b"a/b".to_vec()), ("mtime".to_owned(), b"1432983484.5".to_vec())]);
+        assert_eq!(pax_record("a", &[b'x'; 4]), b"9 a=xxxx\n");
+        assert_eq!(pax_record("a", &[b'x'; 5]), b"11 a=xxxxx\n");
+        assert!(parse_pax_records(b"5 a=b\n").is_none());
+    }
+
+    fn extension(typeflag: TypeFlag) -> Header {
+        let mut h = Header::new("././@LongLink");
+        h.typeflag = typeflag;
+        h
+    }
+
+    #[test]
+    fn extensions_applied_test() {
+        let long = "d/".repeat(100) + "file";
+        let mut b = Builder::new(Vec::new());
+        b.append(&extension(TypeFlag::GnuLongName), format!("{}\0", long).as_bytes()).unwrap();
+        b.append(&Header::new("truncated"), b"one").unwrap();
+        b.append(&extension(TypeFlag::PaxExtendedAttributes), &pax_record("uid", b"4000000000")).unwrap();
+        b.append(&Header::new("big-uid"), b"two").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+
+        let entries = archive.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, long);
+        assert_eq!(entries[0].extension_offset, 0);
+        assert_eq!(entries[0].header_offset, 1024);
+        assert_eq!(archive.contents(&entries[0]), b"one");
+        assert_eq!(entries[1].uid, 4000000000);
+        assert_eq!(entries[1].pax.get("uid"), Some(&b"4000000000".to_vec()));
+    }
+
+    #[test]
+    fn orphan_extension_test() {
+        /* Records followed by the terminator describe no entry */
+        for &typeflag in &[TypeFlag::PaxExtendedAttributes, TypeFlag::GnuLongName, TypeFlag::GnuLongLink] {
+            let mut b = Builder::new(Vec::new());
+            b.append(&Header::new("a"), b"").unwrap();
+            b.append(&extension(typeflag), &pax_record("path", b"orphan")).unwrap();
+            match Archive::new(b.finish().unwrap()) {
+                Err(Error::Truncated { offset: 512 }) => {},
+                r => panic!("unexpected result: {:?}", r.map(|a| a.entries().len()))
+            }
+        }
+    }
+
+    #[test]
+    fn metadata_too_large_test() {
+        let mut b = Builder::new(Vec::new());
+        b.append(&extension(TypeFlag::GnuLongName), &[b'a'; 2048]).unwrap();
+        b.append(&Header::new("a"), b"").unwrap();
+        let options = ParseOptions {
+            max_metadata_size: 1024,
+            ..ParseOptions::default()
+        };
+        match Archive::with_options(b.finish().unwrap(), &options) {
+            Err(Error::MetadataTooLarge { offset: 0, size: 2048, limit: 1024 }) => {},
+            r => panic!("unexpected result: {:?}", r.map(|a| a.entries().len()))
+        }
+    }
+}
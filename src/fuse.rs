@@ -0,0 +1,160 @@
+extern crate fuser;
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use self::fuser::{Config, Errno, FileAttr, FileHandle, Filesystem, FopenFlags, Generation, INodeNo,
+                  LockOwner, MountOption, OpenAccMode, OpenFlags, ReplyAttr, ReplyData, ReplyDirectory,
+                  ReplyEntry, ReplyOpen, Request};
+
+use vfs::{is_link_loop, ArchiveFs, FileType, Stat};
+
+/*
+ * Read-only FUSE filesystem over an archive
+ */
+
+/* Archives never change under the mount, let the kernel cache for a while */
+const TTL: Duration = Duration::from_secs(60);
+
+fn errno(e: &io::Error) -> Errno {
+    if is_link_loop(e) {
+        return Errno::ELOOP;
+    }
+    match e.kind() {
+        io::ErrorKind::NotFound => Errno::ENOENT,
+        io::ErrorKind::NotADirectory => Errno::ENOTDIR,
+        io::ErrorKind::IsADirectory => Errno::EISDIR,
+        io::ErrorKind::InvalidInput => Errno::EINVAL,
+        _ => Errno::EIO
+    }
+}
+
+fn kind(t: FileType) -> fuser::FileType {
+    match t {
+        FileType::File => fuser::FileType::RegularFile,
+        FileType::Directory => fuser::FileType::Directory,
+        FileType::Symlink => fuser::FileType::Symlink,
+        FileType::CharDevice => fuser::FileType::CharDevice,
+        FileType::BlockDevice => fuser::FileType::BlockDevice,
+        FileType::Fifo => fuser::FileType::NamedPipe
+    }
+}
+
+fn attr(s: &Stat) -> FileAttr {
+    let mtime = UNIX_EPOCH + Duration::from_secs(s.mtime);
+    FileAttr {
+        ino:     INodeNo(s.ino),
+        size:    s.size,
+        blocks:  s.size.div_ceil(512),
+        atime:   mtime,
+        mtime:   mtime,
+        ctime:   mtime,
+        crtime:  mtime,
+        kind:    kind(s.file_type),
+        perm:    (s.mode & 0o7777) as u16,
+        nlink:   if s.file_type == FileType::Directory { 2 } else { 1 },
+        uid:     s.uid as u32,
+        gid:     s.gid as u32,
+        rdev:    ((s.devmajor << 8) | (s.devminor & 0xff) | ((s.devminor & !0xff) << 12)) as u32,
+        blksize: 512,
+        flags:   0
+    }
+}
+
+/// FUSE adapter serving an `ArchiveFs`. Contents are read on demand from the
+/// archive buffer, so mounting an mmap'd archive only pages in what is read.
+///
+/// Gzip compressed archives are mounted through `ArchiveFs::open_gzip`,
+/// whose seek index keeps reads lazy: each one decompresses from the
+/// closest access point before it rather than from the start.
+pub struct ArchiveMount {
+    fs: ArchiveFs
+}
+
+impl ArchiveMount {
+    pub fn new(fs: ArchiveFs) -> ArchiveMount {
+        ArchiveMount {
+            fs: fs
+        }
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.mount_options = vec![MountOption::RO, MountOption::FSName("tar".to_owned()), MountOption::Subtype("tar".to_owned())];
+        config
+    }
+
+    /// Mount on `mountpoint`, blocking until unmounted
+    pub fn mount<P: AsRef<Path>>(self, mountpoint: P) -> io::Result<()> {
+        fuser::mount(self, mountpoint, &ArchiveMount::config())
+    }
+
+    /// Mount on `mountpoint` from a background thread, unmounting when the session is dropped
+    pub fn spawn_mount<P: AsRef<Path>>(self, mountpoint: P) -> io::Result<fuser::BackgroundSession> {
+        fuser::spawn_mount(self, mountpoint, &ArchiveMount::config())
+    }
+}
+
+impl Filesystem for ArchiveMount {
+    fn lookup(&self, _req: &Request, parent: INodeNo, name: &OsStr, reply: ReplyEntry) {
+        let name = match name.to_str() {
+            Some(n) => n,
+            None => return reply.error(Errno::ENOENT)
+        };
+        match self.fs.lookup_ino(parent.0, name) {
+            Ok(s) => reply.entry(&TTL, &attr(&s), Generation(0)),
+            Err(e) => reply.error(errno(&e))
+        }
+    }
+
+    fn getattr(&self, _req: &Request, ino: INodeNo, _fh: Option<FileHandle>, reply: ReplyAttr) {
+        match self.fs.stat_ino(ino.0) {
+            Ok(s) => reply.attr(&TTL, &attr(&s)),
+            Err(e) => reply.error(errno(&e))
+        }
+    }
+
+    fn readlink(&self, _req: &Request, ino: INodeNo, reply: ReplyData) {
+        match self.fs.read_link_ino(ino.0) {
+            Ok(target) => reply.data(target.as_bytes()),
+            Err(e) => reply.error(errno(&e))
+        }
+    }
+
+    fn open(&self, _req: &Request, ino: INodeNo, flags: OpenFlags, reply: ReplyOpen) {
+        match flags.acc_mode() {
+            OpenAccMode::O_RDONLY => {},
+            _ => return reply.error(Errno::EROFS)
+        }
+        match self.fs.stat_ino(ino.0) {
+            Ok(_) => reply.opened(FileHandle(0), FopenFlags::FOPEN_KEEP_CACHE),
+            Err(e) => reply.error(errno(&e))
+        }
+    }
+
+    fn read(&self, _req: &Request, ino: INodeNo, _fh: FileHandle, offset: u64, size: u32,
+            _flags: OpenFlags, _lock_owner: Option<LockOwner>, reply: ReplyData) {
+        match self.fs.read_ino(ino.0, offset, size as usize) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(errno(&e))
+        }
+    }
+
+    fn readdir(&self, _req: &Request, ino: INodeNo, _fh: FileHandle, offset: u64, mut reply: ReplyDirectory) {
+        let (entries, parent) = match (self.fs.read_dir_ino(ino.0), self.fs.parent_ino(ino.0)) {
+            (Ok(entries), Ok(parent)) => (entries, parent),
+            (Err(e), _) | (_, Err(e)) => return reply.error(errno(&e))
+        };
+        let dots = vec![(ino.0, fuser::FileType::Directory, ".".to_owned()), (parent, fuser::FileType::Directory, "..".to_owned())];
+        let all = dots.into_iter().chain(entries.into_iter().map(|e| (e.ino, kind(e.file_type), e.name)));
+        /* Offsets are positions in the listing, the kernel passes back the last one it got */
+        for (i, (ino, kind, name)) in all.enumerate().skip(offset as usize) {
+            if reply.add(INodeNo(ino), i as u64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
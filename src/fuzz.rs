@@ -0,0 +1,257 @@
+extern crate arbitrary;
+
+use self::arbitrary::{Arbitrary, Result, Unstructured};
+use nom::IResult;
+
+use builder::{Builder, Header};
+use parser::{parse_tar, ExtraHeader, TypeFlag};
+
+/*
+ * Structured fuzzing inputs
+ *
+ * Everything generated here is accepted by the builder, values are biased
+ * towards field limits and block boundaries.
+ */
+
+const TYPE_FLAGS: [TypeFlag; 8] = [
+    TypeFlag::NormalFile,
+    TypeFlag::HardLink,
+    TypeFlag::SymbolicLink,
+    TypeFlag::CharacterSpecial,
+    TypeFlag::BlockSpecial,
+    TypeFlag::Directory,
+    TypeFlag::FIFO,
+    TypeFlag::ContiguousFile
+];
+
+fn arbitrary_component(u: &mut Unstructured, max: usize) -> Result<String> {
+    let len = u.int_in_range(1..=max)?;
+    let mut s = String::with_capacity(len);
+    while s.len() < len {
+        let c = match u.int_in_range(0u8..=9)? {
+            0 => char::arbitrary(u)?,
+            1 => ' ',
+            2 => '.',
+            _ => *u.choose(&['a', 'Z', '0', '-', '_', '\u{e9}', '\u{1f980}'])?
+        };
+        if c != '\0' && c != '/' && s.len() + c.len_utf8() <= len {
+            s.push(c);
+        } else {
+            s.push('x');
+        }
+    }
+    Ok(s)
+}
+
+fn arbitrary_string(u: &mut Unstructured, max: usize) -> Result<String> {
+    if u.ratio(1, 4)? {
+        Ok(String::new())
+    } else {
+        arbitrary_component(u, max)
+    }
+}
+
+/* Either short paths, or a prefix/name split close to the ustar limits */
+fn arbitrary_path(u: &mut Unstructured) -> Result<String> {
+    let mut path = if u.ratio(1, 3)? {
+        format!("{}/{}", arbitrary_component(u, 154)?, arbitrary_component(u, 98)?)
+    } else {
+        let mut path = arbitrary_component(u, 32)?;
+        while path.len() < 60 && u.ratio(1, 2)? {
+            path.push('/');
+            path.push_str(&arbitrary_component(u, 32)?);
+        }
+        path
+    };
+    if path.len() < 99 && u.ratio(1, 5)? {
+        path.push('/');
+    }
+    Ok(path)
+}
+
+fn arbitrary_octal(u: &mut Unstructured, digits: u32) -> Result<u64> {
+    let max = (1u64 << (3 * digits)) - 1;
+    match u.int_in_range(0u8..=3)? {
+        0 => Ok(0),
+        1 => Ok(max),
+        _ => u.int_in_range(0..=max)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Header {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Header> {
+        Ok(Header {
+            path:     arbitrary_path(u)?,
+            mode:     arbitrary_octal(u, 7)?,
+            uid:      arbitrary_octal(u, 7)?,
+            gid:      arbitrary_octal(u, 7)?,
+            mtime:    arbitrary_octal(u, 11)?,
+            typeflag: *u.choose(&TYPE_FLAGS)?,
+            linkname: arbitrary_string(u, 99)?,
+            uname:    arbitrary_string(u, 31)?,
+            gname:    arbitrary_string(u, 31)?,
+            devmajor: arbitrary_octal(u, 7)?,
+            devminor: arbitrary_octal(u, 7)?
+        })
+    }
+}
+
+/// An entry to be written: header and contents
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct FuzzEntry {
+    pub header:   Header,
+    pub contents: Vec<u8>
+}
+
+impl<'a> Arbitrary<'a> for FuzzEntry {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<FuzzEntry> {
+        let header = Header::arbitrary(u)?;
+        let len = match u.int_in_range(0u8..=5)? {
+            0 => 0,
+            1 => 511,
+            2 => 512,
+            3 => 513,
+            _ => u.int_in_range(0..=2048)?
+        };
+        let mut contents = u.bytes(len.min(u.len()))?.to_vec();
+        /* Keep block-boundary sizes even when running out of input */
+        contents.resize(len, 0);
+        Ok(FuzzEntry {
+            header:   header,
+            contents: contents
+        })
+    }
+}
+
+/// A corruption applied on top of a valid archive
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub enum Mutation {
+    /// Overwrite the byte at offset (modulo the archive length)
+    SetByte(usize, u8),
+    /// Cut the archive at offset (modulo the archive length)
+    Truncate(usize),
+    /// Append raw bytes after the terminator
+    Append(Vec<u8>)
+}
+
+impl<'a> Arbitrary<'a> for Mutation {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Mutation> {
+        match u.int_in_range(0u8..=5)? {
+            0 => Ok(Mutation::Truncate(usize::arbitrary(u)?)),
+            1 => Ok(Mutation::Append(Vec::arbitrary(u)?)),
+            _ => Ok(Mutation::SetByte(usize::arbitrary(u)?, u8::arbitrary(u)?))
+        }
+    }
+}
+
+/// A whole archive: its entries and optional corruptions
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct FuzzArchive {
+    pub entries:   Vec<FuzzEntry>,
+    pub mutations: Vec<Mutation>
+}
+
+impl<'a> Arbitrary<'a> for FuzzArchive {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<FuzzArchive> {
+        let entries = u.arbitrary_iter()?.take(16).collect::<Result<Vec<FuzzEntry>>>()?;
+        let mutations = if u.ratio(1, 2)? {
+            u.arbitrary_iter()?.take(8).collect::<Result<Vec<Mutation>>>()?
+        } else {
+            Vec::new()
+        };
+        Ok(FuzzArchive {
+            entries:   entries,
+            mutations: mutations
+        })
+    }
+}
+
+impl FuzzArchive {
+    /// The archive as written by the builder, without mutations
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for e in &self.entries {
+            builder.append(&e.header, &e.contents).expect("generated entry rejected by the builder");
+        }
+        builder.finish().expect("writing to a Vec cannot fail")
+    }
+
+    /// The archive with all mutations applied
+    pub fn to_mutated_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        for m in &self.mutations {
+            match *m {
+                Mutation::SetByte(offset, b) => {
+                    if !bytes.is_empty() {
+                        let len = bytes.len();
+                        bytes[offset % len] = b;
+                    }
+                },
+                Mutation::Truncate(offset) => {
+                    let len = offset % (bytes.len() + 1);
+                    bytes.truncate(len);
+                },
+                Mutation::Append(ref extra) => bytes.extend_from_slice(extra)
+            }
+        }
+        bytes
+    }
+
+    /// Write, parse back and panic if anything was lost on the way,
+    /// then make sure the parser survives the mutated version
+    pub fn check_round_trip(&self) {
+        let bytes = self.to_bytes();
+        let entries = match parse_tar(&bytes[..]) {
+            IResult::Done(_, entries) => entries,
+            e => panic!("cannot parse built archive: {:?}", e)
+        };
+        assert_eq!(entries.len(), self.entries.len());
+        for (parsed, e) in entries.iter().zip(self.entries.iter()) {
+            let h = &parsed.header;
+            assert_eq!(h.path(), e.header.path);
+            assert_eq!(h.mode, format!("{:07o}", e.header.mode));
+            assert_eq!(h.uid, e.header.uid);
+            assert_eq!(h.gid, e.header.gid);
+            assert_eq!(h.mtime, e.header.mtime);
+            assert_eq!(h.size, e.contents.len() as u64);
+            assert_eq!(h.typeflag, e.header.typeflag);
+            assert_eq!(h.linkname, e.header.linkname);
+            assert_eq!(parsed.contents, &e.contents[..]);
+            match h.ustar {
+                ExtraHeader::UStar(ref ustar) => {
+                    assert_eq!(ustar.uname, e.header.uname);
+                    assert_eq!(ustar.gname, e.header.gname);
+                    assert_eq!(ustar.devmajor, e.header.devmajor);
+                    assert_eq!(ustar.devminor, e.header.devminor);
+                },
+                ExtraHeader::Padding => panic!("built header parsed as old-style tar")
+            }
+        }
+
+        let _ = parse_tar(&self.to_mutated_bytes()[..]);
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_archives_round_trip_test() {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let seed = (0..1 << 16).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect::<Vec<u8>>();
+        for start in 0..64 {
+            let mut u = Unstructured::new(&seed[start * 1024..]);
+            FuzzArchive::arbitrary(&mut u).unwrap().check_round_trip();
+        }
+    }
+}
@@ -0,0 +1,517 @@
+extern crate flate2;
+extern crate miniz_oxide;
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+
+use self::flate2::Crc;
+use self::miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+use self::miniz_oxide::inflate::core::{decompress, DecompressorOxide, TINFL_LZ_DICT_SIZE};
+use self::miniz_oxide::inflate::TINFLStatus;
+
+/*
+ * Seek index over gzip streams
+ */
+
+/// Uncompressed bytes between two access points of a `GzipIndex` by default
+pub const DEFAULT_SPAN: u64 = 1 << 20;
+
+const CHUNK_SIZE: usize = 64 << 10;
+
+/* Member header flags */
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "gzip stream ends early")
+}
+
+/* Compressed input, buffered, knowing where it is in the stream */
+struct Input<R> {
+    reader: R,
+    buf:    Box<[u8]>,
+    start:  usize,
+    end:    usize,
+    /* Offset of buf[start] in the compressed stream */
+    offset: u64
+}
+
+impl<R: Read> Input<R> {
+    fn new(reader: R) -> Input<R> {
+        Input {
+            reader: reader,
+            buf:    vec![0; CHUNK_SIZE].into_boxed_slice(),
+            start:  0,
+            end:    0,
+            offset: 0
+        }
+    }
+
+    /* Buffered bytes, empty at the end of the stream */
+    fn fill(&mut self) -> io::Result<&[u8]> {
+        while self.start == self.end {
+            match self.reader.read(&mut self.buf) {
+                Ok(n) => {
+                    self.start = 0;
+                    self.end = n;
+                    break;
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(&self.buf[self.start..self.end])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.start += n;
+        self.offset += n as u64;
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let b = self.fill()?.first().cloned();
+        if b.is_some() {
+            self.consume(1);
+        }
+        Ok(b)
+    }
+
+    fn expect(&mut self) -> io::Result<u8> {
+        self.byte()?.ok_or_else(truncated)
+    }
+
+    /* Skip a NUL terminated header field */
+    fn skip_string(&mut self) -> io::Result<()> {
+        while self.expect()? != 0 {}
+        Ok(())
+    }
+}
+
+impl<R: Seek> Input<R> {
+    fn seek(&mut self, offset: u64) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.start = 0;
+        self.end = 0;
+        self.offset = offset;
+        Ok(())
+    }
+}
+
+/* Read a member header, false at the end of the stream */
+fn read_header<R: Read>(input: &mut Input<R>) -> io::Result<bool> {
+    let mut fixed = [0u8; 10];
+    fixed[0] = match input.byte()? {
+        Some(b) => b,
+        None => return Ok(false)
+    };
+    for b in fixed[1..].iter_mut() {
+        *b = input.expect()?;
+    }
+    if fixed[..3] != [0x1f, 0x8b, 8] {
+        return Err(invalid("not a gzip stream"));
+    }
+    let flags = fixed[3];
+    if flags & FEXTRA != 0 {
+        let len = input.expect()? as usize | (input.expect()? as usize) << 8;
+        for _ in 0..len {
+            input.expect()?;
+        }
+    }
+    if flags & FNAME != 0 {
+        input.skip_string()?;
+    }
+    if flags & FCOMMENT != 0 {
+        input.skip_string()?;
+    }
+    if flags & FHCRC != 0 {
+        input.expect()?;
+        input.expect()?;
+    }
+    Ok(true)
+}
+
+/* The inflater and the window of output it refers back to, everything
+ * needed to carry on decompressing from where it is */
+#[derive(Clone)]
+struct Inflate {
+    state:  Box<DecompressorOxide>,
+    window: Box<[u8]>,
+    /* Where in the window the next output goes */
+    pos:    usize
+}
+
+impl Inflate {
+    fn new() -> Inflate {
+        Inflate {
+            state:  Box::default(),
+            window: vec![0; TINFL_LZ_DICT_SIZE].into_boxed_slice(),
+            pos:    0
+        }
+    }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+enum Phase {
+    Header,
+    Deflate,
+    Trailer,
+    End
+}
+
+/* An access point: decompression resumes there reading `input` onwards */
+struct Point {
+    out:     u64,
+    input:   u64,
+    inflate: Inflate
+}
+
+/* Decompresses concatenated gzip members, like `gzip -d` */
+struct Decoder<R> {
+    input:   Input<R>,
+    inflate: Inflate,
+    phase:   Phase,
+    /* Uncompressed offset of the next byte handed out */
+    out:     u64,
+    /* Output in the window not handed out yet */
+    ready:   Range<usize>,
+    /* Checksum of the member so far, when it was read from its start */
+    crc:     Option<Crc>
+}
+
+impl<R: Read> Decoder<R> {
+    fn new(reader: R) -> Decoder<R> {
+        Decoder {
+            input:   Input::new(reader),
+            inflate: Inflate::new(),
+            phase:   Phase::Header,
+            out:     0,
+            ready:   0..0,
+            crc:     None
+        }
+    }
+
+    /* Where decompression stands, if it can resume from here */
+    fn point(&self) -> Option<Point> {
+        if self.phase != Phase::Deflate || !self.ready.is_empty() {
+            return None;
+        }
+        Some(Point {
+            out:     self.out,
+            input:   self.input.offset,
+            inflate: self.inflate.clone()
+        })
+    }
+
+    /* Decompress the next piece of the current member into the window */
+    fn inflate(&mut self) -> io::Result<()> {
+        let data = self.input.fill()?;
+        let flags = if data.is_empty() { 0 } else { TINFL_FLAG_HAS_MORE_INPUT };
+        let pos = self.inflate.pos;
+        let (status, consumed, written) = decompress(&mut self.inflate.state, data, &mut self.inflate.window, pos, flags);
+        self.input.consume(consumed);
+        if let Some(ref mut crc) = self.crc {
+            crc.update(&self.inflate.window[pos..pos + written]);
+        }
+        self.ready = pos..pos + written;
+        self.inflate.pos = (pos + written) & (TINFL_LZ_DICT_SIZE - 1);
+        match status {
+            TINFLStatus::Done => self.phase = Phase::Trailer,
+            TINFLStatus::NeedsMoreInput | TINFLStatus::HasMoreOutput => {},
+            TINFLStatus::FailedCannotMakeProgress => return Err(truncated()),
+            _ => return Err(invalid("corrupt deflate stream"))
+        }
+        Ok(())
+    }
+
+    fn trailer(&mut self) -> io::Result<()> {
+        let mut trailer = [0u8; 8];
+        for b in trailer.iter_mut() {
+            *b = self.input.expect()?;
+        }
+        if let Some(crc) = self.crc.take() {
+            let sum = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+            let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+            if crc.sum() != sum || crc.amount() != size {
+                return Err(invalid("gzip member does not match its checksum"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Decoder<R> {
+    fn restore(&mut self, point: Option<&Point>) -> io::Result<()> {
+        match point {
+            Some(p) => {
+                self.input.seek(p.input)?;
+                self.inflate = p.inflate.clone();
+                self.phase = Phase::Deflate;
+                self.out = p.out;
+            },
+            None => {
+                self.input.seek(0)?;
+                self.phase = Phase::Header;
+                self.out = 0;
+            }
+        }
+        self.ready = 0..0;
+        self.crc = None;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if !self.ready.is_empty() {
+                let n = cmp::min(buf.len(), self.ready.len());
+                buf[..n].copy_from_slice(&self.inflate.window[self.ready.start..self.ready.start + n]);
+                self.ready.start += n;
+                self.out += n as u64;
+                return Ok(n);
+            }
+            match self.phase {
+                Phase::Header => {
+                    if !read_header(&mut self.input)? {
+                        self.phase = Phase::End;
+                        continue;
+                    }
+                    *self.inflate.state = DecompressorOxide::new();
+                    self.crc = Some(Crc::new());
+                    self.phase = Phase::Deflate;
+                },
+                Phase::Deflate => self.inflate()?,
+                Phase::Trailer => {
+                    self.trailer()?;
+                    self.phase = Phase::Header;
+                },
+                Phase::End => return Ok(0)
+            }
+        }
+    }
+}
+
+/// Access points into a gzip stream, about every `span` bytes of the
+/// uncompressed data, so reading from any offset decompresses at most
+/// `span` bytes before getting there. Each point holds the state of the
+/// inflater and its 32 KiB window, about 44 KiB in all.
+pub struct GzipIndex {
+    points: Vec<Point>,
+    len:    u64
+}
+
+impl GzipIndex {
+    /// Decompress a whole stream of one or more members once, checking
+    /// them against their checksums, to index it
+    pub fn build<R: Read>(reader: R, span: u64) -> io::Result<GzipIndex> {
+        let mut decoder = Decoder::new(reader);
+        let mut points = Vec::new();
+        let mut next = span;
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            if decoder.out >= next {
+                if let Some(p) = decoder.point() {
+                    next = p.out + span;
+                    points.push(p);
+                }
+            }
+            if decoder.read(&mut buf)? == 0 {
+                break;
+            }
+        }
+        Ok(GzipIndex {
+            points: points,
+            len:    decoder.out
+        })
+    }
+
+    /// Size of the uncompressed data
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of access points, the start of the stream not counting
+    pub fn access_points(&self) -> usize {
+        self.points.len()
+    }
+
+    /* The last access point at or before `offset` */
+    fn point_before(&self, offset: u64) -> Option<&Point> {
+        match self.points.partition_point(|p| p.out <= offset) {
+            0 => None,
+            n => Some(&self.points[n - 1])
+        }
+    }
+}
+
+/// The uncompressed data of an indexed gzip stream, read from any offset.
+/// Reading on from where the last read stopped never goes back to an
+/// access point.
+pub struct GzipReader<R: Read + Seek> {
+    index:   Arc<GzipIndex>,
+    decoder: Decoder<R>,
+    /* Uncompressed offset the next read starts from */
+    pos:     u64
+}
+
+impl<R: Read + Seek> GzipReader<R> {
+    /// Read the stream `index` was built from
+    pub fn new(reader: R, index: Arc<GzipIndex>) -> GzipReader<R> {
+        GzipReader {
+            index:   index,
+            decoder: Decoder::new(reader),
+            pos:     0
+        }
+    }
+
+    pub fn index(&self) -> &Arc<GzipIndex> {
+        &self.index
+    }
+
+    /* Get the decoder to `pos`, from the closest access point before it
+     * unless the decoder is already between that point and `pos` */
+    fn reposition(&mut self) -> io::Result<()> {
+        let point = self.index.point_before(self.pos);
+        let from = point.map(|p| p.out).unwrap_or(0);
+        if self.decoder.out > self.pos || self.decoder.out < from {
+            self.decoder.restore(point)?;
+        }
+        let skip = self.pos - self.decoder.out;
+        io::copy(&mut (&mut self.decoder).take(skip), &mut io::sink())?;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos != self.decoder.out {
+            self.reposition()?;
+        }
+        if self.pos != self.decoder.out {
+            /* Past the end */
+            return Ok(0);
+        }
+        let n = self.decoder.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for GzipReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.index.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d)
+        };
+        match target {
+            Some(p) => {
+                self.pos = p;
+                Ok(p)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of stream"))
+        }
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use self::flate2::write::GzEncoder;
+    use self::flate2::Compression;
+
+    /* Text with repeats across windows, and noise so it does not shrink to nothing */
+    fn sample(lines: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        let mut data = Vec::new();
+        for i in 0..lines {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            data.extend_from_slice(format!("line {} of the sample, value {}\n", i % 5000, x % 100000).as_bytes());
+        }
+        data
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    #[test]
+    fn random_access_test() {
+        /* Two members, as written by pigz or by concatenating files */
+        let first = sample(40000, 1);
+        let second = sample(30000, 2);
+        let mut compressed = gzip(&first);
+        compressed.extend(gzip(&second));
+        let mut data = first;
+        data.extend(second);
+
+        let index = Arc::new(GzipIndex::build(&compressed[..], 1 << 16).unwrap());
+        assert_eq!(index.len(), data.len() as u64);
+        assert!(index.access_points() >= (data.len() >> 16) - 2);
+
+        let mut reader = GzipReader::new(Cursor::new(compressed), index.clone());
+        let mut x = 7u64;
+        for _ in 0..50 {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            let offset = x % data.len() as u64;
+            let mut buf = vec![0; 3000];
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            let n = reader.read(&mut buf).unwrap();
+            assert!(n > 0);
+            assert_eq!(&buf[..n], &data[offset as usize..offset as usize + n]);
+        }
+
+        /* Reading on, and past the end */
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, &data[10..]);
+        reader.seek(SeekFrom::End(5)).unwrap();
+        assert_eq!(reader.read(&mut [0; 10]).unwrap(), 0);
+    }
+
+    #[test]
+    fn damaged_stream_test() {
+        let data = sample(1000, 3);
+        let compressed = gzip(&data);
+        match GzipIndex::build(&compressed[..compressed.len() - 20], DEFAULT_SPAN) {
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {},
+            r => panic!("unexpected result: {:?}", r.map(|i| i.len()))
+        }
+        let mut corrupt = compressed.clone();
+        let n = corrupt.len();
+        corrupt[n - 8] ^= 1;
+        match GzipIndex::build(&corrupt[..], DEFAULT_SPAN) {
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {},
+            r => panic!("unexpected result: {:?}", r.map(|i| i.len()))
+        }
+        assert!(GzipIndex::build(&data[..], DEFAULT_SPAN).is_err());
+        assert!(GzipIndex::build(&b""[..], DEFAULT_SPAN).unwrap().is_empty());
+    }
+}
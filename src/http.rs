@@ -0,0 +1,174 @@
+use std::io::Cursor;
+
+use archive::{Archive, EntryMetadata};
+use digest::sha256;
+use sniff::content_type;
+use time::civil_from_days;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/*
+ * HTTP response metadata for archive entries
+ */
+
+/// Headers needed to serve an entry as a static file
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ResponseMetadata {
+    pub content_length: u64,
+    /// IMF-fixdate, as expected by the `Last-Modified` header
+    pub last_modified:  String,
+    /// Strong validator derived from the SHA-256 digest of the contents
+    pub etag:           String,
+    pub content_type:   &'static str
+}
+
+impl ResponseMetadata {
+    pub fn for_entry(archive: &Archive, entry: &EntryMetadata) -> ResponseMetadata {
+        let contents = archive.contents(entry);
+        ResponseMetadata {
+            content_length: entry.size,
+            last_modified:  http_date(entry.mtime),
+            etag:           format!("\"{}\"", sha256(contents)),
+            content_type:   content_type(&entry.path, contents)
+        }
+    }
+
+    /// Whether an `If-None-Match` header value matches this entry
+    pub fn matches_etag(&self, if_none_match: &str) -> bool {
+        if_none_match.split(',').map(|t| t.trim()).any(|t| t == "*" || t == self.etag || t.trim_start_matches("W/") == self.etag)
+    }
+}
+
+/// Format seconds since the epoch as an HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`)
+pub fn http_date(secs: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let (y, m, d) = civil_from_days(days);
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            DAYS[(days % 7) as usize], d, MONTHS[(m - 1) as usize], y, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+/*
+ * Range requests
+ */
+
+/// How a `Range` header applies to a body of a given length
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub enum RangeOutcome {
+    /// Serve the whole body with a 200
+    Full,
+    /// Serve bytes `start..=end` with a 206 and this `Content-Range`
+    Partial { start: u64, end: u64, content_range: String },
+    /// Answer 416 with this `Content-Range`
+    Unsatisfiable { content_range: String }
+}
+
+impl RangeOutcome {
+    /// Resolve an optional `Range` header value. Multiple ranges and unknown
+    /// units are ignored, which the RFC allows, and yield the full body
+    pub fn resolve(range: Option<&str>, len: u64) -> RangeOutcome {
+        let spec = match range.map(|r| r.trim()) {
+            Some(r) if r.starts_with("bytes=") && !r.contains(',') => &r[6..],
+            _ => return RangeOutcome::Full
+        };
+        let dash = match spec.find('-') {
+            Some(i) => i,
+            None => return RangeOutcome::Full
+        };
+        let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+        let bounds = if first.is_empty() {
+            /* Suffix range: the last n bytes */
+            match last.parse::<u64>() {
+                Ok(0) => None,
+                Ok(n) if len > 0 => Some((len.saturating_sub(n), len - 1)),
+                Ok(_) => None,
+                Err(_) => return RangeOutcome::Full
+            }
+        } else {
+            let start = match first.parse::<u64>() {
+                Ok(s) => s,
+                Err(_) => return RangeOutcome::Full
+            };
+            let end = if last.is_empty() {
+                len.saturating_sub(1)
+            } else {
+                match last.parse::<u64>() {
+                    Ok(e) if e >= start => e.min(len.saturating_sub(1)),
+                    _ => return RangeOutcome::Full
+                }
+            };
+            if start < len { Some((start, end)) } else { None }
+        };
+        match bounds {
+            Some((start, end)) => RangeOutcome::Partial {
+                start:         start,
+                end:           end,
+                content_range: format!("bytes {}-{}/{}", start, end, len)
+            },
+            None => RangeOutcome::Unsatisfiable {
+                content_range: format!("bytes */{}", len)
+            }
+        }
+    }
+}
+
+/// Reader over the part of the entry contents selected by the range
+pub fn body<'a>(archive: &'a Archive, entry: &EntryMetadata, range: &RangeOutcome) -> Cursor<&'a [u8]> {
+    let contents = archive.contents(entry);
+    let selected = match *range {
+        RangeOutcome::Full => contents,
+        RangeOutcome::Partial { start, end, .. } => &contents[start as usize..end as usize + 1],
+        RangeOutcome::Unsatisfiable { .. } => &contents[..0]
+    };
+    Cursor::new(selected)
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn http_date_test() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(1432983484), "Sat, 30 May 2015 10:58:04 GMT");
+    }
+
+    #[test]
+    fn range_test() {
+        assert_eq!(RangeOutcome::resolve(None, 10), RangeOutcome::Full);
+        assert_eq!(RangeOutcome::resolve(Some("bytes=0-1,4-5"), 10), RangeOutcome::Full);
+        assert_eq!(RangeOutcome::resolve(Some("bytes=2-"), 10), RangeOutcome::Partial {
+            start: 2, end: 9, content_range: "bytes 2-9/10".to_owned()
+        });
+        assert_eq!(RangeOutcome::resolve(Some("bytes=-3"), 10), RangeOutcome::Partial {
+            start: 7, end: 9, content_range: "bytes 7-9/10".to_owned()
+        });
+        assert_eq!(RangeOutcome::resolve(Some("bytes=10-20"), 10), RangeOutcome::Unsatisfiable {
+            content_range: "bytes */10".to_owned()
+        });
+    }
+
+    #[test]
+    fn entry_response_test() {
+        let archive = Archive::new(include_bytes!("../examples/simple/test.tar").to_vec()).unwrap();
+        let entry = archive.get("test/foo").unwrap();
+        let meta = ResponseMetadata::for_entry(&archive, entry);
+        assert_eq!(meta.content_length, 12);
+        assert_eq!(meta.content_type, "text/plain; charset=utf-8");
+        assert!(meta.matches_etag(&format!("W/{}", meta.etag)));
+
+        let mut s = String::new();
+        body(&archive, entry, &RangeOutcome::resolve(Some("bytes=8-10"), entry.size)).read_to_string(&mut s).unwrap();
+        assert_eq!(s, "foo");
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use archive::{index_key, Archive, EntryMetadata};
+use atomic::AtomicFile;
+use builder::{type_flag_to_byte, Builder, Header};
+use digest::{sha256, Digest};
+use error::Error;
+use parser::{char_to_type_flag, padding, TypeFlag};
+use rename::{escape, unescape};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Sidecar indexes, kept up to date as entries are appended
+ */
+
+const MAGIC: &str = "tar-index 2";
+
+/// Where an entry is and what it holds
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IndexEntry {
+    pub path:             String,
+    pub typeflag:         TypeFlag,
+    /// Offset of the first PAX or GNU long name record of the entry, or of
+    /// its header when it has none
+    pub extension_offset: u64,
+    pub header_offset:    u64,
+    pub data_offset:      u64,
+    pub size:             u64,
+    /// Digest of the contents of regular files
+    pub digest:           Option<Digest>
+}
+
+fn is_regular(typeflag: TypeFlag) -> bool {
+    typeflag == TypeFlag::NormalFile || typeflag == TypeFlag::ContiguousFile
+}
+
+impl IndexEntry {
+    fn new(e: &EntryMetadata, contents: &[u8]) -> IndexEntry {
+        IndexEntry {
+            path:             e.path.clone(),
+            typeflag:         e.typeflag,
+            extension_offset: e.extension_offset,
+            header_offset:    e.header_offset,
+            data_offset:      e.data_offset,
+            size:             e.size,
+            digest:           if is_regular(e.typeflag) { Some(e.recorded_digest().unwrap_or_else(|| sha256(contents))) } else { None }
+        }
+    }
+}
+
+/// Entries of an archive stored next to it, so they can be listed and
+/// looked up without scanning the archive.
+///
+/// The index remembers the length of the archive it describes and a digest
+/// of its last header, and is considered stale once either changed.
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct ArchiveIndex {
+    entries:     Vec<IndexEntry>,
+    lookup:      HashMap<String, usize>,
+    /* Offset of the terminator, where the next header goes */
+    end:         u64,
+    length:      u64,
+    /* Digest of the header block of the last entry */
+    last_header: Option<Digest>
+}
+
+/// Path of the sidecar index of an archive: its own path with `.index` added
+pub fn sidecar_path<P: AsRef<Path>>(archive: P) -> PathBuf {
+    let mut path = archive.as_ref().as_os_str().to_owned();
+    path.push(".index");
+    PathBuf::from(path)
+}
+
+fn invalid(n: usize, msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("index line {}: {}", n, msg))
+}
+
+impl ArchiveIndex {
+    /// Index of a loaded archive, digesting the regular files that do not
+    /// record their digest
+    pub fn from_archive(archive: &Archive) -> ArchiveIndex {
+        let mut index = ArchiveIndex::default();
+        for e in archive.volume_header().into_iter().chain(archive.entries()) {
+            index.end = index.end.max(e.data_offset + e.size + padding(e.size));
+        }
+        for e in archive.entries() {
+            index.push(IndexEntry::new(e, archive.contents(e)));
+        }
+        index.length = archive.as_bytes().len() as u64;
+        index.last_header = archive.entries().last()
+            .map(|e| sha256(&archive.as_bytes()[e.header_offset as usize..][..512]));
+        index
+    }
+
+    fn push(&mut self, entry: IndexEntry) {
+        /* Later copies of a path replace earlier ones, as in the archive index */
+        self.lookup.insert(index_key(&entry.path).to_owned(), self.entries.len());
+        self.end = self.end.max(entry.data_offset + entry.size + padding(entry.size));
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.lookup.get(index_key(path)).map(|&i| &self.entries[i])
+    }
+
+    /// Length of the archive the index describes
+    pub fn archive_length(&self) -> u64 {
+        self.length
+    }
+
+    /// Read an index written by `write`
+    pub fn read<R: BufRead>(reader: R) -> io::Result<ArchiveIndex> {
+        let mut lines = reader.lines();
+        let header = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+        let mut fields = header.rsplitn(4, ' ');
+        let (end, length, last_header) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(last), Some(end), Some(length), Some(MAGIC)) => match (length.parse(), end.parse(), last) {
+                (Ok(length), Ok(end), "-") => (end, length, None),
+                (Ok(length), Ok(end), hex) => (end, length, Some(Digest::from_hex(hex).ok_or_else(|| invalid(1, "invalid digest"))?)),
+                _ => return Err(invalid(1, "invalid archive length"))
+            },
+            _ => return Err(invalid(1, "not an archive index"))
+        };
+        let mut index = ArchiveIndex::default();
+        for (n, line) in lines.enumerate() {
+            let line = line?;
+            let n = n + 2;
+            let fields = line.splitn(7, '\t').collect::<Vec<_>>();
+            if fields.len() != 7 {
+                return Err(invalid(n, "expected seven tab separated fields"));
+            }
+            let number = |s: &str| s.parse::<u64>().map_err(|_| invalid(n, "invalid number"));
+            let typeflag = match fields[4].chars().collect::<Vec<_>>()[..] {
+                [c] => char_to_type_flag(c),
+                _ => return Err(invalid(n, "invalid type"))
+            };
+            let digest = match fields[5] {
+                "-" => None,
+                hex => Some(Digest::from_hex(hex).ok_or_else(|| invalid(n, "invalid digest"))?)
+            };
+            index.push(IndexEntry {
+                path:             unescape(fields[6]).ok_or_else(|| invalid(n, "invalid escape"))?,
+                typeflag:         typeflag,
+                extension_offset: number(fields[0])?,
+                header_offset:    number(fields[1])?,
+                data_offset:      number(fields[2])?,
+                size:             number(fields[3])?,
+                digest:           digest
+            });
+        }
+        if index.end > end {
+            return Err(invalid(1, "entries beyond the end of the archive"));
+        }
+        index.end = end;
+        index.length = length;
+        index.last_header = last_header;
+        Ok(index)
+    }
+
+    /// Write the index as a header line then one tab separated line per entry
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let hex = |d: Option<&Digest>| d.map(|d| d.to_hex()).unwrap_or_else(|| "-".to_owned());
+        writeln!(out, "{} {} {} {}", MAGIC, self.length, self.end, hex(self.last_header.as_ref()))?;
+        for e in &self.entries {
+            /* The exact vendor type is not kept, any uppercase letter stands for it */
+            let typeflag = match e.typeflag {
+                TypeFlag::VendorSpecific => b'A',
+                t => type_flag_to_byte(t)?
+            };
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}", e.extension_offset, e.header_offset, e.data_offset, e.size,
+                typeflag as char, hex(e.digest.as_ref()), escape(&e.path))?;
+        }
+        out.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<ArchiveIndex> {
+        ArchiveIndex::read(BufReader::new(File::open(path)?))
+    }
+
+    /// The sidecar index of the archive at `archive`, if there is one and it
+    /// is current
+    pub fn load_current<P: AsRef<Path>>(archive: P) -> io::Result<Option<ArchiveIndex>> {
+        let mut file = File::open(&archive)?;
+        match ArchiveIndex::load(sidecar_path(&archive)) {
+            Ok(index) => Ok(if is_current(&index, &mut file)? { Some(index) } else { None }),
+            Err(_) => Ok(None)
+        }
+    }
+
+    /// Write the index to `path`, replacing any previous one atomically
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = AtomicFile::create(path)?;
+        self.write(&mut file)?;
+        file.commit()
+    }
+}
+
+/* Whether `index` still describes the archive in `file` */
+fn is_current(index: &ArchiveIndex, file: &mut File) -> io::Result<bool> {
+    if file.metadata()?.len() != index.length {
+        return Ok(false);
+    }
+    let mut block = [0u8; 512];
+    if let (Some(e), Some(digest)) = (index.entries.last(), index.last_header.as_ref()) {
+        file.seek(SeekFrom::Start(e.header_offset))?;
+        file.read_exact(&mut block)?;
+        if sha256(&block) != *digest {
+            return Ok(false);
+        }
+    }
+    file.seek(SeekFrom::Start(index.end))?;
+    file.read_exact(&mut block)?;
+    Ok(block.iter().all(|b| *b == 0))
+}
+
+/// Appends entries to an archive on disk, updating its sidecar index with
+/// each one instead of scanning the archive again.
+///
+/// Entries are written over the terminator, which `finish` writes back
+/// after them. Padding up to a record size is dropped.
+pub struct Appender {
+    builder: Builder<File>,
+    index:   ArchiveIndex,
+    sidecar: PathBuf
+}
+
+impl Appender {
+    /// Open an archive for appending. Its sidecar index is used when it is
+    /// current, otherwise the archive is scanned once to rebuild it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Appender, Error> {
+        let sidecar = sidecar_path(&path);
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let index = match ArchiveIndex::load_current(&path).unwrap_or(None) {
+            Some(index) => index,
+            None => {
+                let archive = Archive::open(&path)?;
+                /* Entries written over the terminator would bury what follows it */
+                if let Some((offset, _)) = archive.trailing_data() {
+                    let msg = format!("archive has data after its terminator at offset {}", offset);
+                    return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, msg)));
+                }
+                ArchiveIndex::from_archive(&archive)
+            }
+        };
+        file.seek(SeekFrom::Start(index.end))?;
+        Ok(Appender {
+            builder: Builder::resume(file, index.end),
+            index:   index,
+            sidecar: sidecar
+        })
+    }
+
+    /// The index, including the entries appended so far
+    pub fn index(&self) -> &ArchiveIndex {
+        &self.index
+    }
+
+    /// Append an entry header followed by its padded contents
+    pub fn append(&mut self, header: &Header, contents: &[u8]) -> io::Result<()> {
+        let offset = self.builder.offset();
+        let block = header.to_block(contents.len() as u64)?;
+        self.builder.append(header, contents)?;
+        self.index.last_header = Some(sha256(&block));
+        self.index.push(IndexEntry {
+            path:             header.path.clone(),
+            typeflag:         header.typeflag,
+            extension_offset: offset,
+            header_offset:    offset,
+            data_offset:      offset + 512,
+            size:             contents.len() as u64,
+            digest:           if is_regular(header.typeflag) { Some(sha256(contents)) } else { None }
+        });
+        Ok(())
+    }
+
+    /// Terminate the archive and save the updated index next to it
+    pub fn finish(self) -> io::Result<ArchiveIndex> {
+        let mut index = self.index;
+        let file = self.builder.finish()?;
+        index.length = index.end + 1024;
+        file.set_len(index.length)?;
+        file.sync_all()?;
+        index.save(&self.sidecar)?;
+        Ok(index)
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    #[test]
+    fn append_test() {
+        let dir = env::temp_dir().join(format!("tar-index-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log.tar");
+        fs::write(&path, &include_bytes!("../examples/simple/test.tar")[..]).unwrap();
+
+        /* No index yet, the archive is scanned */
+        let mut appender = Appender::open(&path).unwrap();
+        assert_eq!(appender.index().entries().len(), 4);
+        appender.append(&Header::new("log/1"), b"first").unwrap();
+        let index = appender.finish().unwrap();
+        assert_eq!(ArchiveIndex::load(sidecar_path(&path)).unwrap(), index);
+
+        /* The saved index is current, so only the new entry is added */
+        let mut appender = Appender::open(&path).unwrap();
+        assert_eq!(appender.index(), &index);
+        appender.append(&Header::new("log/2"), b"second").unwrap();
+        appender.append(&Header::new("test/foo"), b"replaced").unwrap();
+        let index = appender.finish().unwrap();
+
+        let archive = Archive::open(&path).unwrap();
+        assert_eq!(index, ArchiveIndex::from_archive(&archive));
+        let entry = index.get("test/foo").unwrap();
+        assert_eq!(entry.digest, Some(sha256(b"replaced")));
+        assert_eq!(&archive.as_bytes()[entry.data_offset as usize..][..8], b"replaced");
+        assert_eq!(index.get("test/").unwrap().digest, None);
+
+        /* Rewriting the last header in place makes the index stale */
+        let mut data = fs::read(&path).unwrap();
+        let last = index.entries().last().unwrap().header_offset as usize;
+        assert_eq!(ArchiveIndex::load_current(&path).unwrap(), Some(index.clone()));
+        data[last + 136] = b'1';
+        fs::write(&path, &data).unwrap();
+        assert_eq!(ArchiveIndex::load_current(&path).unwrap(), None);
+
+        /* Once the archive changes behind its back the index is rebuilt */
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("other"), b"").unwrap();
+        fs::write(&path, b.finish().unwrap()).unwrap();
+        let appender = Appender::open(&path).unwrap();
+        assert_eq!(appender.index().entries().iter().map(|e| &e.path[..]).collect::<Vec<_>>(), vec!["other"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_test() {
+        let archive = Archive::new(include_bytes!("../examples/simple/test.tar").to_vec()).unwrap();
+        let index = ArchiveIndex::from_archive(&archive);
+        let mut out = Vec::new();
+        index.write(&mut out).unwrap();
+        let last = sha256(&archive.as_bytes()[2560..3072]).to_hex();
+        assert!(out.starts_with(format!("tar-index 2 10240 3584 {}\n0\t0\t512\t0\t5\t-\ttest/\n", last).as_bytes()));
+        assert_eq!(ArchiveIndex::read(&out[..]).unwrap(), index);
+        assert!(ArchiveIndex::read(&b"tar-index 1 0 0\n"[..]).is_err());
+        assert!(ArchiveIndex::read(&b"tar-index 2 0 0 -\n0\t0\t512\t1\t0\t-\n"[..]).is_err());
+        assert!(ArchiveIndex::read(&b"tar-index 2 0 0 abc\n"[..]).is_err());
+
+        /* Vendor specific types are kept as such */
+        let mut index = ArchiveIndex::from_archive(&archive);
+        index.entries[1].typeflag = TypeFlag::VendorSpecific;
+        let mut out = Vec::new();
+        index.write(&mut out).unwrap();
+        assert_eq!(ArchiveIndex::read(&out[..]).unwrap().entries()[1].typeflag, TypeFlag::VendorSpecific);
+    }
+}
@@ -0,0 +1,282 @@
+#[cfg(feature = "gzip")]
+extern crate flate2;
+
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use archive::Archive;
+use builder::Builder;
+use error::Error;
+use framing::ParseOptions;
+
+/*
+ * Layers: whole stream transformations between the tar framing and the
+ * bytes stored, like encryption or compression
+ */
+
+/// One direction of a layer, turning the stream chunk by chunk. Unlike
+/// entry transforms codecs may fail, for instance on corrupt input.
+pub trait StreamCodec {
+    /// Process the next chunk, appending the result to `out`
+    fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()>;
+
+    /// Emit anything held back once the stream is over
+    fn finish(&mut self, _out: &mut Vec<u8>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A reversible stream transformation, giving what archives are encoded
+/// with when written and decoded with when read
+pub trait Layer {
+    fn encoder(&self) -> Box<dyn StreamCodec + Send>;
+
+    fn decoder(&self) -> Box<dyn StreamCodec + Send>;
+}
+
+/// Writer passing everything through a codec before `inner`. Wrapping an
+/// `Extractor` in the decoder of a layer extracts layered archives.
+pub struct LayerWriter<W: Write> {
+    inner: W,
+    codec: Box<dyn StreamCodec + Send>,
+    out:   Vec<u8>
+}
+
+impl<W: Write> LayerWriter<W> {
+    pub fn new(inner: W, codec: Box<dyn StreamCodec + Send>) -> LayerWriter<W> {
+        LayerWriter {
+            inner: inner,
+            codec: codec,
+            out:   Vec::new()
+        }
+    }
+
+    /// Write out what the codec held back and give back the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.clear();
+        self.codec.finish(&mut self.out)?;
+        self.inner.write_all(&self.out)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for LayerWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.clear();
+        self.codec.update(buf, &mut self.out)?;
+        self.inner.write_all(&self.out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+const CHUNK_SIZE: usize = 64 << 10;
+
+/// Reader passing everything read from `inner` through a codec, for the
+/// streaming functions taking any reader
+pub struct LayerReader<R: Read> {
+    inner:    R,
+    codec:    Box<dyn StreamCodec + Send>,
+    buf:      Vec<u8>,
+    out:      Vec<u8>,
+    pos:      usize,
+    finished: bool
+}
+
+impl<R: Read> LayerReader<R> {
+    pub fn new(inner: R, codec: Box<dyn StreamCodec + Send>) -> LayerReader<R> {
+        LayerReader {
+            inner:    inner,
+            codec:    codec,
+            buf:      vec![0; CHUNK_SIZE],
+            out:      Vec::new(),
+            pos:      0,
+            finished: false
+        }
+    }
+}
+
+impl<R: Read> Read for LayerReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        /* Codecs may take several chunks before producing anything */
+        while self.pos == self.out.len() && !self.finished {
+            self.out.clear();
+            self.pos = 0;
+            let n = match self.inner.read(&mut self.buf) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+            match n {
+                0 => {
+                    self.finished = true;
+                    self.codec.finish(&mut self.out)?;
+                },
+                n => self.codec.update(&self.buf[..n], &mut self.out)?
+            }
+        }
+        let n = cmp::min(buf.len(), self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Builder<LayerWriter<W>> {
+    /// Write an archive encoded by `layer`
+    pub fn layered<L: Layer + ?Sized>(inner: W, layer: &L) -> Builder<LayerWriter<W>> {
+        Builder::new(LayerWriter::new(inner, layer.encoder()))
+    }
+
+    /// Write the terminator, then the end of the encoded stream
+    pub fn finish_layered(self) -> io::Result<W> {
+        self.finish()?.finish()
+    }
+}
+
+impl Archive {
+    /// Load an archive encoded by `layer`
+    pub fn read_layered<R: Read, L: Layer + ?Sized>(reader: R, layer: &L, options: &ParseOptions) -> Result<Archive, Error> {
+        let mut data = Vec::new();
+        LayerReader::new(reader, layer.decoder()).read_to_end(&mut data)?;
+        Archive::with_options(data, options)
+    }
+}
+
+/*
+ * Gzip
+ */
+
+/// Gzip compression as a layer, decoding concatenated members like `gzip -d`
+#[cfg(feature = "gzip")]
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq,Hash)]
+pub struct GzipLayer;
+
+#[cfg(feature = "gzip")]
+struct GzipEncoder(flate2::write::GzEncoder<Vec<u8>>);
+
+#[cfg(feature = "gzip")]
+impl StreamCodec for GzipEncoder {
+    fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        self.0.write_all(input)?;
+        out.append(self.0.get_mut());
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        self.0.try_finish()?;
+        out.append(self.0.get_mut());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gzip")]
+struct GzipDecoder(flate2::write::MultiGzDecoder<Vec<u8>>);
+
+#[cfg(feature = "gzip")]
+impl StreamCodec for GzipDecoder {
+    fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        self.0.write_all(input)?;
+        out.append(self.0.get_mut());
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        self.0.try_finish()?;
+        out.append(self.0.get_mut());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl Layer for GzipLayer {
+    fn encoder(&self) -> Box<dyn StreamCodec + Send> {
+        Box::new(GzipEncoder(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default())))
+    }
+
+    fn decoder(&self) -> Box<dyn StreamCodec + Send> {
+        Box::new(GzipDecoder(flate2::write::MultiGzDecoder::new(Vec::new())))
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::Header;
+    use sums::{write_sums, SumsFormat};
+
+    /* XORs with a repeating key, and refuses to decode a stream cut short */
+    struct Xor {
+        key: Vec<u8>
+    }
+
+    struct XorCodec {
+        key:    Vec<u8>,
+        pos:    usize,
+        decode: bool
+    }
+
+    impl StreamCodec for XorCodec {
+        fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+            for &b in input {
+                out.push(b ^ self.key[self.pos % self.key.len()]);
+                self.pos += 1;
+            }
+            Ok(())
+        }
+
+        fn finish(&mut self, _out: &mut Vec<u8>) -> io::Result<()> {
+            match self.decode && !self.pos.is_multiple_of(512) {
+                true => Err(io::Error::new(io::ErrorKind::InvalidData, "stream cut short")),
+                false => Ok(())
+            }
+        }
+    }
+
+    impl Layer for Xor {
+        fn encoder(&self) -> Box<dyn StreamCodec + Send> {
+            Box::new(XorCodec { key: self.key.clone(), pos: 0, decode: false })
+        }
+
+        fn decoder(&self) -> Box<dyn StreamCodec + Send> {
+            Box::new(XorCodec { key: self.key.clone(), pos: 0, decode: true })
+        }
+    }
+
+    #[test]
+    fn layer_test() {
+        let layer = Xor { key: b"secret".to_vec() };
+        let mut b = Builder::layered(Vec::new(), &layer);
+        b.append(&Header::new("a"), b"hidden").unwrap();
+        let data = b.finish_layered().unwrap();
+        assert!(!data.windows(6).any(|w| w == b"hidden"));
+
+        let archive = Archive::read_layered(&data[..], &layer, &ParseOptions::default()).unwrap();
+        assert_eq!(archive.contents_of("a"), Some(&b"hidden"[..]));
+        let reader = LayerReader::new(&data[..], layer.decoder());
+        assert_eq!(write_sums(reader, &ParseOptions::default(), SumsFormat::Gnu, io::sink()).unwrap(), 1);
+        match Archive::read_layered(&data[..1000], &layer, &ParseOptions::default()) {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::InvalidData => {},
+            r => panic!("unexpected result: {:?}", r.map(|a| a.entries().len()))
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_layer_test() {
+        let mut b = Builder::layered(Vec::new(), &GzipLayer);
+        b.append(&Header::new("a"), &[b'x'; 10000]).unwrap();
+        let data = b.finish_layered().unwrap();
+        assert!(data.starts_with(b"\x1f\x8b") && data.len() < 1000);
+        let archive = Archive::read_layered(&data[..], &GzipLayer, &ParseOptions::default()).unwrap();
+        assert_eq!(archive.contents_of("a").map(|c| c.len()), Some(10000));
+    }
+}
@@ -1,6 +1,58 @@
-#[macro_use]
+#![allow(clippy::redundant_field_names)]
+
 extern crate nom;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 pub use self::parser::*;
 
+pub mod analysis;
+pub mod archive;
+pub mod atomic;
+pub mod batch;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod builder;
+pub mod cache;
+pub mod digest;
+pub mod dump;
+pub mod error;
+pub mod extract;
+pub mod field;
+pub mod filter;
+pub mod framing;
+#[cfg(feature = "fuse")]
+pub mod fuse;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+#[cfg(feature = "gzip")]
+pub mod gzindex;
+pub mod http;
+pub mod index;
+pub mod layer;
+pub mod lines;
+pub mod listing;
+pub mod merge;
+pub mod mode;
+pub mod oci;
+pub mod pack;
 pub mod parser;
+pub mod paths;
+pub mod provenance;
+pub mod rename;
+pub mod route;
+pub mod secrets;
+pub mod sink;
+#[cfg(all(unix, feature = "snapshot"))]
+pub mod snapshot;
+pub mod slice;
+pub mod sniff;
+pub mod sums;
+pub mod tail;
+pub mod time;
+pub mod trailer;
+pub mod transform;
+pub mod validate;
+pub mod vfs;
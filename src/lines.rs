@@ -0,0 +1,265 @@
+use std::cmp;
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::from_utf8;
+
+use archive::{Archive, EntryMetadata};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Reading text entries line by line
+ */
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Any byte stream, each byte standing for the same code point
+    Latin1
+}
+
+impl Encoding {
+    /* Bytes in a code unit */
+    fn unit(self) -> usize {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
+            _ => 1
+        }
+    }
+
+    fn is_newline(self, unit: &[u8]) -> bool {
+        match self {
+            Encoding::Utf16Le => unit == [b'\n', 0],
+            Encoding::Utf16Be => unit == [0, b'\n'],
+            _ => unit == [b'\n']
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units = bytes.chunks(2).map(|u| match self {
+                    Encoding::Utf16Le => u16::from_le_bytes([u[0], u[1]]),
+                    _ => u16::from_be_bytes([u[0], u[1]])
+                });
+                char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+            }
+        }
+    }
+}
+
+/// Guess the encoding of text from its first bytes, returning it with the
+/// length of the byte order mark to skip
+pub fn detect_encoding(head: &[u8]) -> (Encoding, usize) {
+    if head.starts_with(b"\xef\xbb\xbf") {
+        return (Encoding::Utf8, 3);
+    }
+    if head.starts_with(b"\xff\xfe") {
+        return (Encoding::Utf16Le, 2);
+    }
+    if head.starts_with(b"\xfe\xff") {
+        return (Encoding::Utf16Be, 2);
+    }
+    /* ASCII text in UTF-16 has every other byte zero */
+    let zeroes = |parity: usize| head.iter().skip(parity).step_by(2).filter(|b| **b == 0).count();
+    let (even, odd) = (zeroes(0), zeroes(1));
+    let units = head.len() / 2;
+    if units > 0 && odd * 2 > units && even * 8 < units {
+        return (Encoding::Utf16Le, 0);
+    }
+    if units > 0 && even * 2 > units && odd * 8 < units {
+        return (Encoding::Utf16Be, 0);
+    }
+    match from_utf8(head) {
+        /* The head may end in the middle of a character */
+        Err(ref e) if e.error_len().is_some() => (Encoding::Latin1, 0),
+        _ => (Encoding::Utf8, 0)
+    }
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LineOptions {
+    /// Detected from the first bytes when not given
+    pub encoding:        Option<Encoding>,
+    /// Longer lines are cut to this many bytes, the rest skipped
+    pub max_line_length: usize,
+    /// Stop after reading this many bytes
+    pub max_bytes:       Option<u64>
+}
+
+impl Default for LineOptions {
+    fn default() -> LineOptions {
+        LineOptions {
+            encoding:        None,
+            max_line_length: 64 << 10,
+            max_bytes:       None
+        }
+    }
+}
+
+/// Lines of text read from a source, without their line terminators. Only
+/// the current line is held in memory.
+pub struct Lines<R> {
+    reader:          BufReader<R>,
+    encoding:        Option<Encoding>,
+    max_line_length: usize,
+    remaining:       u64,
+    done:            bool
+}
+
+impl<R: Read> Lines<R> {
+    pub fn new(reader: R, options: &LineOptions) -> Lines<R> {
+        Lines {
+            reader:          BufReader::new(reader),
+            encoding:        options.encoding,
+            max_line_length: options.max_line_length,
+            remaining:       options.max_bytes.unwrap_or(u64::MAX),
+            done:            false
+        }
+    }
+
+    /// Encoding of the text, detecting it if needed
+    pub fn encoding(&mut self) -> io::Result<Encoding> {
+        if let Some(e) = self.encoding {
+            return Ok(e);
+        }
+        let (encoding, bom) = detect_encoding(self.reader.fill_buf()?);
+        self.reader.consume(bom);
+        self.remaining = self.remaining.saturating_sub(bom as u64);
+        self.encoding = Some(encoding);
+        Ok(encoding)
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let encoding = self.encoding()?;
+        let unit_len = encoding.unit();
+        let mut line = Vec::new();
+        let mut unit = Vec::with_capacity(unit_len);
+        let mut read = false;
+        loop {
+            let (used, ended) = {
+                let buf = match self.reader.fill_buf() {
+                    Ok(buf) => buf,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e)
+                };
+                let buf = &buf[..cmp::min(buf.len() as u64, self.remaining) as usize];
+                if buf.is_empty() {
+                    break;
+                }
+                let mut used = 0;
+                let mut ended = false;
+                for &b in buf {
+                    used += 1;
+                    unit.push(b);
+                    if unit.len() < unit_len {
+                        continue;
+                    }
+                    if encoding.is_newline(&unit) {
+                        ended = true;
+                        break;
+                    }
+                    if line.len() + unit_len <= self.max_line_length {
+                        line.extend_from_slice(&unit);
+                    }
+                    unit.clear();
+                }
+                (used, ended)
+            };
+            read = true;
+            self.reader.consume(used);
+            self.remaining -= used as u64;
+            if ended {
+                break;
+            }
+        }
+        if !read {
+            return Ok(None);
+        }
+        let mut text = encoding.decode(&line);
+        if text.ends_with('\r') {
+            text.pop();
+        }
+        Ok(Some(text))
+    }
+}
+
+impl<R: Read> Iterator for Lines<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        if self.done {
+            return None;
+        }
+        match self.read_line() {
+            Ok(Some(line)) => Some(Ok(line)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl Archive {
+    /// Lines of the contents of an entry, read in place
+    pub fn lines<'a>(&'a self, entry: &EntryMetadata, options: &LineOptions) -> Lines<&'a [u8]> {
+        Lines::new(self.contents(entry), options)
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::{Builder, Header};
+
+    fn lines(data: &[u8], options: &LineOptions) -> Vec<String> {
+        Lines::new(data, options).collect::<io::Result<Vec<_>>>().unwrap()
+    }
+
+    #[test]
+    fn encodings_test() {
+        assert_eq!(detect_encoding(b"plain"), (Encoding::Utf8, 0));
+        assert_eq!(detect_encoding(b"caf\xc3"), (Encoding::Utf8, 0));
+        assert_eq!(detect_encoding(b"caf\xe9 au lait"), (Encoding::Latin1, 0));
+        assert_eq!(detect_encoding(b"\xff\xfea\x00"), (Encoding::Utf16Le, 2));
+        assert_eq!(detect_encoding(b"\x00a\x00b\x00\n"), (Encoding::Utf16Be, 0));
+
+        let options = LineOptions::default();
+        assert_eq!(lines(b"\xef\xbb\xbfone\r\ntwo\n\nlast", &options), vec!["one", "two", "", "last"]);
+        assert_eq!(lines(b"one\n", &options), vec!["one"]);
+        assert!(lines(b"", &options).is_empty());
+        let utf16 = "h\u{e9}\nw\u{1f980}\n".encode_utf16().flat_map(|u| u.to_le_bytes().to_vec()).collect::<Vec<_>>();
+        let mut data = b"\xff\xfe".to_vec();
+        data.extend(utf16);
+        assert_eq!(lines(&data, &options), vec!["h\u{e9}", "w\u{1f980}"]);
+        assert_eq!(lines(b"caf\xe9\n", &options), vec!["caf\u{e9}"]);
+    }
+
+    #[test]
+    fn caps_test() {
+        let options = LineOptions { max_line_length: 4, max_bytes: Some(14), ..LineOptions::default() };
+        assert_eq!(lines(b"abcdefgh\nij\nklmnop\n", &options), vec!["abcd", "ij", "kl"]);
+
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("log"), b"first\nsecond\n").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        let entry = archive.get("log").unwrap();
+        let read = archive.lines(entry, &LineOptions::default()).collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(read, vec!["first", "second"]);
+    }
+}
@@ -0,0 +1,145 @@
+use std::io::{self, Write};
+use std::str::from_utf8;
+
+use archive::{Archive, EntryMetadata};
+use builder::type_flag_to_byte;
+use parser::{char_to_type_flag, TypeFlag};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * NUL-delimited listings, like `find -print0`
+ *
+ * Every entry is a record of `FIELDS` fields, each ending with a NUL byte:
+ * type flag, octal mode, uid, gid, size, mtime, path and link target.
+ * Names may hold spaces and newlines, never NUL, so records need no quoting.
+ */
+
+/// Fields in a listing record
+pub const FIELDS: usize = 8;
+
+/// One record of a listing
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ListingEntry {
+    pub typeflag: TypeFlag,
+    pub mode:     u64,
+    pub uid:      u64,
+    pub gid:      u64,
+    pub size:     u64,
+    pub mtime:    u64,
+    pub path:     String,
+    pub linkname: String
+}
+
+impl From<&EntryMetadata> for ListingEntry {
+    fn from(e: &EntryMetadata) -> ListingEntry {
+        ListingEntry {
+            typeflag: e.typeflag,
+            mode:     e.mode,
+            uid:      e.uid,
+            gid:      e.gid,
+            size:     e.size,
+            mtime:    e.mtime,
+            path:     e.path.clone(),
+            linkname: e.linkname.clone()
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Write the record of one entry
+pub fn write_record<W: Write>(out: &mut W, entry: &ListingEntry) -> io::Result<()> {
+    if entry.path.contains('\0') || entry.linkname.contains('\0') {
+        return Err(invalid_data(format!("{:?} cannot be listed with NUL separators", entry.path)));
+    }
+    /* The exact vendor type is not kept, any uppercase letter stands for it */
+    let typeflag = match entry.typeflag {
+        TypeFlag::VendorSpecific => b'A',
+        t => type_flag_to_byte(t)?
+    };
+    out.write_all(&[typeflag, 0])?;
+    write!(out, "{:o}\0{}\0{}\0{}\0{}\0{}\0{}\0", entry.mode, entry.uid, entry.gid, entry.size, entry.mtime,
+           entry.path, entry.linkname)
+}
+
+/// List every entry of an archive
+pub fn write_listing<W: Write>(archive: &Archive, mut out: W) -> io::Result<()> {
+    for e in archive.entries() {
+        write_record(&mut out, &ListingEntry::from(e))?;
+    }
+    Ok(())
+}
+
+/// Read back a listing written by `write_listing`
+pub fn parse_listing(data: &[u8]) -> io::Result<Vec<ListingEntry>> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+    if data[data.len() - 1] != 0 {
+        return Err(invalid_data("listing does not end with a NUL".to_owned()));
+    }
+    let fields = data[..data.len() - 1].split(|b| *b == 0)
+        .map(|f| from_utf8(f).map_err(|_| invalid_data("listing field is not UTF-8".to_owned())))
+        .collect::<io::Result<Vec<_>>>()?;
+    if fields.len() % FIELDS != 0 {
+        return Err(invalid_data(format!("listing has {} fields, not a multiple of {}", fields.len(), FIELDS)));
+    }
+
+    fields.chunks(FIELDS).enumerate().map(|(i, r)| {
+        let number = |s: &str, radix: u32| {
+            u64::from_str_radix(s, radix).map_err(|_| invalid_data(format!("invalid number {:?} in record {}", s, i)))
+        };
+        let mut chars = r[0].chars();
+        let typeflag = match (chars.next(), chars.next()) {
+            (Some(c), None) => char_to_type_flag(c),
+            _ => return Err(invalid_data(format!("invalid type {:?} in record {}", r[0], i)))
+        };
+        Ok(ListingEntry {
+            typeflag: typeflag,
+            mode:     number(r[1], 8)?,
+            uid:      number(r[2], 10)?,
+            gid:      number(r[3], 10)?,
+            size:     number(r[4], 10)?,
+            mtime:    number(r[5], 10)?,
+            path:     r[6].to_owned(),
+            linkname: r[7].to_owned()
+        })
+    }).collect()
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::{Builder, Header};
+
+    #[test]
+    fn listing_round_trip_test() {
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("two words\nand a line"), b"contents").unwrap();
+        let mut link = Header::new("link");
+        link.typeflag = TypeFlag::SymbolicLink;
+        link.linkname = "two words\nand a line".to_owned();
+        b.append(&link, b"").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+
+        let mut out = Vec::new();
+        write_listing(&archive, &mut out).unwrap();
+        let fields = out.split(|b| *b == 0).take(FIELDS).collect::<Vec<_>>();
+        assert_eq!(fields, vec![&b"0"[..], b"644", b"0", b"0", b"8", b"0", b"two words\nand a line", b""]);
+        let entries = parse_listing(&out).unwrap();
+        assert_eq!(entries, archive.entries().iter().map(ListingEntry::from).collect::<Vec<_>>());
+
+        assert!(parse_listing(b"").unwrap().is_empty());
+        assert!(parse_listing(&out[..out.len() - 1]).is_err());
+        assert!(parse_listing(b"0\x00644\x00").is_err());
+        assert!(parse_listing(b"0\x00999\x000\x000\x000\x000\x00a\x00\x00").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use archive::{index_key, Archive, EntryMetadata};
+use builder::Builder;
+use error::Error;
+use parser::TypeFlag;
+use provenance::Provenance;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Merging archives
+ */
+
+/// What becomes of hard links whose target is in another input
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LinkPolicy {
+    /// Keep the link when the output already holds its target's contents
+    /// under the target path, copying the contents otherwise
+    Preserve,
+    /// Always store a copy of the contents
+    Materialize
+}
+
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MergeSummary {
+    pub entries:            u64,
+    pub links_preserved:    u64,
+    /// Hard links written as regular files holding their target's contents
+    pub links_materialized: u64,
+    /// Hard links whose target is in no input, written unchanged
+    pub dangling:           Vec<String>
+}
+
+fn is_regular(e: &EntryMetadata) -> bool {
+    e.typeflag == TypeFlag::NormalFile || e.typeflag == TypeFlag::ContiguousFile
+}
+
+/* The regular entry holding the contents a hard link of input `i` refers
+ * to: in that input when it came before the link, else in the nearest
+ * other input that has it */
+fn resolve<'a>(inputs: &[(&str, &'a Archive)], i: usize, origins: &HashMap<&str, (usize, &'a EntryMetadata)>, linkname: &str)
+    -> Option<(usize, &'a EntryMetadata)>
+{
+    if let Some(&origin) = origins.get(index_key(linkname)) {
+        return Some(origin);
+    }
+    let others = (0..i).rev().chain(i + 1..inputs.len());
+    others.filter_map(|j| inputs[j].1.get(linkname).filter(|t| is_regular(t)).map(|t| (j, t))).next()
+}
+
+/// Write the entries of every input to `builder`, one input after the
+/// other, as if they were extracted in turn. Hard links are resolved to
+/// the contents they stand for, also across inputs, and only kept when
+/// the output holds those contents under the link target when the link
+/// is written. Each entry is traced to the id of its input.
+pub fn merge<W: Write>(inputs: &[(&str, &Archive)], policy: LinkPolicy, builder: &mut Builder<W>) -> Result<MergeSummary, Error> {
+    let mut summary = MergeSummary::default();
+    /* What each output path holds: the input and header of its contents */
+    let mut written: HashMap<String, (usize, u64)> = HashMap::new();
+    for (i, &(source, archive)) in inputs.iter().enumerate() {
+        let mut origins = HashMap::new();
+        for e in archive.entries() {
+            let key = index_key(&e.path);
+            let mut provenance = Provenance::new(source, e);
+            summary.entries += 1;
+            if e.typeflag != TypeFlag::HardLink {
+                builder.append_entry_traced(e, archive.contents(e), provenance)?;
+                if is_regular(e) {
+                    written.insert(key.to_owned(), (i, e.header_offset));
+                    origins.insert(key, (i, e));
+                } else {
+                    written.remove(key);
+                    origins.remove(key);
+                }
+                continue;
+            }
+
+            let (j, target) = match resolve(inputs, i, &origins, &e.linkname) {
+                Some(origin) => origin,
+                None => {
+                    summary.dangling.push(e.path.clone());
+                    builder.append_entry_traced(e, b"", provenance)?;
+                    written.remove(key);
+                    origins.remove(key);
+                    continue;
+                }
+            };
+            let identity = (j, target.header_offset);
+            let linked = written.get(index_key(&e.linkname)) == Some(&identity);
+            if linked && (j == i || policy == LinkPolicy::Preserve) {
+                builder.append_entry_traced(e, b"", provenance)?;
+                summary.links_preserved += 1;
+            } else {
+                let mut copy = e.clone();
+                copy.typeflag = TypeFlag::NormalFile;
+                copy.linkname.clear();
+                provenance.transforms.push("materialize_link".to_owned());
+                builder.append_entry_traced(&copy, inputs[j].1.contents(target), provenance)?;
+                summary.links_materialized += 1;
+            }
+            written.insert(key.to_owned(), identity);
+            origins.insert(key, (j, target));
+        }
+    }
+    Ok(summary)
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::Header;
+
+    fn archive(entries: &[(&str, Option<&str>, &[u8])]) -> Archive {
+        let mut b = Builder::new(Vec::new());
+        for &(path, link, contents) in entries {
+            let mut h = Header::new(path);
+            if let Some(target) = link {
+                h.typeflag = TypeFlag::HardLink;
+                h.linkname = target.to_owned();
+            }
+            b.append(&h, contents).unwrap();
+        }
+        Archive::new(b.finish().unwrap()).unwrap()
+    }
+
+    fn merged(inputs: &[(&str, &Archive)], policy: LinkPolicy) -> (MergeSummary, Archive) {
+        let mut b = Builder::new(Vec::new());
+        let summary = merge(inputs, policy, &mut b).unwrap();
+        (summary, Archive::new(b.finish().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn merge_links_test() {
+        let base = archive(&[("shared", None, b"data"), ("own", Some("shared"), b"")]);
+        let layer = archive(&[("alias", Some("shared"), b""), ("lost", Some("nowhere"), b"")]);
+
+        let (summary, out) = merged(&[("base", &base), ("layer", &layer)], LinkPolicy::Preserve);
+        assert_eq!((summary.entries, summary.links_preserved, summary.links_materialized), (4, 2, 0));
+        assert_eq!(summary.dangling, vec!["lost"]);
+        assert_eq!(out.get("alias").unwrap().typeflag, TypeFlag::HardLink);
+
+        let (summary, out) = merged(&[("base", &base), ("layer", &layer)], LinkPolicy::Materialize);
+        assert_eq!((summary.links_preserved, summary.links_materialized), (1, 1));
+        assert_eq!(out.contents_of("alias"), Some(&b"data"[..]));
+
+        /* A target coming later cannot be linked to */
+        let (summary, out) = merged(&[("layer", &layer), ("base", &base)], LinkPolicy::Preserve);
+        assert_eq!((summary.links_preserved, summary.links_materialized), (1, 1));
+        assert_eq!(out.entries()[0].typeflag, TypeFlag::NormalFile);
+        assert_eq!(out.contents_of("alias"), Some(&b"data"[..]));
+
+        /* The input nearest before the link provides its target */
+        let replaced = archive(&[("shared", None, b"other")]);
+        let (summary, _) = merged(&[("base", &base), ("replaced", &replaced), ("layer", &layer)], LinkPolicy::Preserve);
+        assert_eq!((summary.links_preserved, summary.links_materialized), (2, 0));
+        let (summary, out) = merged(&[("base", &base), ("replaced", &replaced), ("layer", &layer)], LinkPolicy::Materialize);
+        assert_eq!(summary.links_materialized, 1);
+        assert_eq!(out.contents_of("alias"), Some(&b"other"[..]));
+    }
+
+    #[test]
+    fn merge_long_paths_test() {
+        /* Paths no ustar header holds, stored in PAX records */
+        let long = format!("{}/{}", "d".repeat(150), "f".repeat(120));
+        let alias = format!("{}/alias", "a".repeat(200));
+        let plain = archive(&[("target", None, b"data"), ("link", Some("target"), b"")]);
+        let mut target = plain.entries()[0].clone();
+        target.path = long.clone();
+        let mut b = Builder::new(Vec::new());
+        b.append_entry(&target, b"data").unwrap();
+        let layer = Archive::new(b.finish().unwrap()).unwrap();
+        let mut link = plain.entries()[1].clone();
+        link.path = alias.clone();
+        link.linkname = long.clone();
+        let mut b = Builder::new(Vec::new());
+        b.append_entry(&link, b"").unwrap();
+        let links = Archive::new(b.finish().unwrap()).unwrap();
+
+        let (summary, out) = merged(&[("layer", &layer), ("links", &links)], LinkPolicy::Preserve);
+        assert_eq!(summary.links_preserved, 1);
+        assert_eq!(out.contents_of(&long), Some(&b"data"[..]));
+        assert_eq!(out.get(&alias).unwrap().linkname, long);
+
+        /* A materialized link drops the link path record along with the link */
+        let (summary, out) = merged(&[("layer", &layer), ("links", &links)], LinkPolicy::Materialize);
+        assert_eq!(summary.links_materialized, 1);
+        let copy = out.get(&alias).unwrap();
+        assert_eq!((copy.typeflag, &copy.linkname[..]), (TypeFlag::NormalFile, ""));
+        assert_eq!(out.contents_of(&alias), Some(&b"data"[..]));
+    }
+}
@@ -0,0 +1,150 @@
+use archive::EntryMetadata;
+use filter::{EntryFilter, Verdict};
+use parser::TypeFlag;
+use paths::glob_match;
+
+/*
+ * Symbolic mode strings, as printed by `ls -l` and `tar -tv`
+ */
+
+/* Type characters of `tar -tv`, which tells hard links apart */
+const TYPE_CHARS: &[(char, TypeFlag)] = &[
+    ('-', TypeFlag::NormalFile),
+    ('h', TypeFlag::HardLink),
+    ('l', TypeFlag::SymbolicLink),
+    ('c', TypeFlag::CharacterSpecial),
+    ('b', TypeFlag::BlockSpecial),
+    ('d', TypeFlag::Directory),
+    ('p', TypeFlag::FIFO),
+    ('C', TypeFlag::ContiguousFile),
+    ('g', TypeFlag::PaxInterexchangeFormat),
+    ('x', TypeFlag::PaxExtendedAttributes),
+    ('L', TypeFlag::GnuLongName),
+    ('K', TypeFlag::GnuLongLink),
+    ('V', TypeFlag::GnuVolumeHeader),
+    ('?', TypeFlag::VendorSpecific)
+];
+
+/* Setuid, setgid and sticky bits, shown in the execute column of their class */
+const SPECIAL: [(u64, char, char); 3] = [(0o4000, 's', 'S'), (0o2000, 's', 'S'), (0o1000, 't', 'T')];
+
+/// `rwxr-xr-x` for the permission bits of a mode
+pub fn render_permissions(mode: u64) -> String {
+    let mut s = String::with_capacity(9);
+    for (class, &(bit, set, unset)) in SPECIAL.iter().enumerate() {
+        let shift = 6 - 3 * class;
+        s.push(if mode & (0o4 << shift) != 0 { 'r' } else { '-' });
+        s.push(if mode & (0o2 << shift) != 0 { 'w' } else { '-' });
+        let exec = mode & (0o1 << shift) != 0;
+        s.push(match (mode & bit != 0, exec) {
+            (true, true) => set,
+            (true, false) => unset,
+            (false, true) => 'x',
+            (false, false) => '-'
+        });
+    }
+    s
+}
+
+/// `-rwxr-xr-x` for a type and mode
+pub fn render_mode(typeflag: TypeFlag, mode: u64) -> String {
+    let c = TYPE_CHARS.iter().find(|&&(_, t)| t == typeflag).map(|&(c, _)| c).unwrap_or('?');
+    format!("{}{}", c, render_permissions(mode))
+}
+
+/// Permission bits of a string like `rwsr-xr-t`
+pub fn parse_permissions(s: &str) -> Option<u64> {
+    let chars = s.chars().collect::<Vec<_>>();
+    if chars.len() != 9 {
+        return None;
+    }
+    let mut mode = 0;
+    for (class, &(bit, set, unset)) in SPECIAL.iter().enumerate() {
+        let shift = 6 - 3 * class;
+        let c = &chars[class * 3..class * 3 + 3];
+        mode |= match c[0] { 'r' => 0o4 << shift, '-' => 0, _ => return None };
+        mode |= match c[1] { 'w' => 0o2 << shift, '-' => 0, _ => return None };
+        mode |= match c[2] {
+            'x' => 0o1 << shift,
+            '-' => 0,
+            c if c == set => bit | (0o1 << shift),
+            c if c == unset => bit,
+            _ => return None
+        };
+    }
+    Some(mode)
+}
+
+/// Type and mode of a string like `lrwxrwxrwx`
+pub fn parse_mode(s: &str) -> Option<(TypeFlag, u64)> {
+    let mut chars = s.chars();
+    let c = chars.next()?;
+    let typeflag = TYPE_CHARS.iter().find(|&&(t, _)| t == c).map(|&(_, t)| t)?;
+    parse_permissions(chars.as_str()).map(|mode| (typeflag, mode))
+}
+
+/// An entry filter setting the permission bits of the entries matching a
+/// glob pattern, from a string like `rw-r--r--`
+pub struct ModeOverride {
+    pattern: String,
+    mode:    u64
+}
+
+impl ModeOverride {
+    /// `None` if `permissions` is not a valid permission string
+    pub fn new(pattern: &str, permissions: &str) -> Option<ModeOverride> {
+        parse_permissions(permissions).map(|mode| ModeOverride { pattern: pattern.to_owned(), mode: mode })
+    }
+}
+
+impl EntryFilter for ModeOverride {
+    fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+        if glob_match(&self.pattern, &entry.path) {
+            entry.mode = (entry.mode & !0o7777) | self.mode;
+        }
+        Verdict::Keep
+    }
+
+    fn name(&self) -> &str {
+        "mode_override"
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archive::Archive;
+    use filter::filter_listing;
+
+    #[test]
+    fn mode_strings_test() {
+        assert_eq!(render_mode(TypeFlag::NormalFile, 0o755), "-rwxr-xr-x");
+        assert_eq!(render_mode(TypeFlag::SymbolicLink, 0o777), "lrwxrwxrwx");
+        assert_eq!(render_mode(TypeFlag::Directory, 0o1777), "drwxrwxrwt");
+        assert_eq!(render_mode(TypeFlag::NormalFile, 0o6644), "-rwSr-Sr--");
+        assert_eq!(render_mode(TypeFlag::HardLink, 0o4711), "hrws--x--x");
+
+        for mode in 0..0o10000 {
+            assert_eq!(parse_permissions(&render_permissions(mode)), Some(mode));
+        }
+        assert_eq!(parse_mode("lrwxrwxrwx"), Some((TypeFlag::SymbolicLink, 0o777)));
+        assert_eq!(parse_mode("crw-rw----"), Some((TypeFlag::CharacterSpecial, 0o660)));
+        assert_eq!(parse_mode("-rwxr-xr-"), None);
+        assert_eq!(parse_mode("zrwxr-xr-x"), None);
+        assert_eq!(parse_permissions("rwxr-xr-s"), None);
+    }
+
+    #[test]
+    fn mode_override_test() {
+        let archive = Archive::new(include_bytes!("../examples/simple/test.tar").to_vec()).unwrap();
+        assert!(ModeOverride::new("*", "rwxrwxrwz").is_none());
+        let mut filter = ModeOverride::new("test/b*", "rw-------").unwrap();
+        let listed = filter_listing(&archive, &mut filter).unwrap();
+        let modes = listed.iter().map(|e| render_mode(e.typeflag, e.mode)).collect::<Vec<_>>();
+        assert_eq!(modes, vec!["drwxr-xr-x", "-rw-------", "-rw-r--r--", "-rw-------"]);
+    }
+}
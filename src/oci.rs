@@ -0,0 +1,112 @@
+use std::io::{self, Read};
+
+use archive::Archive;
+use digest::{sha256, Digest, DigestWriter};
+use error::Error;
+
+/*
+ * OCI layer digests
+ *
+ * An image config lists, in `rootfs.diff_ids`, the SHA-256 of each layer's
+ * uncompressed tar stream as `sha256:<hex>`.
+ */
+
+/// Format a digest the way `diff_ids` entries are written
+pub fn diff_id(digest: &Digest) -> String {
+    format!("sha256:{}", digest)
+}
+
+/// Parse a `sha256:<hex>` diff_id
+pub fn parse_diff_id(s: &str) -> Option<Digest> {
+    if !s.starts_with("sha256:") {
+        return None;
+    }
+    Digest::from_hex(&s["sha256:".len()..])
+}
+
+/// The diff_id of a layer held in memory
+pub fn layer_diff_id(archive: &Archive) -> String {
+    diff_id(&sha256(archive.as_bytes()))
+}
+
+/// Check a layer against the diff_id its image config lists for it
+pub fn verify_layer(archive: &Archive, expected: &str) -> Result<(), Error> {
+    check_diff_id(expected, &sha256(archive.as_bytes()))
+}
+
+pub(crate) fn check_diff_id(expected: &str, actual: &Digest) -> Result<(), Error> {
+    match parse_diff_id(expected) {
+        Some(ref d) if d == actual => Ok(()),
+        _ => Err(Error::DiffIdMismatch { expected: expected.to_owned(), actual: diff_id(actual) })
+    }
+}
+
+/// Computes the diff_id of an uncompressed layer as it is read, so one pass
+/// both parses and validates it. For compressed layers, see
+/// `ExtractPolicy::diff_id` which hashes the decompressed stream.
+pub struct DiffIdReader<R: Read> {
+    inner:  R,
+    hasher: DigestWriter
+}
+
+impl<R: Read> DiffIdReader<R> {
+    pub fn new(inner: R) -> DiffIdReader<R> {
+        DiffIdReader {
+            inner:  inner,
+            hasher: DigestWriter::new()
+        }
+    }
+
+    /// diff_id of everything read so far
+    pub fn diff_id(&self) -> String {
+        diff_id(&self.hasher.clone().finish())
+    }
+
+    /// Check what has been read against the expected diff_id
+    pub fn verify(&self, expected: &str) -> Result<(), Error> {
+        check_diff_id(expected, &self.hasher.clone().finish())
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for DiffIdReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tail::Tailer;
+
+    const TEST_TAR_DIFF_ID: &str = "sha256:90d1ea2f67a023d784d2aa6a06ffb2ccefb0ab8d8fb9a4ef09f040289f5ce2cc";
+
+    #[test]
+    fn diff_id_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let archive = Archive::new(tar.to_vec()).unwrap();
+        assert_eq!(layer_diff_id(&archive), TEST_TAR_DIFF_ID);
+        assert!(verify_layer(&archive, TEST_TAR_DIFF_ID).is_ok());
+        match verify_layer(&archive, "sha256:00") {
+            Err(Error::DiffIdMismatch { ref actual, .. }) if actual == TEST_TAR_DIFF_ID => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+
+        let mut reader = DiffIdReader::new(&tar[..]);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(Tailer::new(Cursor::new(data)).poll().unwrap().len(), 4);
+        assert!(reader.verify(TEST_TAR_DIFF_ID).is_ok());
+    }
+}
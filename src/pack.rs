@@ -0,0 +1,328 @@
+use std::fs::{self, File, FileType, Metadata};
+use std::io::{self, Write};
+use std::path::Path;
+#[cfg(not(unix))]
+use std::time::UNIX_EPOCH;
+
+use builder::{Builder, Header};
+use parser::TypeFlag;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Packing files from disk
+ */
+
+/// What to do with a kind of file met while packing
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SpecialAction {
+    Store,
+    /// Leave the file out, listing it in `PackSummary::skipped`
+    Skip,
+    /// Stop with an `Unsupported` I/O error
+    Fail
+}
+
+/// How special files are packed. Symbolic links are always stored as
+/// links, never followed.
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PackPolicy {
+    pub fifos:       SpecialAction,
+    /// Character and block devices, stored with their device numbers
+    pub devices:     SpecialAction,
+    /// Files no ustar header can represent: sockets, and devices whose
+    /// numbers do not fit the header. `Store` is taken as `Skip`.
+    pub unsupported: SpecialAction,
+    /// Levels below the packed directory to descend, deeper files being
+    /// left out and listed in `PackSummary::too_deep`
+    pub max_depth:   Option<usize>
+}
+
+impl Default for PackPolicy {
+    fn default() -> PackPolicy {
+        PackPolicy {
+            fifos:       SpecialAction::Store,
+            devices:     SpecialAction::Store,
+            unsupported: SpecialAction::Skip,
+            max_depth:   None
+        }
+    }
+}
+
+/// What `Builder::append_dir_all` packed
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PackSummary {
+    pub entries: u64,
+    /// Archive names of the files left out by the policy
+    pub skipped:  Vec<String>,
+    /// Archive names of the files below `PackPolicy::max_depth`
+    pub too_deep: Vec<String>
+}
+
+/* Largest device number the 8 byte ustar fields hold */
+pub(crate) const MAX_DEVICE: u64 = 0o7777777;
+
+enum Kind {
+    File,
+    Directory,
+    Symlink,
+    Fifo,
+    Device(TypeFlag),
+    /// Sockets, doors and anything else without a tar type
+    Other
+}
+
+#[cfg(unix)]
+fn kind(t: FileType) -> Kind {
+    use std::os::unix::fs::FileTypeExt;
+
+    if t.is_file() {
+        Kind::File
+    } else if t.is_dir() {
+        Kind::Directory
+    } else if t.is_symlink() {
+        Kind::Symlink
+    } else if t.is_fifo() {
+        Kind::Fifo
+    } else if t.is_char_device() {
+        Kind::Device(TypeFlag::CharacterSpecial)
+    } else if t.is_block_device() {
+        Kind::Device(TypeFlag::BlockSpecial)
+    } else {
+        Kind::Other
+    }
+}
+
+#[cfg(not(unix))]
+fn kind(t: FileType) -> Kind {
+    if t.is_file() {
+        Kind::File
+    } else if t.is_dir() {
+        Kind::Directory
+    } else if t.is_symlink() {
+        Kind::Symlink
+    } else {
+        Kind::Other
+    }
+}
+
+/* Split a device number the way glibc does */
+#[cfg(target_os = "linux")]
+pub(crate) fn device_numbers(dev: u64) -> (u64, u64) {
+    (((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff), (dev & 0xff) | ((dev >> 12) & !0xff))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub(crate) fn device_numbers(dev: u64) -> (u64, u64) {
+    ((dev >> 24) & 0xff, dev & 0xffffff)
+}
+
+#[cfg(unix)]
+pub(crate) fn header_for(name: &str, m: &Metadata) -> Header {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut header = Header::new(name);
+    header.mode = m.mode() as u64 & 0o7777;
+    header.uid = m.uid() as u64;
+    header.gid = m.gid() as u64;
+    header.mtime = m.mtime().max(0) as u64;
+    header
+}
+
+#[cfg(unix)]
+fn device(m: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+
+    device_numbers(m.rdev())
+}
+
+#[cfg(not(unix))]
+fn header_for(name: &str, m: &Metadata) -> Header {
+    let mut header = Header::new(name);
+    if m.is_dir() {
+        header.mode = 0o755;
+    }
+    header.mtime = m.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or(0);
+    header
+}
+
+#[cfg(not(unix))]
+fn device(_: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+fn unsupported(name: &str, what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("{}: {} cannot be archived", name, what))
+}
+
+/* Whether to store a special file, or the error refusing it */
+pub(crate) fn decide(action: SpecialAction, name: &str, what: &str) -> io::Result<bool> {
+    match action {
+        SpecialAction::Store => Ok(true),
+        SpecialAction::Skip => Ok(false),
+        SpecialAction::Fail => Err(unsupported(name, what))
+    }
+}
+
+pub(crate) fn decide_unsupported(policy: &PackPolicy, name: &str, what: &str) -> io::Result<bool> {
+    match policy.unsupported {
+        SpecialAction::Fail => Err(unsupported(name, what)),
+        _ => Ok(false)
+    }
+}
+
+pub(crate) fn utf8_name(name: &std::ffi::OsStr) -> io::Result<&str> {
+    name.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{:?} is not valid UTF-8", name)))
+}
+
+impl<W: Write> Builder<W> {
+    /// Append the file at `path` under `name`, with its metadata. Links are
+    /// stored, not followed, and directories are appended without their
+    /// contents. Returns false when the policy left the file out.
+    pub fn append_path<P: AsRef<Path>>(&mut self, name: &str, path: P, policy: &PackPolicy) -> io::Result<bool> {
+        let path = path.as_ref();
+        let metadata = fs::symlink_metadata(path)?;
+        let mut header = header_for(name, &metadata);
+        match kind(metadata.file_type()) {
+            Kind::File => {
+                self.append_from(&header, File::open(path)?)?;
+                return Ok(true);
+            },
+            Kind::Directory => {
+                header.typeflag = TypeFlag::Directory;
+                if !header.path.ends_with('/') {
+                    header.path.push('/');
+                }
+            },
+            Kind::Symlink => {
+                header.typeflag = TypeFlag::SymbolicLink;
+                header.mode = 0o777;
+                header.linkname = utf8_name(fs::read_link(path)?.as_os_str())?.to_owned();
+            },
+            Kind::Fifo => {
+                if !decide(policy.fifos, name, "FIFO")? {
+                    return Ok(false);
+                }
+                header.typeflag = TypeFlag::FIFO;
+            },
+            Kind::Device(typeflag) => {
+                let (major, minor) = device(&metadata);
+                if major > MAX_DEVICE || minor > MAX_DEVICE {
+                    return decide_unsupported(policy, name, "device with large numbers");
+                }
+                if !decide(policy.devices, name, "device")? {
+                    return Ok(false);
+                }
+                header.typeflag = typeflag;
+                header.devmajor = major;
+                header.devminor = minor;
+            },
+            Kind::Other => return decide_unsupported(policy, name, "socket")
+        }
+        self.append(&header, b"")?;
+        Ok(true)
+    }
+
+    /// Append the directory at `path` under `name` and everything below
+    /// it, each directory followed by its children in name order. The walk
+    /// keeps its own stack, so any depth the filesystem allows is fine.
+    pub fn append_dir_all<P: AsRef<Path>>(&mut self, name: &str, path: P, policy: &PackPolicy) -> io::Result<PackSummary> {
+        let mut summary = PackSummary::default();
+        /* Pending files, the next one last, with their depth */
+        let mut stack = vec![(name.trim_end_matches('/').to_owned(), path.as_ref().to_path_buf(), 0)];
+        while let Some((name, path, depth)) = stack.pop() {
+            if policy.max_depth.map(|limit| depth > limit).unwrap_or(false) {
+                summary.too_deep.push(name);
+                continue;
+            }
+            if !self.append_path(&name, &path, policy)? {
+                summary.skipped.push(name);
+                continue;
+            }
+            summary.entries += 1;
+            if !fs::symlink_metadata(&path)?.is_dir() {
+                continue;
+            }
+            let mut children = fs::read_dir(&path)?.collect::<io::Result<Vec<_>>>()?;
+            children.sort_by_key(|c| c.file_name());
+            for child in children.into_iter().rev() {
+                let file_name = child.file_name();
+                let child_name = match &name[..] {
+                    "" => utf8_name(&file_name)?.to_owned(),
+                    _ => format!("{}/{}", name, utf8_name(&file_name)?)
+                };
+                stack.push((child_name, child.path(), depth + 1));
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixListener;
+    use std::process::{self, Command};
+    use archive::Archive;
+
+    #[test]
+    fn append_dir_all_test() {
+        let dir = env::temp_dir().join(format!("tar-pack-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/file"), b"contents").unwrap();
+        symlink("sub/file", dir.join("link")).unwrap();
+        let _listener = UnixListener::bind(dir.join("socket")).unwrap();
+        let fifo = Command::new("mkfifo").arg(dir.join("fifo")).status().map(|s| s.success()).unwrap_or(false);
+
+        let mut b = Builder::new(Vec::new());
+        let summary = b.append_dir_all("root", &dir, &PackPolicy::default()).unwrap();
+        assert_eq!(summary.skipped, vec!["root/socket"]);
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        let entries = archive.entries().iter().map(|e| (&e.path[..], e.typeflag)).collect::<Vec<_>>();
+        let mut expected = vec![("root/", TypeFlag::Directory)];
+        if fifo {
+            expected.push(("root/fifo", TypeFlag::FIFO));
+        }
+        expected.extend(vec![("root/link", TypeFlag::SymbolicLink), ("root/sub/", TypeFlag::Directory), ("root/sub/file", TypeFlag::NormalFile)]);
+        assert_eq!(entries, expected);
+        assert_eq!(archive.contents_of("root/sub/file"), Some(&b"contents"[..]));
+        assert_eq!(archive.entries()[entries.len() - 3].linkname, "sub/file");
+
+        let policy = PackPolicy { max_depth: Some(1), ..PackPolicy::default() };
+        let summary = Builder::new(Vec::new()).append_dir_all("root", &dir, &policy).unwrap();
+        assert_eq!(summary.too_deep, vec!["root/sub/file"]);
+
+        let policy = PackPolicy { unsupported: SpecialAction::Fail, ..PackPolicy::default() };
+        match Builder::new(Vec::new()).append_dir_all("root", &dir, &policy) {
+            Err(ref e) if e.kind() == io::ErrorKind::Unsupported => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        if fifo {
+            let policy = PackPolicy { fifos: SpecialAction::Skip, ..PackPolicy::default() };
+            let summary = Builder::new(Vec::new()).append_dir_all("root", &dir, &policy).unwrap();
+            assert_eq!(summary.skipped, vec!["root/fifo", "root/socket"]);
+        }
+
+        /* Device numbers come from the node */
+        #[cfg(target_os = "linux")]
+        {
+            let mut b = Builder::new(Vec::new());
+            assert!(b.append_path("null", "/dev/null", &PackPolicy::default()).unwrap());
+            let archive = Archive::new(b.finish().unwrap()).unwrap();
+            let null = &archive.entries()[0];
+            assert_eq!((null.typeflag, null.devmajor, null.devminor), (TypeFlag::CharacterSpecial, 1, 3));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
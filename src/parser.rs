@@ -1,18 +1,24 @@
-use std::str::from_utf8;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::str::{from_utf8, Utf8Error};
 use std::result::Result;
 use nom::*;
 
+use field::read_number;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 /*
  * Core structs
  */
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub struct TarEntry<'a> {
     pub header:   PosixHeader<'a>,
     pub contents: &'a [u8]
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
 pub struct PosixHeader<'a> {
     pub name:     &'a str,
     pub mode:     &'a str,
@@ -27,7 +33,9 @@ pub struct PosixHeader<'a> {
 }
 
 /* TODO: support vendor specific */
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum TypeFlag {
     NormalFile,
     HardLink,
@@ -35,20 +43,25 @@ pub enum TypeFlag {
     CharacterSpecial,
     BlockSpecial,
     Directory,
+    /* snake_case would make this f_i_f_o */
+    #[cfg_attr(feature = "serde", serde(rename = "fifo"))]
     FIFO,
     ContiguousFile,
     PaxInterexchangeFormat,
     PaxExtendedAttributes,
+    GnuLongName,
+    GnuLongLink,
+    GnuVolumeHeader,
     VendorSpecific
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub enum ExtraHeader<'a> {
     UStar(UStarHeader<'a>),
     Padding
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub struct UStarHeader<'a> {
     pub magic:    &'a str,
     pub version:  &'a str,
@@ -59,18 +72,18 @@ pub struct UStarHeader<'a> {
     pub extra:    UStarExtraHeader<'a>
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub enum UStarExtraHeader<'a> {
     PosixUStar(PosixUStarHeader<'a>),
     Pax(PaxHeader<'a>)
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub struct PosixUStarHeader<'a> {
     pub prefix: &'a str
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub struct PaxHeader<'a> {
     pub atime:      u64,
     pub ctime:      u64,
@@ -81,29 +94,71 @@ pub struct PaxHeader<'a> {
     pub realsize:   u64,
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub struct Sparse {
     pub offset:   u64,
     pub numbytes: u64
 }
 
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub struct Padding;
 
+/*
+ * Paths and ordering
+ */
+
+impl<'a> PosixHeader<'a> {
+    /// Full path of the entry, joining the ustar prefix (if any) and the name
+    pub fn path(&self) -> Cow<'a, str> {
+        match self.ustar {
+            ExtraHeader::UStar(UStarHeader { extra: UStarExtraHeader::PosixUStar(ref p), .. }) if !p.prefix.is_empty() => {
+                Cow::Owned(format!("{}/{}", p.prefix, self.name))
+            },
+            _ => Cow::Borrowed(self.name)
+        }
+    }
+}
+
+/* Headers sort by path first, the remaining fields only break ties to stay consistent with Eq */
+impl<'a> Ord for PosixHeader<'a> {
+    fn cmp(&self, other: &PosixHeader<'a>) -> Ordering {
+        self.path().cmp(&other.path()).then_with(|| {
+            (self.name, self.mode, self.uid, self.gid, self.size, self.mtime, self.chksum, self.typeflag, self.linkname, &self.ustar)
+                .cmp(&(other.name, other.mode, other.uid, other.gid, other.size, other.mtime, other.chksum, other.typeflag, other.linkname, &other.ustar))
+        })
+    }
+}
+
+impl<'a> PartialOrd for PosixHeader<'a> {
+    fn partial_cmp(&self, other: &PosixHeader<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Ord for TarEntry<'a> {
+    fn cmp(&self, other: &TarEntry<'a>) -> Ordering {
+        self.header.cmp(&other.header).then_with(|| self.contents.cmp(other.contents))
+    }
+}
+
+impl<'a> PartialOrd for TarEntry<'a> {
+    fn partial_cmp(&self, other: &TarEntry<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 /*
  * Useful macros
  */
 
+/* Fields are NUL terminated unless they use their whole size */
+fn nul_terminated(field: &[u8]) -> Result<&str, Utf8Error> {
+    from_utf8(&field[..field.iter().position(|b| *b == 0).unwrap_or(field.len())])
+}
+
 macro_rules! take_str_eat_garbage (
     ( $i:expr, $size:expr ) => (
-        chain!($i,
-            s: map_res!(take_until!("\0"), from_utf8)                  ~
-            length: expr_opt!({($size as usize).checked_sub(s.len())}) ~
-            take!(length),
-            ||{
-                s
-            }
-        )
+        map_res!($i, take!($size), nul_terminated)
     );
 );
 
@@ -134,7 +189,7 @@ macro_rules! take_until_expr_with_limit_consume(
             }
             begin += remaining - i.len();
             remaining = i.len();
-            cnt = cnt + 1;
+            cnt += 1;
             if cnt == $limit {
               break
             }
@@ -158,7 +213,7 @@ macro_rules! take_until_expr_with_limit_consume(
     }
   );
   ($i:expr, $f:expr, $stop: expr, $limit: expr) => (
-    take_until_expr_with_limit_consume!($i, call!($f), $stop, $limit);
+    take_until_expr_with_limit_consume!($i, call!($f), $stop, $limit)
   );
 );
 
@@ -170,7 +225,7 @@ pub fn octal_to_u64(s: &str) -> Result<u64, &'static str> {
     let mut u = 0;
 
     for c in s.chars() {
-        if c < '0' || c > '7' {
+        if !('0'..='7').contains(&c) {
             return Err("invalid octal string received");
         }
         u *= 8;
@@ -180,18 +235,19 @@ pub fn octal_to_u64(s: &str) -> Result<u64, &'static str> {
     Ok(u)
 }
 
-fn parse_octal(i: &[u8], n: usize) -> IResult<&[u8], u64> {
-    map_res!(i, take_str_eat_garbage!(n), octal_to_u64)
+/* Octal, or GNU base-256 for values too large for it */
+fn parse_number(i: &[u8], n: usize) -> IResult<&[u8], u64> {
+    map_opt!(i, take!(n), read_number)
 }
 
-named!(parse_octal8<&[u8], u64>, apply!(parse_octal, 8));
-named!(parse_octal12<&[u8], u64>, apply!(parse_octal, 12));
+named!(parse_octal8<&[u8], u64>, apply!(parse_number, 8));
+named!(parse_octal12<&[u8], u64>, apply!(parse_number, 12));
 
 /*
  * TypeFlag parsing
  */
 
-fn char_to_type_flag(c: char) -> TypeFlag {
+pub(crate) fn char_to_type_flag(c: char) -> TypeFlag {
     match c {
         '0' | '\0' => TypeFlag::NormalFile,
         '1' => TypeFlag::HardLink,
@@ -203,7 +259,10 @@ fn char_to_type_flag(c: char) -> TypeFlag {
         '7' => TypeFlag::ContiguousFile,
         'g' => TypeFlag::PaxInterexchangeFormat,
         'x' => TypeFlag::PaxExtendedAttributes,
-        'A' ... 'Z' => TypeFlag::VendorSpecific,
+        'L' => TypeFlag::GnuLongName,
+        'K' => TypeFlag::GnuLongLink,
+        'V' => TypeFlag::GnuVolumeHeader,
+        'A'..='Z' => TypeFlag::VendorSpecific,
         _ => TypeFlag::NormalFile
     }
 }
@@ -235,10 +294,8 @@ fn parse_sparses_with_limit(i: &[u8], limit: usize) -> IResult<&[u8], Vec<Sparse
     take_until_expr_with_limit_consume!(i, parse_one_sparse, |s: &Sparse| s.offset == 0 && s.numbytes == 0, limit)
 }
 
-fn add_to_vec<'a, 'b>(sparses: &'a mut Vec<Sparse>, extra: &'b mut Vec<Sparse>) -> &'a mut Vec<Sparse> {
-    while sparses.len() != 0 {
-      extra.pop().map(|s| sparses.push(s));
-    }
+fn add_to_vec<'a>(sparses: &'a mut Vec<Sparse>, extra: &mut Vec<Sparse>) -> &'a mut Vec<Sparse> {
+    sparses.append(extra);
     sparses
 }
 
@@ -258,7 +315,7 @@ fn parse_extra_sparses<'a, 'b>(i: &'a [u8], isextended: bool, sparses: &'b mut V
     }
 }
 
-fn parse_pax_extra_sparses<'a, 'b>(i: &'a [u8], h: &'b mut PaxHeader) -> IResult<&'a [u8], &'b mut Vec<Sparse>> {
+fn parse_pax_extra_sparses<'a, 'b>(i: &'a [u8], h: &'b mut PaxHeader<'_>) -> IResult<&'a [u8], &'b mut Vec<Sparse>> {
     parse_extra_sparses(i, h.isextended, &mut h.sparses)
 }
 
@@ -276,7 +333,7 @@ named!(parse_bool<&[u8], bool>, map!(take!(1), to_bool));
  * UStar PAX extended parsing
  */
 
-fn parse_ustar00_extra_pax(i: &[u8]) -> IResult<&[u8], PaxHeader> {
+fn parse_ustar00_extra_pax(i: &[u8]) -> IResult<&[u8], PaxHeader<'_>> {
     chain!(i,
         atime:       parse_octal12                       ~
         ctime:       parse_octal12                       ~
@@ -305,7 +362,7 @@ fn parse_ustar00_extra_pax(i: &[u8]) -> IResult<&[u8], PaxHeader> {
  * UStar Posix parsing
  */
 
-fn parse_ustar00_extra_posix(i: &[u8]) -> IResult<&[u8], UStarExtraHeader> {
+fn parse_ustar00_extra_posix(i: &[u8]) -> IResult<&[u8], UStarExtraHeader<'_>> {
     chain!(i,
         prefix: parse_str155 ~
         take!(12),
@@ -317,7 +374,7 @@ fn parse_ustar00_extra_posix(i: &[u8]) -> IResult<&[u8], UStarExtraHeader> {
     )
 }
 
-fn parse_ustar00_extra<'a, 'b>(i: &'a [u8], flag: &'b TypeFlag) -> IResult<&'a [u8], UStarExtraHeader<'a>> {
+fn parse_ustar00_extra<'a>(i: &'a [u8], flag: &TypeFlag) -> IResult<&'a [u8], UStarExtraHeader<'a>> {
     match *flag {
         TypeFlag::PaxInterexchangeFormat => {
             chain!(i,
@@ -332,7 +389,7 @@ fn parse_ustar00_extra<'a, 'b>(i: &'a [u8], flag: &'b TypeFlag) -> IResult<&'a [
     }
 }
 
-fn parse_ustar00<'a, 'b>(i: &'a [u8], flag: &'b TypeFlag) -> IResult<&'a [u8], ExtraHeader<'a>> {
+fn parse_ustar00<'a>(i: &'a [u8], flag: &TypeFlag) -> IResult<&'a [u8], ExtraHeader<'a>> {
     chain!(i,
         tag!("00")             ~
         uname:    parse_str32  ~
@@ -354,7 +411,7 @@ fn parse_ustar00<'a, 'b>(i: &'a [u8], flag: &'b TypeFlag) -> IResult<&'a [u8], E
     )
 }
 
-fn parse_ustar<'a, 'b>(i: &'a [u8], flag: &'b TypeFlag) -> IResult<&'a [u8], ExtraHeader<'a>> {
+fn parse_ustar<'a>(i: &'a [u8], flag: &TypeFlag) -> IResult<&'a [u8], ExtraHeader<'a>> {
     chain!(i,
         tag!("ustar\0") ~
         ustar: apply!(parse_ustar00, flag),
@@ -368,7 +425,7 @@ fn parse_ustar<'a, 'b>(i: &'a [u8], flag: &'b TypeFlag) -> IResult<&'a [u8], Ext
  * Posix tar archive header parsing
  */
 
-fn parse_posix(i: &[u8]) -> IResult<&[u8], ExtraHeader> {
+fn parse_posix(i: &[u8]) -> IResult<&[u8], ExtraHeader<'_>> {
     chain!(i,
         take!(255), /* padding to 512 */
         ||{
@@ -377,7 +434,7 @@ fn parse_posix(i: &[u8]) -> IResult<&[u8], ExtraHeader> {
     )
 }
 
-fn parse_header(i: &[u8]) -> IResult<&[u8], PosixHeader> {
+pub fn parse_header(i: &[u8]) -> IResult<&[u8], PosixHeader<'_>> {
     chain!(i,
         name:     parse_str100    ~
         mode:     parse_str8      ~
@@ -410,15 +467,18 @@ fn parse_header(i: &[u8]) -> IResult<&[u8], PosixHeader> {
  * Contents parsing
  */
 
-fn parse_contents(i: &[u8], size: u64) -> IResult<&[u8], &[u8]> {
-    let trailing = size % 512;
-    let padding = match trailing {
+/// Number of zero bytes rounding contents of this size up to a block
+pub fn padding(size: u64) -> u64 {
+    match size % 512 {
         0 => 0,
         t => 512 - t
-    };
+    }
+}
+
+fn parse_contents(i: &[u8], size: u64) -> IResult<&[u8], &[u8]> {
     chain!(i,
         contents: take!(size as usize) ~
-        take!(padding as usize),
+        take!(padding(size) as usize),
         ||{
             contents
         }
@@ -429,7 +489,7 @@ fn parse_contents(i: &[u8], size: u64) -> IResult<&[u8], &[u8]> {
  * Tar entry header + contents parsing
  */
 
-fn parse_entry(i: &[u8]) -> IResult<&[u8], TarEntry> {
+pub fn parse_entry(i: &[u8]) -> IResult<&[u8], TarEntry<'_>> {
     chain!(i,
         header:   parse_header ~
         contents: apply!(parse_contents, header.size),
@@ -446,21 +506,63 @@ fn parse_entry(i: &[u8]) -> IResult<&[u8], TarEntry> {
  * Tar archive parsing
  */
 
-fn filter_entries(entries: Vec<TarEntry>) -> Vec<TarEntry> {
-    /* Filter out empty entries */
-    entries.into_iter().filter(|e| e.header.name != "").collect::<Vec<TarEntry>>()
+/// What a record of an archive is, before any of them is dropped
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub enum TarItem<'a> {
+    Entry(TarEntry<'a>),
+    /// A PAX, GNU long name or volume header record rather than a file
+    Metadata(TarEntry<'a>),
+    /// An all-zero block, ending the archive or padding its last record
+    Terminator,
+    /// A header that parses but names nothing, which no writer produces
+    EmptyName(TarEntry<'a>)
 }
 
-pub fn parse_tar(i: &[u8]) -> IResult<&[u8], Vec<TarEntry>> {
+fn is_metadata(typeflag: TypeFlag) -> bool {
+    matches!(typeflag, TypeFlag::PaxExtendedAttributes | TypeFlag::PaxInterexchangeFormat |
+                       TypeFlag::GnuLongName | TypeFlag::GnuLongLink | TypeFlag::GnuVolumeHeader)
+}
+
+pub fn parse_item(i: &[u8]) -> IResult<&[u8], TarItem<'_>> {
+    if i.len() >= 512 && i[..512].iter().all(|b| *b == 0) {
+        return IResult::Done(&i[512..], TarItem::Terminator);
+    }
+    match parse_entry(i) {
+        IResult::Done(rest, e) => IResult::Done(rest, match e {
+            _ if is_metadata(e.header.typeflag) => TarItem::Metadata(e),
+            _ if e.header.name.is_empty() => TarItem::EmptyName(e),
+            _ => TarItem::Entry(e)
+        }),
+        IResult::Incomplete(n) => IResult::Incomplete(n),
+        IResult::Error(e) => IResult::Error(e)
+    }
+}
+
+/// Every record of an archive, classified
+pub fn parse_items(i: &[u8]) -> IResult<&[u8], Vec<TarItem<'_>>> {
     chain!(i,
-        entries: map!(many0!(parse_entry), filter_entries) ~
+        items: many0!(parse_item) ~
         eof,
         ||{
-            entries
+            items
         }
     )
 }
 
+/* Entries and metadata records, as listed by parse_tar */
+fn entries(items: Vec<TarItem<'_>>) -> Vec<TarEntry<'_>> {
+    items.into_iter().filter_map(|item| match item {
+        TarItem::Entry(e) | TarItem::Metadata(e) => Some(e),
+        TarItem::Terminator | TarItem::EmptyName(_) => None
+    }).collect()
+}
+
+/// Entries and metadata records of an archive. Headers with an empty
+/// name are left out, `parse_items` reports them.
+pub fn parse_tar(i: &[u8]) -> IResult<&[u8], Vec<TarEntry<'_>>> {
+    map!(i, parse_items, entries)
+}
+
 /*
  * Tests
  */
@@ -468,9 +570,22 @@ pub fn parse_tar(i: &[u8]) -> IResult<&[u8], Vec<TarEntry>> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::str::from_utf8;
     use nom::IResult;
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_type_flag_test() {
+        use serde_json;
+
+        let names = [(TypeFlag::NormalFile, "normal_file"), (TypeFlag::FIFO, "fifo"),
+                     (TypeFlag::PaxExtendedAttributes, "pax_extended_attributes"), (TypeFlag::GnuLongName, "gnu_long_name")];
+        for &(flag, name) in &names {
+            let json = serde_json::to_string(&flag).unwrap();
+            assert_eq!(json, format!("\"{}\"", name));
+            assert_eq!(serde_json::from_str::<TypeFlag>(&json).unwrap(), flag);
+        }
+    }
+
     #[test]
     fn octal_to_u64_ok_test() {
         assert_eq!(octal_to_u64("756"), Ok(494));
@@ -489,5 +604,76 @@ mod tests {
         let s = b"foobar\0\0\0\0baz";
         let baz = b"baz";
         assert_eq!(take_str_eat_garbage!(&s[..], 10), IResult::Done(&baz[..], "foobar"));
+        assert_eq!(take_str_eat_garbage!(&s[..], 6), IResult::Done(&s[6..], "foobar"));
+    }
+
+    #[test]
+    fn extra_sparses_test() {
+        /* One extension block with all 21 slots used and no block after it */
+        let mut block = Vec::new();
+        for n in 1..22 {
+            block.extend_from_slice(format!("{:011o}\0{:011o}\0", n * 1024, 512).as_bytes());
+        }
+        block.extend_from_slice(&[0; 8]);
+        let mut sparses = vec![Sparse { offset: 0, numbytes: 512 }];
+        match parse_extra_sparses(&block, true, &mut sparses) {
+            IResult::Done(rest, all) => {
+                assert!(rest.is_empty());
+                assert_eq!(all.len(), 22);
+                assert_eq!(all[1], Sparse { offset: 1024, numbytes: 512 });
+                assert_eq!(all[21], Sparse { offset: 21 * 1024, numbytes: 512 });
+            },
+            e => panic!("cannot parse sparse extension: {:?}", e)
+        }
+    }
+
+    #[test]
+    fn entries_order_by_path_test() {
+        use std::collections::HashSet;
+
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let mut entries = match parse_tar(&tar[..]) {
+            IResult::Done(_, entries) => entries,
+            e => panic!("cannot parse tar archive: {:?}", e)
+        };
+        let set = entries.iter().cloned().collect::<HashSet<TarEntry>>();
+        assert_eq!(set.len(), 4);
+
+        entries.sort();
+        let paths = entries.iter().map(|e| e.header.path()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["test/", "test/bar", "test/baz", "test/foo"]);
+    }
+
+    #[test]
+    fn parse_items_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let items = match parse_items(&tar[..]) {
+            IResult::Done(_, items) => items,
+            e => panic!("cannot parse tar archive: {:?}", e)
+        };
+        assert_eq!(items.iter().filter(|i| matches!(i, TarItem::Entry(_))).count(), 4);
+        assert_eq!(items.iter().filter(|i| **i == TarItem::Terminator).count(), 20 - 7);
+
+        /* A named header with its name wiped out */
+        let mut block = tar[512..1536].to_vec();
+        for b in &mut block[..100] {
+            *b = 0;
+        }
+        match parse_items(&block) {
+            IResult::Done(_, ref items) if matches!(items[..], [TarItem::EmptyName(ref e)] if e.contents == b"This is bar\n") => {},
+            e => panic!("unexpected result: {:?}", e)
+        }
+        assert_eq!(parse_tar(&block), IResult::Done(&b""[..], vec![]));
+    }
+
+    #[test]
+    fn parse_tar_edge_cases_test() {
+        assert_eq!(parse_tar(b""), IResult::Done(&b""[..], vec![]));
+        assert_eq!(parse_tar(&[0; 1024]), IResult::Done(&b""[..], vec![]));
+        /* A last block cut short is incomplete, not an empty archive */
+        match parse_tar(&[0; 100]) {
+            IResult::Incomplete(_) => {},
+            e => panic!("unexpected result: {:?}", e)
+        }
     }
 }
@@ -0,0 +1,99 @@
+/*
+ * Archive path helpers
+ */
+
+/// Split a path into its meaningful components, dropping empty and `.` ones
+pub fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".").collect()
+}
+
+/// Lexically normalize an archive path: no leading `/` or `./`, no empty or
+/// `.` components, `..` applied to the preceding component.
+/// Returns `None` if the path climbs above the archive root.
+pub fn normalize(path: &str) -> Option<String> {
+    let mut out: Vec<&str> = Vec::new();
+    for c in components(path) {
+        if c == ".." {
+            out.pop()?;
+        } else {
+            out.push(c);
+        }
+    }
+    Some(out.join("/"))
+}
+
+/// Number of directories above an entry plus one, `a/b/c` being 3 deep.
+/// `None` if the path climbs above the archive root.
+pub fn depth(path: &str) -> Option<usize> {
+    normalize(path).map(|p| components(&p).len())
+}
+
+/// Parent of a normalized path, the root being `""`
+pub fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(i) => &path[..i],
+        None => ""
+    }
+}
+
+/// Last component of a normalized path
+pub fn file_name(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(i) => &path[i + 1..],
+        None => path
+    }
+}
+
+/// Shell-style wildcard match where `*` matches any run of characters,
+/// `/` included, and `?` any single character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    /* Where to resume after the last `*` when a later part fails */
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_test() {
+        assert_eq!(normalize("./a//b/./c/"), Some("a/b/c".to_owned()));
+        assert_eq!(normalize("/a/b/../c"), Some("a/c".to_owned()));
+        assert_eq!(normalize("a/../.."), None);
+        assert_eq!(parent("a/b/c"), "a/b");
+        assert_eq!(file_name("a/b/c"), "c");
+        assert_eq!(parent("a"), "");
+    }
+
+    #[test]
+    fn glob_match_test() {
+        assert!(glob_match("backup-*", "backup-2015-05-30"));
+        assert!(glob_match("a*b?d", "a/x/bcd"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("backup-?", "backup-10"));
+        assert!(!glob_match("*.tar", "a.tar.gz"));
+    }
+}
@@ -0,0 +1,113 @@
+use archive::EntryMetadata;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Where the entries of a built archive come from
+ */
+
+/// Origin of an entry copied from another archive
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Provenance {
+    /// Identifier of the source archive, such as its path or digest
+    pub source:     String,
+    /// Offset of the entry header in the source
+    pub offset:     u64,
+    /// Path of the entry in the source
+    pub path:       String,
+    /// Names of the filters and transforms applied, in order
+    pub transforms: Vec<String>
+}
+
+impl Provenance {
+    pub fn new(source: &str, entry: &EntryMetadata) -> Provenance {
+        Provenance {
+            source:     source.to_owned(),
+            offset:     entry.header_offset,
+            path:       entry.path.clone(),
+            transforms: Vec::new()
+        }
+    }
+}
+
+/// One entry written by a `Builder`
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReportEntry {
+    pub path:       String,
+    /// Offset of the entry header in the built archive
+    pub offset:     u64,
+    /// Set for entries appended with `Builder::append_traced`
+    pub provenance: Option<Provenance>
+}
+
+/// Every entry a `Builder` wrote, see `Builder::track_provenance`
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BuildReport {
+    pub entries: Vec<ReportEntry>
+}
+
+impl BuildReport {
+    /// Entries copied from one source
+    pub fn from_source<'a>(&'a self, source: &'a str) -> impl Iterator<Item = &'a ReportEntry> + 'a {
+        self.entries.iter().filter(move |e| e.provenance.as_ref().map(|p| p.source == source).unwrap_or(false))
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use archive::Archive;
+    use builder::{Builder, Header};
+    use filter::{convert_traced, EntryFilter, FilterChain};
+    use transform::Replace;
+
+    struct Keep;
+
+    impl EntryFilter for Keep {
+        fn name(&self) -> &str {
+            "keep"
+        }
+    }
+
+    #[test]
+    fn report_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let source = Archive::new(tar.to_vec()).unwrap();
+        let mut chain = FilterChain::new();
+        chain.push(Keep);
+
+        let mut b = Builder::new(Cursor::new(Vec::new()));
+        b.record_digests(true);
+        b.transform(|_| Some(Box::new(Replace::new(b"This", b"That"))));
+        b.track_provenance(true);
+        assert_eq!(convert_traced(&source, "simple", &mut chain, &mut b).unwrap(), 4);
+        b.append_streaming(&Header::new("extra"), &b"This"[..]).unwrap();
+        let report = b.report().unwrap().clone();
+        let built = Archive::new(b.finish().unwrap().into_inner()).unwrap();
+
+        assert_eq!(report.entries.len(), 5);
+        assert_eq!(built.entries().iter().map(|e| e.header_offset).collect::<Vec<_>>(),
+                   report.entries.iter().map(|e| e.offset).collect::<Vec<_>>());
+        let bar = &report.entries[1];
+        assert_eq!(bar.path, "test/bar");
+        assert_eq!(bar.provenance, Some(Provenance {
+            source:     "simple".to_owned(),
+            offset:     512,
+            path:       "test/bar".to_owned(),
+            transforms: vec!["keep".to_owned(), "replace".to_owned()]
+        }));
+        /* Directories are not transformed */
+        assert_eq!(report.entries[0].provenance.as_ref().unwrap().transforms, vec!["keep"]);
+        assert_eq!(report.entries[4].provenance, None);
+        assert_eq!(report.from_source("simple").count(), 4);
+        assert_eq!(built.contents_of("test/bar"), Some(&b"That is bar\n"[..]));
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use archive::{Archive, EntryMetadata};
+use builder::split_path;
+use filter::{EntryFilter, Verdict};
+use parser::TypeFlag;
+use paths;
+use provenance::BuildReport;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Checking renamed paths before an archive is rewritten
+ */
+
+/// Header format the renamed entries will be written in, which decides
+/// how long their names may be
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum NameFormat {
+    /// Names must fit the name and prefix fields, link targets 100 bytes
+    Ustar,
+    /// PAX records hold names of any length
+    Pax
+}
+
+/// Why renaming an entry would produce an archive that extracts incorrectly
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RenameViolation {
+    /// The new name does not fit the header format
+    TooLong { path: String, renamed: String },
+    /// The new name of the target of a hard link does not fit its link field
+    LinkTooLong { path: String, linkname: String, renamed: String },
+    /// The new name is absolute or climbs above the archive root
+    Traversal { path: String, renamed: String },
+    /// Another entry with a different name is renamed to the same path
+    Duplicate { path: String, renamed: String, other: String },
+    /// The new name lies below an entry that is not a directory
+    ParentNotDirectory { path: String, renamed: String, parent: String }
+}
+
+/* Lookup key of a path, like the archive index */
+fn key(path: &str) -> Option<String> {
+    paths::normalize(path)
+}
+
+/// Check the names `rename` gives every entry of `archive`, reporting all
+/// violations so an edit can be refused before anything is written.
+/// Problems already present under the original names are not reported.
+pub fn validate_renames<F>(archive: &Archive, format: NameFormat, mut rename: F) -> Vec<RenameViolation>
+    where F: FnMut(&EntryMetadata) -> String
+{
+    let renamed = archive.entries().iter().map(&mut rename).collect::<Vec<_>>();
+    let mut violations = Vec::new();
+
+    /* Hard links follow their target to its new name */
+    let targets = archive.entries().iter().zip(&renamed)
+        .filter_map(|(e, new)| Some((key(&e.path)?, new)))
+        .collect::<HashMap<_, _>>();
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut files: HashMap<String, usize> = HashMap::new();
+    for (i, (e, new)) in archive.entries().iter().zip(&renamed).enumerate() {
+        if format == NameFormat::Ustar && split_path(new).is_err() && split_path(&e.path).is_ok() {
+            violations.push(RenameViolation::TooLong { path: e.path.clone(), renamed: new.clone() });
+        }
+        if format == NameFormat::Ustar && e.typeflag == TypeFlag::HardLink && e.linkname.len() < 100 {
+            match key(&e.linkname).and_then(|k| targets.get(&k)) {
+                Some(link) if link.len() >= 100 => {
+                    violations.push(RenameViolation::LinkTooLong { path: e.path.clone(), linkname: e.linkname.clone(), renamed: (*link).clone() });
+                },
+                _ => {}
+            }
+        }
+        let k = match key(new) {
+            Some(ref k) if !new.starts_with('/') => k.clone(),
+            _ => {
+                if key(&e.path).is_some() && !e.path.starts_with('/') {
+                    violations.push(RenameViolation::Traversal { path: e.path.clone(), renamed: new.clone() });
+                }
+                continue;
+            }
+        };
+        match seen.get(&k) {
+            /* Later copies of one path replace earlier ones, as with `tar -u` */
+            Some(&j) if key(&archive.entries()[j].path) != key(&e.path) => {
+                violations.push(RenameViolation::Duplicate { path: e.path.clone(), renamed: new.clone(), other: archive.entries()[j].path.clone() });
+            },
+            _ => { seen.insert(k.clone(), i); }
+        }
+        if e.typeflag != TypeFlag::Directory {
+            files.insert(k, i);
+        }
+    }
+
+    for (e, new) in archive.entries().iter().zip(&renamed) {
+        let k = match key(new) {
+            Some(k) => k,
+            None => continue
+        };
+        let mut parent = paths::parent(&k);
+        while !parent.is_empty() {
+            if let Some(&j) = files.get(parent) {
+                violations.push(RenameViolation::ParentNotDirectory {
+                    path:    e.path.clone(),
+                    renamed: new.clone(),
+                    parent:  renamed[j].clone()
+                });
+                break;
+            }
+            parent = paths::parent(parent);
+        }
+    }
+    violations
+}
+
+/*
+ * Rename maps
+ */
+
+/// Exact paths to rename, old to new. Hard links to a renamed entry are
+/// retargeted along with it.
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RenameMap {
+    pub renames: BTreeMap<String, String>
+}
+
+/* Tabs, newlines and backslashes in table fields are escaped */
+pub(crate) fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+pub(crate) fn unescape(field: &str) -> Option<String> {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        out.push(match c {
+            '\\' => match chars.next()? {
+                't' => '\t',
+                'n' => '\n',
+                '\\' => '\\',
+                _ => return None
+            },
+            c => c
+        });
+    }
+    Some(out)
+}
+
+impl RenameMap {
+    pub fn new() -> RenameMap {
+        RenameMap::default()
+    }
+
+    pub fn insert(&mut self, old: &str, new: &str) {
+        self.renames.insert(old.to_owned(), new.to_owned());
+    }
+
+    /// New name of `path`, unchanged when it is not renamed
+    pub fn apply<'a>(&'a self, path: &'a str) -> &'a str {
+        self.renames.get(path).map(|p| &p[..]).unwrap_or(path)
+    }
+
+    /// Read a table of `old<TAB>new` lines, as written by `to_table`. Blank
+    /// lines are ignored, renaming one path twice is an error.
+    pub fn parse_table(table: &str) -> io::Result<RenameMap> {
+        let mut map = RenameMap::new();
+        for (n, line) in table.lines().enumerate().filter(|&(_, l)| !l.is_empty()) {
+            let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, msg));
+            let mut fields = line.split('\t');
+            let (old, new) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(old), Some(new), None) => (old, new),
+                _ => return Err(invalid("expected two tab separated paths"))
+            };
+            let (old, new) = match (unescape(old), unescape(new)) {
+                (Some(old), Some(new)) => (old, new),
+                _ => return Err(invalid("invalid escape"))
+            };
+            if map.renames.insert(old, new).is_some() {
+                return Err(invalid("path renamed twice"));
+            }
+        }
+        Ok(map)
+    }
+
+    /// The map as a table, sorted by old path
+    pub fn to_table(&self) -> String {
+        self.renames.iter().map(|(old, new)| format!("{}\t{}\n", escape(old), escape(new))).collect()
+    }
+
+    /// Renames that actually happened in a build, whatever made them, so
+    /// a repack can be reviewed or replayed with this map alone
+    pub fn from_report(report: &BuildReport) -> RenameMap {
+        let mut map = RenameMap::new();
+        for e in &report.entries {
+            match e.provenance {
+                Some(ref p) if p.path != e.path => map.insert(&p.path, &e.path),
+                _ => {}
+            }
+        }
+        map
+    }
+}
+
+impl EntryFilter for RenameMap {
+    fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+        if entry.typeflag == TypeFlag::HardLink {
+            entry.linkname = self.apply(&entry.linkname).to_owned();
+        }
+        entry.path = self.apply(&entry.path).to_owned();
+        Verdict::Keep
+    }
+
+    fn name(&self) -> &str {
+        "rename_map"
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::{Builder, Header};
+    use filter::{convert_traced, FilterChain};
+
+    fn archive() -> Archive {
+        let mut b = Builder::new(Vec::new());
+        let mut dir = Header::new("src/");
+        dir.typeflag = TypeFlag::Directory;
+        b.append(&dir, b"").unwrap();
+        b.append(&Header::new("src/a.rs"), b"a").unwrap();
+        b.append(&Header::new("src/b.rs"), b"b").unwrap();
+        b.append(&Header::new("README"), b"r").unwrap();
+        Archive::new(b.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn validate_renames_test() {
+        let archive = archive();
+        assert!(validate_renames(&archive, NameFormat::Ustar, |e| format!("pkg/{}", e.path)).is_empty());
+
+        let violations = validate_renames(&archive, NameFormat::Ustar, |e| match &e.path[..] {
+            "src/a.rs" => "src/b.rs".to_owned(),
+            "README" => "src/b.rs/README".to_owned(),
+            p => p.to_owned()
+        });
+        assert_eq!(violations, vec![
+            RenameViolation::Duplicate { path: "src/b.rs".to_owned(), renamed: "src/b.rs".to_owned(), other: "src/a.rs".to_owned() },
+            RenameViolation::ParentNotDirectory { path: "README".to_owned(), renamed: "src/b.rs/README".to_owned(), parent: "src/b.rs".to_owned() }
+        ]);
+
+        let long = "d/".repeat(150);
+        let violations = validate_renames(&archive, NameFormat::Ustar, |e| format!("{}{}", long, e.path));
+        assert_eq!(violations.len(), 4);
+        assert!(validate_renames(&archive, NameFormat::Pax, |e| format!("{}{}", long, e.path)).is_empty());
+
+        /* A renamed target may fit its own header but not the link field */
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("target"), b"t").unwrap();
+        let mut link = Header::new("link");
+        link.typeflag = TypeFlag::HardLink;
+        link.linkname = "target".to_owned();
+        b.append(&link, b"").unwrap();
+        let links = Archive::new(b.finish().unwrap()).unwrap();
+        let deep = format!("{}target", "d/".repeat(60));
+        let violations = validate_renames(&links, NameFormat::Ustar, |e| if e.path == "target" { deep.clone() } else { e.path.clone() });
+        assert_eq!(violations, vec![
+            RenameViolation::LinkTooLong { path: "link".to_owned(), linkname: "target".to_owned(), renamed: deep.clone() }
+        ]);
+        assert!(validate_renames(&links, NameFormat::Pax, |e| if e.path == "target" { deep.clone() } else { e.path.clone() }).is_empty());
+
+        let violations = validate_renames(&archive, NameFormat::Pax, |e| paths::file_name(e.path.trim_end_matches('/')).to_owned());
+        assert!(violations.is_empty());
+        let violations = validate_renames(&archive, NameFormat::Pax, |e| match &e.path[..] {
+            "src/b.rs" => "src/../../b.rs".to_owned(),
+            "README" => "/README".to_owned(),
+            p => p.to_owned()
+        });
+        assert_eq!(violations, vec![
+            RenameViolation::Traversal { path: "src/b.rs".to_owned(), renamed: "src/../../b.rs".to_owned() },
+            RenameViolation::Traversal { path: "README".to_owned(), renamed: "/README".to_owned() }
+        ]);
+    }
+
+    #[test]
+    fn rename_map_test() {
+        let map = RenameMap::parse_table("src/a.rs\tlib/a.rs\n\nREADME\tdoc/READ\\tME\n").unwrap();
+        assert_eq!(map.apply("README"), "doc/READ\tME");
+        assert_eq!(map.apply("src/b.rs"), "src/b.rs");
+        assert_eq!(RenameMap::parse_table(&map.to_table()).unwrap(), map);
+        assert!(RenameMap::parse_table("a\tb\na\tc\n").is_err());
+        assert!(RenameMap::parse_table("a b\n").is_err());
+
+        /* Renames made by other filters show up in the exported map */
+        struct Upper;
+        impl EntryFilter for Upper {
+            fn header(&mut self, entry: &mut EntryMetadata) -> Verdict {
+                if entry.path == "src/b.rs" {
+                    entry.path = entry.path.to_uppercase();
+                }
+                Verdict::Keep
+            }
+        }
+        let mut chain = FilterChain::new();
+        chain.push(map.clone());
+        chain.push(Upper);
+        let mut b = Builder::new(Vec::new());
+        b.track_provenance(true);
+        convert_traced(&archive(), "src", &mut chain, &mut b).unwrap();
+        let effective = RenameMap::from_report(b.report().unwrap());
+        assert_eq!(effective.to_table(), "README\tdoc/READ\\tME\nsrc/a.rs\tlib/a.rs\nsrc/b.rs\tSRC/B.RS\n");
+        assert!(validate_renames(&archive(), NameFormat::Ustar, |e| effective.apply(&e.path).to_owned()).is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use nom::IResult;
+
+use archive::EntryMetadata;
+use error::Error;
+use framing::{is_extension, is_zero_block, Framer, ParseOptions, Pending};
+use parser::{padding, parse_header, TypeFlag};
+use sums::{copy, read_block};
+
+/*
+ * Extraction to destinations picked entry by entry
+ */
+
+/// Where the contents of one regular file go
+pub enum Destination<'a> {
+    /// Write them to this file, creating its parent directories. The path
+    /// is used as is, without the checks `Extractor` makes.
+    File(PathBuf),
+    /// Keep them, in `RouteSummary::memory`
+    Memory,
+    /// Stream them to a writer of the caller
+    Writer(Box<dyn Write + 'a>),
+    Skip
+}
+
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+pub struct RouteSummary {
+    /// Regular files written somewhere
+    pub files:   u64,
+    pub bytes:   u64,
+    /// Regular files skipped, and other entries
+    pub skipped: u64,
+    /// Paths and contents of the files routed to `Destination::Memory`
+    pub memory:  Vec<(String, Vec<u8>)>
+}
+
+fn is_regular(e: &EntryMetadata) -> bool {
+    e.typeflag == TypeFlag::NormalFile || e.typeflag == TypeFlag::ContiguousFile
+}
+
+/// Read an archive once from `reader`, asking `route` where each regular
+/// file goes and copying its contents there as they stream by. Other
+/// entries are skipped.
+pub fn extract_routed<'a, R, F>(mut reader: R, options: &ParseOptions, mut route: F) -> Result<RouteSummary, Error>
+    where R: Read, F: FnMut(&EntryMetadata) -> Destination<'a>
+{
+    let mut framer = Framer::new(options.clone());
+    let mut pending = Pending::default();
+    let mut extension_offset = None;
+    let mut offset = 0;
+    let mut summary = RouteSummary::default();
+    let mut block = [0u8; 512];
+    loop {
+        if !read_block(&mut reader, &mut block, extension_offset.unwrap_or(offset))? {
+            match extension_offset {
+                Some(o) => return Err(Error::Truncated { offset: o }),
+                None => return Ok(summary)
+            }
+        }
+        if is_zero_block(&block, extension_offset)? {
+            offset += 512;
+            continue;
+        }
+        let header = match parse_header(&block) {
+            IResult::Done(_, h) => h,
+            _ => return Err(Error::InvalidHeader { offset: offset })
+        };
+        let first = *extension_offset.get_or_insert(offset);
+        let size = header.size;
+
+        if is_extension(header.typeflag) {
+            framer.check_extension(&mut pending, size, offset)?;
+            let mut record = Vec::with_capacity(size as usize);
+            copy(&mut reader, size + padding(size), &mut record, first)?;
+            framer.push_extension(&mut pending, header.typeflag, &record[..size as usize], offset)?;
+            offset += 512 + size + padding(size);
+            continue;
+        }
+
+        extension_offset = None;
+        let pending = mem::take(&mut pending);
+        let (destination, size, path) = if framer.is_empty(&pending, &header, offset)? {
+            (Destination::Skip, size, String::new())
+        } else {
+            let metadata = framer.metadata(pending, &header, first, offset, offset + 512)?;
+            let destination = if is_regular(&metadata) { route(&metadata) } else { Destination::Skip };
+            (destination, metadata.size, metadata.path)
+        };
+        if !matches!(destination, Destination::Skip) {
+            summary.files += 1;
+            summary.bytes += size;
+        }
+        match destination {
+            Destination::Skip => {
+                summary.skipped += 1;
+                copy(&mut reader, size, &mut io::sink(), offset)?;
+            },
+            Destination::File(file) => {
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut file = File::create(&file)?;
+                copy(&mut reader, size, &mut file, offset)?;
+            },
+            Destination::Memory => {
+                let mut contents = Vec::with_capacity(size as usize);
+                copy(&mut reader, size, &mut contents, offset)?;
+                summary.memory.push((path, contents));
+            },
+            Destination::Writer(mut w) => {
+                copy(&mut reader, size, &mut w, offset)?;
+                w.flush()?;
+            }
+        }
+        copy(&mut reader, padding(size), &mut io::sink(), offset)?;
+        offset += 512 + size + padding(size);
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::env;
+    use std::process;
+    use std::rc::Rc;
+
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn extract_routed_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let dir = env::temp_dir().join(format!("tar-route-{}", process::id()));
+        let piped = Rc::new(RefCell::new(Vec::new()));
+        let summary = extract_routed(&tar[..], &ParseOptions::default(), |e| match &e.path[..] {
+            "test/bar" => Destination::File(dir.join("configs/bar")),
+            "test/foo" => Destination::Memory,
+            "test/baz" => Destination::Writer(Box::new(Shared(piped.clone()))),
+            _ => Destination::Skip
+        }).unwrap();
+        assert_eq!((summary.files, summary.bytes, summary.skipped), (3, 36, 1));
+        assert_eq!(summary.memory, vec![("test/foo".to_owned(), b"This is foo\n".to_vec())]);
+        assert_eq!(*piped.borrow(), b"This is baz\n");
+        assert_eq!(fs::read(dir.join("configs/bar")).unwrap(), b"This is bar\n");
+        fs::remove_dir_all(dir).unwrap();
+
+        match extract_routed(&tar[..3000], &ParseOptions::default(), |_| Destination::Memory) {
+            Err(Error::Truncated { offset: 2560 }) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::cmp;
+use std::io::{self, Write};
+
+use builder::Builder;
+
+/*
+ * Block sinks: archive destinations that are not plain writers
+ */
+
+const BLOCK: usize = 512;
+
+/// A destination taking an archive as whole 512 byte blocks, like a
+/// chunked upload, a block store or an encrypting wrapper
+pub trait BlockSink {
+    /// Take the next blocks. `blocks` is never empty and always a multiple
+    /// of 512 bytes long.
+    fn write_blocks(&mut self, blocks: &[u8]) -> io::Result<()>;
+
+    /// Push out anything held back, as far as the sink allows
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Complete the archive once its last block was written
+    fn finalize(&mut self) -> io::Result<()>;
+}
+
+impl BlockSink for Vec<u8> {
+    fn write_blocks(&mut self, blocks: &[u8]) -> io::Result<()> {
+        self.extend_from_slice(blocks);
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: BlockSink + ?Sized> BlockSink for Box<S> {
+    fn write_blocks(&mut self, blocks: &[u8]) -> io::Result<()> {
+        (**self).write_blocks(blocks)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        (**self).finalize()
+    }
+}
+
+/// Writer over a `BlockSink`. Runs of whole blocks go straight to the sink,
+/// at most one partial block is held.
+pub struct BlockWriter<S: BlockSink> {
+    sink:    S,
+    partial: Vec<u8>
+}
+
+impl<S: BlockSink> BlockWriter<S> {
+    pub fn new(sink: S) -> BlockWriter<S> {
+        BlockWriter {
+            sink:    sink,
+            partial: Vec::with_capacity(BLOCK)
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    /// Finalize the sink and give it back. Fails if the data written does
+    /// not end on a block boundary.
+    pub fn finalize(mut self) -> io::Result<S> {
+        if !self.partial.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "archive does not end on a block boundary"));
+        }
+        self.sink.finalize()?;
+        Ok(self.sink)
+    }
+}
+
+impl<S: BlockSink> Write for BlockWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.partial.is_empty() || buf.len() < BLOCK {
+            let n = cmp::min(BLOCK - self.partial.len(), buf.len());
+            self.partial.extend_from_slice(&buf[..n]);
+            if self.partial.len() == BLOCK {
+                self.sink.write_blocks(&self.partial)?;
+                self.partial.clear();
+            }
+            return Ok(n);
+        }
+        let n = buf.len() - buf.len() % BLOCK;
+        self.sink.write_blocks(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+impl<S: BlockSink> Builder<BlockWriter<S>> {
+    /// Write an archive to a block sink
+    pub fn with_sink(sink: S) -> Builder<BlockWriter<S>> {
+        Builder::new(BlockWriter::new(sink))
+    }
+
+    /// Write the terminator, then finalize the sink and give it back
+    pub fn finish_sink(self) -> io::Result<S> {
+        self.finish()?.finalize()
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::Header;
+
+    /* Collects uploads of up to `part` bytes, like a multipart upload */
+    struct Uploads {
+        part:     usize,
+        buffer:   Vec<u8>,
+        parts:    Vec<Vec<u8>>,
+        complete: bool
+    }
+
+    impl BlockSink for Uploads {
+        fn write_blocks(&mut self, blocks: &[u8]) -> io::Result<()> {
+            assert_eq!(blocks.len() % BLOCK, 0);
+            self.buffer.extend_from_slice(blocks);
+            while self.buffer.len() >= self.part {
+                let rest = self.buffer.split_off(self.part);
+                self.parts.push(std::mem::replace(&mut self.buffer, rest));
+            }
+            Ok(())
+        }
+
+        fn finalize(&mut self) -> io::Result<()> {
+            if !self.buffer.is_empty() {
+                self.parts.push(std::mem::take(&mut self.buffer));
+            }
+            self.complete = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_test() {
+        let contents = vec![7u8; 3000];
+        let mut expected = Builder::new(Vec::new());
+        expected.append(&Header::new("a"), b"short").unwrap();
+        expected.append(&Header::new("b"), &contents).unwrap();
+        let expected = expected.finish().unwrap();
+
+        let mut b = Builder::with_sink(Uploads { part: 2048, buffer: Vec::new(), parts: Vec::new(), complete: false });
+        b.append(&Header::new("a"), b"short").unwrap();
+        b.append(&Header::new("b"), &contents).unwrap();
+        let uploads = b.finish_sink().unwrap();
+        assert!(uploads.complete);
+        assert_eq!(uploads.parts.iter().map(|p| p.len()).collect::<Vec<_>>(), vec![2048, 2048, 1536]);
+        assert_eq!(uploads.parts.concat(), expected);
+
+        let mut w = BlockWriter::new(Vec::new());
+        w.write_all(b"partial").unwrap();
+        assert!(w.finalize().is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+use std::io;
+
+use nom::IResult;
+
+use archive::{index_key, Archive, EntryMetadata};
+use error::Error;
+use parser::{padding, parse_header, TypeFlag};
+
+/*
+ * Sub-archives copied block for block
+ */
+
+/* End of an entry and its padded contents */
+fn entry_end(e: &EntryMetadata) -> usize {
+    (e.data_offset + e.size + padding(e.size)) as usize
+}
+
+/* Global PAX headers among the extension records of an entry */
+fn global_headers<'a>(data: &'a [u8], e: &EntryMetadata, out: &mut Vec<&'a [u8]>) -> Result<(), Error> {
+    let mut pos = e.extension_offset as usize;
+    while pos < e.header_offset as usize {
+        let header = match parse_header(&data[pos..]) {
+            IResult::Done(_, h) => h,
+            _ => return Err(Error::InvalidHeader { offset: pos as u64 })
+        };
+        let end = pos + 512 + (header.size + padding(header.size)) as usize;
+        if header.typeflag == TypeFlag::PaxInterexchangeFormat {
+            out.push(&data[pos..end]);
+        }
+        pos = end;
+    }
+    Ok(())
+}
+
+/// A tar holding only the entries stored under `paths`, every copy of
+/// them, in archive order. Header blocks, PAX and GNU long name records and
+/// contents are copied verbatim, as is the volume header. Global PAX
+/// headers of the left out entries are kept too, so the selected entries
+/// keep their metadata.
+///
+/// Hard links are copied as they are, whether their target is selected or not.
+pub fn slice(archive: &Archive, paths: &[&str]) -> Result<Vec<u8>, Error> {
+    let wanted = paths.iter().map(|p| index_key(p)).collect::<HashSet<_>>();
+    for p in paths {
+        if archive.get(p).is_none() {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the archive", p))));
+        }
+    }
+
+    let data = archive.as_bytes();
+    let mut parts = Vec::new();
+    if let Some(v) = archive.volume_header() {
+        parts.push(&data[v.extension_offset as usize..entry_end(v)]);
+    }
+    for e in archive.entries() {
+        if wanted.contains(index_key(&e.path)) {
+            parts.push(&data[e.extension_offset as usize..entry_end(e)]);
+        } else {
+            global_headers(data, e, &mut parts)?;
+        }
+    }
+
+    let mut out = Vec::with_capacity(parts.iter().map(|p| p.len()).sum::<usize>() + 1024);
+    for p in parts {
+        out.extend_from_slice(p);
+    }
+    out.extend_from_slice(&[0; 1024]);
+    Ok(out)
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::{pax_record, Builder, Header};
+
+    #[test]
+    fn slice_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let archive = Archive::new(tar.to_vec()).unwrap();
+        let sliced = slice(&archive, &["test/foo", "test/"]).unwrap();
+        assert_eq!(sliced.len(), 512 + 1024 + 1024);
+        assert_eq!(&sliced[..512], &tar[..512]);
+        let sub = Archive::new(sliced).unwrap();
+        assert_eq!(sub.entries().iter().map(|e| &e.path[..]).collect::<Vec<_>>(), vec!["test/", "test/foo"]);
+        assert_eq!(sub.contents_of("test/foo"), Some(&b"This is foo\n"[..]));
+        assert!(slice(&archive, &["test/missing"]).is_err());
+
+        /* Global records of a left out entry still apply */
+        let mut global = Header::new("././@PaxGlobal");
+        global.typeflag = TypeFlag::PaxInterexchangeFormat;
+        let mut b = Builder::new(Vec::new());
+        b.append(&global, &pax_record("uname", b"builder")).unwrap();
+        b.append(&Header::new("skipped"), b"").unwrap();
+        b.append(&Header::new("kept"), b"").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        let sub = Archive::new(slice(&archive, &["kept"]).unwrap()).unwrap();
+        assert_eq!(sub.entries().len(), 1);
+        assert_eq!(sub.entries()[0].uname, "builder");
+    }
+}
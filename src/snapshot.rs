@@ -0,0 +1,421 @@
+extern crate libc;
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::{File, Metadata};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::mem;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::rc::Rc;
+
+use builder::{Builder, Header, Spool};
+use pack::{decide, decide_unsupported, device_numbers, header_for, utf8_name, PackPolicy, PackSummary, MAX_DEVICE};
+use parser::TypeFlag;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Packing live directories consistently
+ */
+
+/// How a file changed while it was being packed
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Change {
+    /// Its contents or metadata changed during every read, the last read
+    /// being stored
+    Modified,
+    /// Another file took its place between listing and opening. A regular
+    /// file or directory opened in its place is stored, anything else is
+    /// left out.
+    Replaced,
+    /// It was removed before it could be opened, and is left out
+    Vanished
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChangedFile {
+    /// Archive name of the file
+    pub name:   String,
+    pub change: Change
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotPolicy {
+    pub pack:           PackPolicy,
+    /// Times a regular file is read again when it changed while read
+    pub retries:        u32,
+    /// Stop with an error on the first change instead of reporting it
+    pub fail_on_change: bool
+}
+
+impl Default for SnapshotPolicy {
+    fn default() -> SnapshotPolicy {
+        SnapshotPolicy {
+            pack:           PackPolicy::default(),
+            retries:        2,
+            fail_on_change: false
+        }
+    }
+}
+
+/// What `Builder::append_snapshot` packed
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotSummary {
+    pub pack:    PackSummary,
+    /// Files that changed under the walk, in archive order
+    pub changed: Vec<ChangedFile>
+}
+
+fn cstring(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path holds a NUL byte"))
+}
+
+/* Open `name` in the directory `dir` without following a final symlink */
+fn openat(dir: RawFd, name: &CStr, flags: libc::c_int) -> io::Result<File> {
+    let fd = unsafe { libc::openat(dir, name.as_ptr(), flags | libc::O_NOFOLLOW | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn lstatat(dir: RawFd, name: &CStr) -> io::Result<libc::stat> {
+    let mut st: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstatat(dir, name.as_ptr(), &mut st, libc::AT_SYMLINK_NOFOLLOW) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(st)
+}
+
+fn readlinkat(dir: RawFd, name: &CStr) -> io::Result<OsString> {
+    let mut buf = vec![0u8; 256];
+    loop {
+        let n = unsafe { libc::readlinkat(dir, name.as_ptr(), buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        /* A full buffer may hold a truncated target */
+        if (n as usize) < buf.len() {
+            buf.truncate(n as usize);
+            return Ok(OsString::from_vec(buf));
+        }
+        let len = buf.len() * 2;
+        buf.resize(len, 0);
+    }
+}
+
+/* Names in an open directory, sorted */
+fn list(dir: &File) -> io::Result<Vec<OsString>> {
+    let fd = unsafe { libc::dup(dir.as_raw_fd()) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stream = unsafe { libc::fdopendir(fd) };
+    if stream.is_null() {
+        let e = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(e);
+    }
+    let mut names = Vec::new();
+    loop {
+        let entry = unsafe { libc::readdir(stream) };
+        if entry.is_null() {
+            break;
+        }
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+        if name.to_bytes() != b"." && name.to_bytes() != b".." {
+            names.push(OsStr::from_bytes(name.to_bytes()).to_owned());
+        }
+    }
+    unsafe { libc::closedir(stream) };
+    names.sort();
+    Ok(names)
+}
+
+fn header_for_stat(name: &str, st: &libc::stat) -> Header {
+    let mut header = Header::new(name);
+    header.mode = st.st_mode as u64 & 0o7777;
+    header.uid = st.st_uid as u64;
+    header.gid = st.st_gid as u64;
+    header.mtime = st.st_mtime.max(0) as u64;
+    header
+}
+
+/* Whether nothing visible changed between two stats of an open file */
+fn unchanged(before: &Metadata, after: &Metadata) -> bool {
+    before.size() == after.size()
+        && (before.mtime(), before.mtime_nsec()) == (after.mtime(), after.mtime_nsec())
+        && (before.ctime(), before.ctime_nsec()) == (after.ctime(), after.ctime_nsec())
+}
+
+/* Contents of an open regular file, read again while it changes. Returns
+ * the last read, spooled so large files are not held in memory, the
+ * metadata after it and whether it was stable. */
+fn read_stable(file: &mut File, retries: u32) -> io::Result<(Spool, Metadata, bool)> {
+    let mut before = file.metadata()?;
+    let mut data = Spool::new();
+    for _ in 0..=retries {
+        file.seek(SeekFrom::Start(0))?;
+        data = Spool::new();
+        io::copy(file, &mut data)?;
+        let after = file.metadata()?;
+        let stable = unchanged(&before, &after) && data.len() == after.size();
+        before = after;
+        if stable {
+            return Ok((data, before, true));
+        }
+    }
+    Ok((data, before, false))
+}
+
+/* Report a change, or the error it is with `fail_on_change` */
+fn changed(summary: &mut SnapshotSummary, policy: &SnapshotPolicy, name: &str, change: Change) -> io::Result<()> {
+    if policy.fail_on_change {
+        return Err(io::Error::other(format!("{}: file changed as we read it ({:?})", name, change)));
+    }
+    summary.changed.push(ChangedFile { name: name.to_owned(), change: change });
+    Ok(())
+}
+
+/* A file to pack: its archive name, the directory holding it, its name
+ * there and its depth below the packed directory */
+struct Pending {
+    name:   String,
+    parent: Rc<File>,
+    entry:  CString,
+    depth:  usize
+}
+
+impl<W: Write> Builder<W> {
+    /// Append the directory at `path` under `name` and everything below it
+    /// like `append_dir_all`, but walking through open directory handles
+    /// so renames above a file cannot redirect the walk. Each file is
+    /// checked after opening to be the one listed, and regular files are
+    /// read again when their size or times change during the read.
+    ///
+    /// One handle stays open per directory level being walked.
+    pub fn append_snapshot<P: AsRef<Path>>(&mut self, name: &str, path: P, policy: &SnapshotPolicy) -> io::Result<SnapshotSummary> {
+        let path = path.as_ref();
+        /* Links above the packed directory are followed, only the walk below it is guarded */
+        let (dir, entry) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(file_name)) => (if parent.as_os_str().is_empty() { Path::new(".") } else { parent }, file_name),
+            /* The root directory, or a path ending in "..", looked up as is */
+            _ => (Path::new("."), path.as_os_str())
+        };
+        let parent = Rc::new(File::open(dir)?);
+        let entry = cstring(entry)?;
+
+        let mut summary = SnapshotSummary::default();
+        let mut stack = vec![Pending {
+            name:   name.trim_end_matches('/').to_owned(),
+            parent: parent,
+            entry:  entry,
+            depth:  0
+        }];
+        while let Some(pending) = stack.pop() {
+            let name = pending.name.clone();
+            if policy.pack.max_depth.map(|limit| pending.depth > limit).unwrap_or(false) {
+                summary.pack.too_deep.push(name);
+                continue;
+            }
+            if let Some(dir) = self.append_pending(&pending, policy, &mut summary)? {
+                let dir = Rc::new(dir);
+                for child in list(&dir)?.into_iter().rev() {
+                    let child_name = match &name[..] {
+                        "" => utf8_name(&child)?.to_owned(),
+                        _ => format!("{}/{}", name, utf8_name(&child)?)
+                    };
+                    stack.push(Pending {
+                        name:   child_name,
+                        parent: dir.clone(),
+                        entry:  cstring(&child)?,
+                        depth:  pending.depth + 1
+                    });
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /* Append one file, returning the open handle of a directory to walk */
+    fn append_pending(&mut self, pending: &Pending, policy: &SnapshotPolicy, summary: &mut SnapshotSummary) -> io::Result<Option<File>> {
+        let name = &pending.name[..];
+        let dir = pending.parent.as_raw_fd();
+        let st = match lstatat(dir, &pending.entry) {
+            Ok(st) => st,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                changed(summary, policy, name, Change::Vanished)?;
+                return Ok(None);
+            },
+            Err(e) => return Err(e)
+        };
+        let mut header = header_for_stat(name, &st);
+        let opens = match st.st_mode & libc::S_IFMT {
+            libc::S_IFREG => Some(libc::O_RDONLY | libc::O_NONBLOCK),
+            libc::S_IFDIR => Some(libc::O_RDONLY | libc::O_DIRECTORY),
+            _ => None
+        };
+        if let Some(flags) = opens {
+            let mut file = match openat(dir, &pending.entry, flags) {
+                Ok(file) => file,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                    changed(summary, policy, name, Change::Vanished)?;
+                    return Ok(None);
+                },
+                /* Something else took the place of the directory */
+                Err(ref e) if e.raw_os_error() == Some(libc::ENOTDIR) || e.raw_os_error() == Some(libc::ELOOP) => {
+                    changed(summary, policy, name, Change::Replaced)?;
+                    return Ok(None);
+                },
+                Err(e) => return Err(e)
+            };
+            let metadata = file.metadata()?;
+            let same = metadata.dev() == st.st_dev as u64 && metadata.ino() == st.st_ino as u64;
+            if !same {
+                changed(summary, policy, name, Change::Replaced)?;
+            }
+            if metadata.is_dir() {
+                let mut header = header_for(name, &metadata);
+                header.typeflag = TypeFlag::Directory;
+                if !header.path.ends_with('/') {
+                    header.path.push('/');
+                }
+                self.append(&header, b"")?;
+                summary.pack.entries += 1;
+                return Ok(Some(file));
+            }
+            if !metadata.is_file() {
+                if same {
+                    changed(summary, policy, name, Change::Replaced)?;
+                }
+                return Ok(None);
+            }
+            let (data, metadata, stable) = read_stable(&mut file, policy.retries)?;
+            if !stable {
+                changed(summary, policy, name, Change::Modified)?;
+            }
+            self.append_spool(&header_for(name, &metadata), data)?;
+            summary.pack.entries += 1;
+            return Ok(None);
+        }
+
+        match st.st_mode & libc::S_IFMT {
+            libc::S_IFLNK => {
+                header.typeflag = TypeFlag::SymbolicLink;
+                header.mode = 0o777;
+                header.linkname = utf8_name(&readlinkat(dir, &pending.entry)?)?.to_owned();
+            },
+            libc::S_IFIFO => {
+                if !decide(policy.pack.fifos, name, "FIFO")? {
+                    summary.pack.skipped.push(name.to_owned());
+                    return Ok(None);
+                }
+                header.typeflag = TypeFlag::FIFO;
+            },
+            kind @ (libc::S_IFCHR | libc::S_IFBLK) => {
+                let (major, minor) = device_numbers(st.st_rdev as u64);
+                let store = match major > MAX_DEVICE || minor > MAX_DEVICE {
+                    true => decide_unsupported(&policy.pack, name, "device with large numbers")?,
+                    false => decide(policy.pack.devices, name, "device")?
+                };
+                if !store {
+                    summary.pack.skipped.push(name.to_owned());
+                    return Ok(None);
+                }
+                header.typeflag = if kind == libc::S_IFCHR { TypeFlag::CharacterSpecial } else { TypeFlag::BlockSpecial };
+                header.devmajor = major;
+                header.devminor = minor;
+            },
+            _ => {
+                decide_unsupported(&policy.pack, name, "socket")?;
+                summary.pack.skipped.push(name.to_owned());
+                return Ok(None);
+            }
+        }
+        self.append(&header, b"")?;
+        summary.pack.entries += 1;
+        Ok(None)
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+    use std::os::unix::fs::symlink;
+    use archive::Archive;
+    use pack::SpecialAction;
+
+    #[test]
+    fn snapshot_test() {
+        let dir = env::temp_dir().join(format!("tar-snapshot-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        fs::write(dir.join("sub/file"), b"contents").unwrap();
+        fs::write(dir.join("top"), b"").unwrap();
+        symlink("sub/file", dir.join("link")).unwrap();
+
+        /* Same archive as the path based walk */
+        let mut b = Builder::new(Vec::new());
+        let summary = b.append_snapshot("root", &dir, &SnapshotPolicy::default()).unwrap();
+        assert!(summary.changed.is_empty());
+        assert_eq!(summary.pack.entries, 6);
+        let mut expected = Builder::new(Vec::new());
+        expected.append_dir_all("root", &dir, &PackPolicy::default()).unwrap();
+        let archive = b.finish().unwrap();
+        assert_eq!(archive, expected.finish().unwrap());
+        let archive = Archive::new(archive).unwrap();
+        assert_eq!(archive.contents_of("root/sub/file"), Some(&b"contents"[..]));
+
+        let policy = SnapshotPolicy { pack: PackPolicy { max_depth: Some(1), ..PackPolicy::default() }, ..SnapshotPolicy::default() };
+        let summary = Builder::new(Vec::new()).append_snapshot("", &dir, &policy).unwrap();
+        assert_eq!(summary.pack.too_deep, vec!["sub/deeper", "sub/file"]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        #[cfg(target_os = "linux")]
+        {
+            /* Procfs files read longer than their size, as if still growing */
+            let mut b = Builder::new(Vec::new());
+            let summary = b.append_snapshot("status", "/proc/self/status", &SnapshotPolicy::default()).unwrap();
+            assert_eq!(summary.changed, vec![ChangedFile { name: "status".to_owned(), change: Change::Modified }]);
+            let archive = Archive::new(b.finish().unwrap()).unwrap();
+            assert!(archive.contents_of("status").unwrap().starts_with(b"Name:"));
+
+            let policy = SnapshotPolicy { fail_on_change: true, ..SnapshotPolicy::default() };
+            assert!(Builder::new(Vec::new()).append_snapshot("status", "/proc/self/status", &policy).is_err());
+
+            let policy = SnapshotPolicy { pack: PackPolicy { devices: SpecialAction::Skip, ..PackPolicy::default() }, ..SnapshotPolicy::default() };
+            let summary = Builder::new(Vec::new()).append_snapshot("null", "/dev/null", &policy).unwrap();
+            assert_eq!(summary.pack.skipped, vec!["null"]);
+        }
+    }
+
+    #[test]
+    fn large_file_test() {
+        use builder::SPOOL_LIMIT;
+
+        let dir = env::temp_dir().join(format!("tar-snapshot-large-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data = vec![b'x'; SPOOL_LIMIT + 1000];
+        fs::write(dir.join("large"), &data).unwrap();
+        let mut b = Builder::new(Vec::new());
+        b.record_digests(true);
+        let summary = b.append_snapshot("", &dir, &SnapshotPolicy::default()).unwrap();
+        assert!(summary.changed.is_empty());
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        assert_eq!(archive.contents_of("large"), Some(&data[..]));
+        assert_eq!(archive.verify_digests().unwrap(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use std::str::from_utf8;
+
+/*
+ * Content type sniffing
+ */
+
+const MAGIC: [(&[u8], &str); 14] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\0", "application/x-xz"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+    (b"\0asm", "application/wasm"),
+    (b"\xca\xfe\xba\xbe", "application/java-vm")
+];
+
+const EXTENSIONS: [(&str, &str); 22] = [
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("wasm", "application/wasm"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("pdf", "application/pdf"),
+    ("so", "application/x-sharedlib")
+];
+
+/// Content type guessed from the leading bytes only
+pub fn sniff_magic(contents: &[u8]) -> Option<&'static str> {
+    MAGIC.iter().find(|&&(magic, _)| contents.starts_with(magic)).map(|&(_, t)| t)
+}
+
+/// Content type guessed from the file extension only
+pub fn sniff_extension(path: &str) -> Option<&'static str> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let ext = match name.rfind('.') {
+        Some(i) if i > 0 => &name[i + 1..],
+        _ => return None
+    };
+    EXTENSIONS.iter().find(|&&(e, _)| e.eq_ignore_ascii_case(ext)).map(|&(_, t)| t)
+}
+
+/// Whether the contents start like a program: a native executable, a
+/// Java class or a script with a `#!` line
+pub fn is_executable(contents: &[u8]) -> bool {
+    match sniff_magic(contents) {
+        Some("application/x-executable") | Some("application/vnd.microsoft.portable-executable") | Some("application/java-vm") => true,
+        _ => contents.starts_with(b"#!")
+    }
+}
+
+/// Whether the contents look like text: valid UTF-8 without control bytes
+/// other than whitespace. A character cut off at the end is allowed, so
+/// the leading bytes of a text file are text too.
+pub fn is_text(contents: &[u8]) -> bool {
+    let text = match from_utf8(contents) {
+        Ok(text) => text,
+        Err(ref e) if e.error_len().is_none() => from_utf8(&contents[..e.valid_up_to()]).expect("valid up to the error"),
+        Err(_) => return false
+    };
+    text.chars().all(|c| !c.is_control() || c == '\n' || c == '\r' || c == '\t' || c == '\x0c')
+}
+
+/// Best guess at the content type: magic bytes win over the extension,
+/// unrecognized contents fall back to plain text or raw bytes
+pub fn content_type(path: &str, contents: &[u8]) -> &'static str {
+    sniff_magic(contents)
+        .or_else(|| sniff_extension(path))
+        .unwrap_or_else(|| {
+            if is_text(contents) {
+                "text/plain; charset=utf-8"
+            } else {
+                "application/octet-stream"
+            }
+        })
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_type_test() {
+        assert_eq!(content_type("x/index.HTML", b"<html>"), "text/html; charset=utf-8");
+        assert_eq!(content_type("logo.txt", b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(content_type("README", b"hello\n"), "text/plain; charset=utf-8");
+        assert_eq!(content_type(".bashrc", b"\0\x01"), "application/octet-stream");
+        assert!(is_executable(b"\x7fELF\x02") && is_executable(b"#!/bin/sh\n"));
+        assert!(!is_executable(b"M"));
+        assert!(is_text(b"caf\xc3") && is_text("caf\u{e9}".as_bytes()));
+        assert!(!is_text(b"caf\xc3(") && !is_text(b"\xff"));
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::{self, Read, Write};
+use std::mem;
+
+use nom::IResult;
+
+use archive::{Archive, EntryMetadata};
+use digest::{sha256, Digest, DigestWriter};
+use error::Error;
+use framing::{is_extension, is_zero_block, Framer, ParseOptions, Pending};
+use parser::{padding, parse_header, TypeFlag};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Checksum lists, as written by sha256sum
+ */
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SumsFormat {
+    /// `<hex>  <path>`, as read by `sha256sum -c`
+    Gnu,
+    /// `SHA256 (<path>) = <hex>`, as written by `sha256sum --tag` and BSD `sha256`
+    Bsd
+}
+
+/// One line of a checksum list
+pub fn sums_line(format: SumsFormat, path: &str, digest: &Digest) -> String {
+    match format {
+        /* Names holding a newline or a backslash are escaped, the line
+         * starting with a backslash to say so */
+        SumsFormat::Gnu if path.contains(['\n', '\\']) => {
+            format!("\\{}  {}\n", digest, path.replace('\\', "\\\\").replace('\n', "\\n"))
+        },
+        SumsFormat::Gnu => format!("{}  {}\n", digest, path),
+        SumsFormat::Bsd => format!("SHA256 ({}) = {}\n", path, digest)
+    }
+}
+
+fn is_regular(e: &EntryMetadata) -> bool {
+    e.typeflag == TypeFlag::NormalFile || e.typeflag == TypeFlag::ContiguousFile
+}
+
+/// Checksum list of the regular files of a loaded archive, in archive order
+pub fn archive_sums(archive: &Archive, format: SumsFormat) -> String {
+    archive.entries().iter()
+        .filter(|e| is_regular(e))
+        .map(|e| sums_line(format, &e.path, &sha256(archive.contents(e))))
+        .collect()
+}
+
+/* Fill `buf`, false if the source ended before its first byte */
+pub(crate) fn read_block<R: Read>(reader: &mut R, buf: &mut [u8], offset: u64) -> Result<bool, Error> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) if len == 0 => return Ok(false),
+            Ok(0) => return Err(Error::Truncated { offset: offset }),
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(Error::Io(e))
+        }
+    }
+    Ok(true)
+}
+
+/* Copy the next `len` bytes of the source to `out` */
+pub(crate) fn copy<R: Read, W: Write>(reader: &mut R, len: u64, out: &mut W, offset: u64) -> Result<(), Error> {
+    if io::copy(&mut reader.by_ref().take(len), out)? < len {
+        return Err(Error::Truncated { offset: offset });
+    }
+    Ok(())
+}
+
+/// Write the checksum list of the regular files of an archive read once
+/// from `reader`, contents being digested as they stream by. Returns how
+/// many files were listed.
+pub fn write_sums<R: Read, W: Write>(mut reader: R, options: &ParseOptions, format: SumsFormat, mut out: W) -> Result<u64, Error> {
+    let mut framer = Framer::new(options.clone());
+    let mut pending = Pending::default();
+    let mut extension_offset = None;
+    let mut offset = 0;
+    let mut listed = 0;
+    let mut block = [0u8; 512];
+    loop {
+        if !read_block(&mut reader, &mut block, extension_offset.unwrap_or(offset))? {
+            match extension_offset {
+                Some(o) => return Err(Error::Truncated { offset: o }),
+                None => return Ok(listed)
+            }
+        }
+        if is_zero_block(&block, extension_offset)? {
+            offset += 512;
+            continue;
+        }
+        let header = match parse_header(&block) {
+            IResult::Done(_, h) => h,
+            _ => return Err(Error::InvalidHeader { offset: offset })
+        };
+        let first = *extension_offset.get_or_insert(offset);
+        let size = header.size;
+
+        if is_extension(header.typeflag) {
+            framer.check_extension(&mut pending, size, offset)?;
+            let mut record = Vec::with_capacity(size as usize);
+            copy(&mut reader, size + padding(size), &mut record, first)?;
+            framer.push_extension(&mut pending, header.typeflag, &record[..size as usize], offset)?;
+            offset += 512 + size + padding(size);
+            continue;
+        }
+
+        extension_offset = None;
+        let pending = mem::take(&mut pending);
+        let (regular, size) = if framer.is_empty(&pending, &header, offset)? {
+            (None, size)
+        } else {
+            let metadata = framer.metadata(pending, &header, first, offset, offset + 512)?;
+            let size = metadata.size;
+            (Some(metadata).filter(is_regular), size)
+        };
+        match regular {
+            Some(m) => {
+                let mut hasher = DigestWriter::new();
+                copy(&mut reader, size, &mut hasher, offset)?;
+                out.write_all(sums_line(format, &m.path, &hasher.finish()).as_bytes())?;
+                listed += 1;
+                copy(&mut reader, padding(size), &mut io::sink(), offset)?;
+            },
+            None => copy(&mut reader, size + padding(size), &mut io::sink(), offset)?
+        }
+        offset += 512 + size + padding(size);
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::{Builder, Header};
+
+    #[test]
+    fn sums_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let mut out = Vec::new();
+        assert_eq!(write_sums(&tar[..], &ParseOptions::default(), SumsFormat::Gnu, &mut out).unwrap(), 3);
+        let sums = String::from_utf8(out).unwrap();
+        let archive = Archive::new(tar.to_vec()).unwrap();
+        assert_eq!(sums, archive_sums(&archive, SumsFormat::Gnu));
+        assert_eq!(sums.lines().next().unwrap(), format!("{}  test/bar", sha256(b"This is bar\n")));
+
+        assert!(archive_sums(&archive, SumsFormat::Bsd).starts_with("SHA256 (test/bar) = "));
+        let digest = sha256(b"");
+        assert_eq!(sums_line(SumsFormat::Gnu, "a\\b\nc", &digest), format!("\\{}  a\\\\b\\nc\n", digest));
+
+        /* Long names come from their GNU record */
+        let long = "d/".repeat(100) + "file";
+        let mut b = Builder::new(Vec::new());
+        let mut record = Header::new("././@LongLink");
+        record.typeflag = TypeFlag::GnuLongName;
+        b.append(&record, long.as_bytes()).unwrap();
+        b.append(&Header::new("short"), b"x").unwrap();
+        let data = b.finish().unwrap();
+        let mut out = Vec::new();
+        write_sums(&data[..], &ParseOptions::default(), SumsFormat::Bsd, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("SHA256 ({}) = {}\n", long, sha256(b"x")));
+        match write_sums(&data[..700], &ParseOptions::default(), SumsFormat::Gnu, io::sink()) {
+            Err(Error::Truncated { offset: 0 }) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        /* The record alone, then the terminator */
+        let mut data = data;
+        data.truncate(1024);
+        data.extend_from_slice(&[0; 1024]);
+        match write_sums(&data[..], &ParseOptions::default(), SumsFormat::Gnu, io::sink()) {
+            Err(Error::Truncated { offset: 0 }) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::from_utf8;
+
+use nom::IResult;
+
+use archive::EntryMetadata;
+use error::Error;
+use framing::{is_extension, parse_pax_records, Frame, Framer, ParseOptions};
+use parser::{padding, parse_header, TypeFlag};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Following archives that are still being appended to
+ */
+
+/// An entry found by a `Tailer`, with its contents
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TailEntry {
+    pub metadata: EntryMetadata,
+    pub contents: Vec<u8>
+}
+
+/// Remembers how far an archive has been parsed and only yields entries
+/// appended since the previous poll.
+///
+/// Parsing stops before a partially written entry and before a zero block,
+/// since appending writers like `tar -r` overwrite the terminator in place.
+pub struct Tailer<R: Read + Seek> {
+    source: R,
+    offset: u64,
+    framer: Framer
+}
+
+impl Tailer<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Tailer<File>, Error> {
+        Ok(Tailer::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Tailer<R> {
+    pub fn new(source: R) -> Tailer<R> {
+        Tailer::resume(source, 0)
+    }
+
+    /// Continue from an offset previously returned by `offset`
+    pub fn resume(source: R, offset: u64) -> Tailer<R> {
+        Tailer::with_options(source, offset, &ParseOptions::default())
+    }
+
+    pub fn with_options(source: R, offset: u64, options: &ParseOptions) -> Tailer<R> {
+        Tailer {
+            source: source,
+            offset: offset,
+            framer: Framer::new(options.clone())
+        }
+    }
+
+    /// Offset up to which the archive has been consumed
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Entries completed since the last call. Only the headers and contents
+    /// of those entries are read, plus the block stopping the parse.
+    pub fn poll(&mut self) -> Result<Vec<TailEntry>, Error> {
+        self.source.seek(SeekFrom::Start(self.offset))?;
+
+        let mut entries = Vec::new();
+        let mut data = Vec::new();
+        let mut want = 512;
+        while self.fill(&mut data, want)? {
+            match self.framer.next(&data, 0, self.offset) {
+                Ok(Frame::Entry { metadata, end }) => {
+                    let start = (metadata.data_offset - self.offset) as usize;
+                    let contents = data[start..start + metadata.size as usize].to_vec();
+                    entries.push(TailEntry {
+                        metadata: *metadata,
+                        contents: contents
+                    });
+                    self.advance(&mut data, end);
+                    want = 512;
+                },
+                Ok(Frame::Empty { end, .. }) => {
+                    self.advance(&mut data, end);
+                    want = 512;
+                },
+                Ok(Frame::ZeroBlock) => break,
+                Ok(Frame::Incomplete) => want = needed(&data),
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(entries)
+    }
+
+    /* Read until `data` holds `want` bytes, false if the archive ends first */
+    fn fill(&mut self, data: &mut Vec<u8>, want: u64) -> Result<bool, Error> {
+        let missing = want.saturating_sub(data.len() as u64);
+        let read = (&mut self.source).take(missing).read_to_end(data)?;
+        Ok(read as u64 == missing)
+    }
+
+    /* Consume the first `end` bytes of `data` */
+    fn advance(&mut self, data: &mut Vec<u8>, end: usize) {
+        data.drain(..end);
+        self.offset += end as u64;
+    }
+}
+
+/* Length the entry starting `data` has, as far as its buffered headers tell,
+ * and at least a block more than is buffered */
+fn needed(data: &[u8]) -> u64 {
+    let more = data.len() as u64 + 512;
+    let mut pos = 0;
+    let mut size = None;
+    loop {
+        let (rest, header) = match parse_header(&data[pos..]) {
+            IResult::Done(rest, header) => (rest, header),
+            _ => return more
+        };
+        let data_pos = data.len() - rest.len();
+        let extension = is_extension(header.typeflag);
+        let entry_size = if extension { header.size } else { size.unwrap_or(header.size) };
+        let end = (data_pos as u64).saturating_add(entry_size).saturating_add(padding(entry_size));
+        if end > data.len() as u64 {
+            return end;
+        }
+        if !extension {
+            return more;
+        }
+        if header.typeflag == TypeFlag::PaxExtendedAttributes {
+            let records = parse_pax_records(&rest[..header.size as usize]).unwrap_or_default();
+            for (key, value) in records {
+                if key == "size" {
+                    size = from_utf8(&value).ok().and_then(|v| v.parse().ok());
+                }
+            }
+        }
+        pos = end as usize;
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use builder::{Builder, Header};
+
+    fn entry(path: &str, contents: &[u8]) -> Vec<u8> {
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new(path), contents).unwrap();
+        let mut data = b.finish().unwrap();
+        data.truncate(data.len() - 1024);
+        data
+    }
+
+    #[test]
+    fn poll_new_entries_test() {
+        let mut data = entry("a.log", b"first");
+        let mut tailer = Tailer::new(Cursor::new(data.clone()));
+        assert_eq!(tailer.poll().unwrap()[0].contents, b"first");
+        assert_eq!(tailer.offset(), 1024);
+
+        /* A half written entry and a terminator are left for later */
+        let second = entry("b.log", &[b'x'; 600]);
+        data.extend_from_slice(&second[..700]);
+        let mut tailer = Tailer::resume(Cursor::new(data.clone()), tailer.offset());
+        assert!(tailer.poll().unwrap().is_empty());
+        assert_eq!(tailer.offset(), 1024);
+
+        data.truncate(1024);
+        data.extend_from_slice(&second);
+        data.extend_from_slice(&[0; 1024]);
+        let mut tailer = Tailer::resume(Cursor::new(data), tailer.offset());
+        let entries = tailer.poll().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].metadata.path, "b.log");
+        assert_eq!(entries[0].metadata.header_offset, 1024);
+        assert_eq!(tailer.offset(), 1024 + 512 + 1024);
+    }
+
+    /* Counts the bytes read through it */
+    struct Counted {
+        inner: Cursor<Vec<u8>>,
+        read:  u64
+    }
+
+    impl Read for Counted {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for Counted {
+        fn seek(&mut self, pos: SeekFrom) -> ::std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn poll_reads_entries_only_test() {
+        /* The second entry has its size in a PAX record */
+        let mut b = Builder::new(Cursor::new(Vec::new()));
+        b.append(&Header::new("a.log"), b"first").unwrap();
+        b.append_streaming(&Header::new("c.log"), &[b'x'; 2000][..]).unwrap();
+        let mut data = b.finish().unwrap().into_inner();
+        data.truncate(data.len() - 1024);
+        let entries = data.len() as u64;
+        /* A terminator padded to a full record, as tar writes */
+        data.extend_from_slice(&[0; 10240]);
+
+        let mut tailer = Tailer::new(Counted { inner: Cursor::new(data), read: 0 });
+        let polled = tailer.poll().unwrap();
+        assert_eq!(polled.len(), 2);
+        assert_eq!(polled[1].metadata.path, "c.log");
+        assert_eq!(polled[1].contents.len(), 2000);
+        assert_eq!(tailer.offset(), entries);
+        assert_eq!(tailer.source.read, entries + 512);
+    }
+}
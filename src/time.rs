@@ -0,0 +1,182 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Timestamps in fixed formats, whatever the local time zone and locale
+ */
+
+/* Days since the epoch to (year, month, day), proleptic Gregorian calendar */
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/* The inverse of civil_from_days */
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if m > 2 { m - 3 } else { m + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TimeFormat {
+    /// `2015-05-30T10:58:04Z`
+    Iso8601,
+    /// Seconds since the epoch, `1432983484`
+    Epoch
+}
+
+/// Format seconds since the epoch as an ISO 8601 UTC date and time
+pub fn iso8601(secs: u64) -> String {
+    let (y, m, d) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", y, m, d, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+pub fn format_time(format: TimeFormat, secs: u64) -> String {
+    match format {
+        TimeFormat::Iso8601 => iso8601(secs),
+        TimeFormat::Epoch => secs.to_string()
+    }
+}
+
+fn number(s: &str, digits: usize) -> Option<u32> {
+    if s.len() != digits || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Parse a user supplied time: seconds since the epoch, optionally with a
+/// leading `@` and a fraction, or a UTC date as `2015-05-30`,
+/// `2015-05-30T10:58:04Z` or `2015-05-30 10:58:04`
+pub fn parse_time(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let epoch = s.strip_prefix('@').unwrap_or(s);
+    let integer = epoch.split('.').next().unwrap_or("");
+    if !integer.is_empty() && epoch.matches('.').count() <= 1 && epoch.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return integer.parse().ok();
+    }
+
+    let (date, time) = match s.find(['T', ' ']) {
+        Some(i) => (&s[..i], Some(s[i + 1..].strip_suffix('Z').unwrap_or(&s[i + 1..]))),
+        None => (s, None)
+    };
+    let mut parts = date.split('-');
+    let (y, m, d) = (number(parts.next()?, 4)?, number(parts.next()?, 2)?, number(parts.next()?, 2)?);
+    let days_in_month = match m {
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None
+    };
+    if parts.next().is_some() || d == 0 || d > days_in_month || y < 1970 {
+        return None;
+    }
+    let mut secs = days_from_civil(y as i64, m, d) as u64 * 86400;
+    if let Some(time) = time {
+        let mut parts = time.split(':');
+        let (h, min, sec) = (number(parts.next()?, 2)?, number(parts.next()?, 2)?, number(parts.next()?, 2)?);
+        if parts.next().is_some() || h > 23 || min > 59 || sec > 60 {
+            return None;
+        }
+        secs += (h * 3600 + min * 60 + sec) as u64;
+    }
+    Some(secs)
+}
+
+/// Bounds on a timestamp, parsed from filters such as `>=2015-01-01`,
+/// `<1432983484` or `2015-01-01..2016-01-01` (start included, end not)
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TimeFilter {
+    /// Earliest time accepted
+    pub since: Option<u64>,
+    /// First time no longer accepted
+    pub until: Option<u64>
+}
+
+impl TimeFilter {
+    pub fn parse(s: &str) -> Option<TimeFilter> {
+        let s = s.trim();
+        let filter = if let Some(i) = s.find("..") {
+            let bound = |b: &str| if b.is_empty() { Some(None) } else { parse_time(b).map(Some) };
+            TimeFilter { since: bound(&s[..i])?, until: bound(&s[i + 2..])? }
+        } else if let Some(t) = s.strip_prefix(">=") {
+            TimeFilter { since: Some(parse_time(t)?), until: None }
+        } else if let Some(t) = s.strip_prefix("<=") {
+            TimeFilter { since: None, until: Some(parse_time(t)?.checked_add(1)?) }
+        } else if let Some(t) = s.strip_prefix('>') {
+            TimeFilter { since: Some(parse_time(t)?.checked_add(1)?), until: None }
+        } else if let Some(t) = s.strip_prefix('<') {
+            TimeFilter { since: None, until: Some(parse_time(t)?) }
+        } else {
+            let t = parse_time(s.strip_prefix('=').unwrap_or(s))?;
+            TimeFilter { since: Some(t), until: Some(t.checked_add(1)?) }
+        };
+        Some(filter)
+    }
+
+    pub fn matches(&self, secs: u64) -> bool {
+        self.since.map(|s| secs >= s).unwrap_or(true) && self.until.map(|u| secs < u).unwrap_or(true)
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_time_test() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(TimeFormat::Iso8601, 1432983484), "2015-05-30T10:58:04Z");
+        assert_eq!(format_time(TimeFormat::Epoch, 1432983484), "1432983484");
+        assert_eq!(iso8601(951782400), "2000-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn parse_time_test() {
+        for s in &["1432983484", "@1432983484.25", "2015-05-30T10:58:04Z", "2015-05-30 10:58:04"] {
+            assert_eq!(parse_time(s), Some(1432983484), "{}", s);
+        }
+        assert_eq!(parse_time("2000-02-29"), Some(951782400));
+        assert_eq!(parse_time("1000"), Some(1000));
+        for s in &["", "2015-02-29", "2015-13-01", "2015-05-30T25:00:00Z", "yesterday", "2015-05", "1.2.3"] {
+            assert_eq!(parse_time(s), None, "{}", s);
+        }
+        for secs in &[0, 951782400, 1432983484, 4102444800] {
+            assert_eq!(parse_time(&iso8601(*secs)), Some(*secs));
+        }
+    }
+
+    #[test]
+    fn time_filter_test() {
+        let f = TimeFilter::parse(">=2015-05-30").unwrap();
+        assert!(f.matches(1432983484) && !f.matches(1432944000 - 1));
+        let f = TimeFilter::parse("2015-01-01..2016-01-01").unwrap();
+        assert!(f.matches(1432983484) && !f.matches(1451606400));
+        assert!(TimeFilter::parse("<=1432983484").unwrap().matches(1432983484));
+        assert!(!TimeFilter::parse(">1432983484").unwrap().matches(1432983484));
+        assert_eq!(TimeFilter::parse("..").unwrap(), TimeFilter::default());
+        assert!(TimeFilter::parse(">soon").is_none());
+    }
+}
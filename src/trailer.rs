@@ -0,0 +1,131 @@
+use std::io::{self, Write};
+
+use archive::Archive;
+use builder::Builder;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Application payloads after the end of the archive
+ */
+
+/* Each trailer is its tag, its payload, then this footer:
+ * TRAILER_MAGIC, the tag length and the payload length, big endian */
+const TRAILER_MAGIC: &[u8; 8] = b"tartrlr1";
+const FOOTER_SIZE: usize = 24;
+
+/// A payload stored after the terminator, such as a detached signature.
+/// Tar readers stop at the terminator and never see it.
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Trailer {
+    /// What the payload is, chosen by the application
+    pub tag:     String,
+    pub payload: Vec<u8>
+}
+
+impl Trailer {
+    pub fn new(tag: &str, payload: &[u8]) -> Trailer {
+        Trailer { tag: tag.to_owned(), payload: payload.to_vec() }
+    }
+}
+
+/// Append one trailer, once the archive is finished
+pub fn write_trailer<W: Write>(out: &mut W, trailer: &Trailer) -> io::Result<()> {
+    out.write_all(trailer.tag.as_bytes())?;
+    out.write_all(&trailer.payload)?;
+    out.write_all(TRAILER_MAGIC)?;
+    out.write_all(&(trailer.tag.len() as u64).to_be_bytes())?;
+    out.write_all(&(trailer.payload.len() as u64).to_be_bytes())
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn be64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    u64::from_be_bytes(buf)
+}
+
+/// Split `data` into the archive and the trailers after it, in the order
+/// they were written. The archive part is what signatures usually cover.
+pub fn read_trailers(data: &[u8]) -> io::Result<(&[u8], Vec<Trailer>)> {
+    let mut end = data.len();
+    let mut trailers = Vec::new();
+    while end >= FOOTER_SIZE && &data[end - FOOTER_SIZE..end - 16] == TRAILER_MAGIC {
+        let tag_len = be64(&data[end - 16..end - 8]);
+        let payload_len = be64(&data[end - 8..end]);
+        let body = (end - FOOTER_SIZE) as u64;
+        if tag_len.checked_add(payload_len).map(|len| len > body).unwrap_or(true) {
+            return Err(invalid("trailer longer than the data before it"));
+        }
+        let payload_start = end - FOOTER_SIZE - payload_len as usize;
+        let tag_start = payload_start - tag_len as usize;
+        let tag = String::from_utf8(data[tag_start..payload_start].to_vec()).map_err(|_| invalid("trailer tag is not valid UTF-8"))?;
+        trailers.push(Trailer { tag: tag, payload: data[payload_start..end - FOOTER_SIZE].to_vec() });
+        end = tag_start;
+    }
+    trailers.reverse();
+    Ok((&data[..end], trailers))
+}
+
+impl<W: Write> Builder<W> {
+    /// Write the terminator followed by `trailers`, and give back the
+    /// underlying writer
+    pub fn finish_with_trailers(self, trailers: &[Trailer]) -> io::Result<W> {
+        let mut inner = self.finish()?;
+        for t in trailers {
+            write_trailer(&mut inner, t)?;
+        }
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl Archive {
+    /// Trailers after the terminator, for an archive parsed with
+    /// `ParseOptions::trailing_data`
+    pub fn trailers(&self) -> io::Result<Vec<Trailer>> {
+        match self.trailing_data() {
+            Some(_) => read_trailers(self.as_bytes()).map(|(_, t)| t),
+            None => Ok(Vec::new())
+        }
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::Header;
+    use digest::sha256;
+    use framing::ParseOptions;
+
+    #[test]
+    fn trailers_test() {
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("a"), b"signed").unwrap();
+        let signature = Trailer::new("signature", sha256(b"").to_hex().as_bytes());
+        let data = b.finish_with_trailers(&[Trailer::new("meta", b""), signature.clone()]).unwrap();
+
+        let (archive_part, trailers) = read_trailers(&data).unwrap();
+        assert_eq!(archive_part.len(), 2048);
+        assert_eq!(trailers, vec![Trailer::new("meta", b""), signature]);
+
+        let options = ParseOptions { trailing_data: true, ..ParseOptions::default() };
+        let archive = Archive::with_options(data.clone(), &options).unwrap();
+        assert_eq!(archive.contents_of("a"), Some(&b"signed"[..]));
+        assert_eq!(archive.trailers().unwrap(), trailers);
+        assert_eq!(read_trailers(&Builder::new(Vec::new()).finish().unwrap()).unwrap().1, vec![]);
+
+        /* A length reaching before the start of the data */
+        let mut bad = data[data.len() - 24..].to_vec();
+        bad[16..].copy_from_slice(&1000u64.to_be_bytes());
+        assert_eq!(read_trailers(&bad).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
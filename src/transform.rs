@@ -0,0 +1,190 @@
+/*
+ * Streaming content transformations
+ */
+
+/// Rewrites entry contents chunk by chunk. Output may be held back across
+/// chunks, for instance when a chunk ends inside a pattern.
+pub trait Transform {
+    /// Transform the next chunk, appending the result to `out`
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>);
+
+    /// Emit anything held back once the contents are over
+    fn finish(&mut self, _out: &mut Vec<u8>) {}
+
+    /// Short name recorded in build reports
+    fn name(&self) -> &str {
+        "transform"
+    }
+}
+
+/// Turns CRLF line endings into LF
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+pub struct LineEndings {
+    held_cr: bool
+}
+
+impl LineEndings {
+    pub fn new() -> LineEndings {
+        LineEndings::default()
+    }
+}
+
+impl Transform for LineEndings {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &b in input {
+            if self.held_cr && b != b'\n' {
+                out.push(b'\r');
+            }
+            self.held_cr = b == b'\r';
+            if !self.held_cr {
+                out.push(b);
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if self.held_cr {
+            out.push(b'\r');
+            self.held_cr = false;
+        }
+    }
+
+    fn name(&self) -> &str {
+        "line_endings"
+    }
+}
+
+/// Replaces every occurrence of a byte string, for template substitution
+/// (`{{version}}` to `1.2.0`) or redaction (a secret to `***`)
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct Replace {
+    from:    Vec<u8>,
+    to:      Vec<u8>,
+    pending: Vec<u8>
+}
+
+impl Replace {
+    /// Panics if `from` is empty
+    pub fn new(from: &[u8], to: &[u8]) -> Replace {
+        assert!(!from.is_empty(), "cannot replace an empty pattern");
+        Replace {
+            from:    from.to_vec(),
+            to:      to.to_vec(),
+            pending: Vec::new()
+        }
+    }
+}
+
+impl Transform for Replace {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        self.pending.extend_from_slice(input);
+        let mut i = 0;
+        while i + self.from.len() <= self.pending.len() {
+            if self.pending[i..].starts_with(&self.from) {
+                out.extend_from_slice(&self.to);
+                i += self.from.len();
+            } else {
+                out.push(self.pending[i]);
+                i += 1;
+            }
+        }
+        /* What is left is shorter than the pattern and may start it */
+        self.pending.drain(..i);
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        out.append(&mut self.pending);
+    }
+
+    fn name(&self) -> &str {
+        "replace"
+    }
+}
+
+/// Applies transforms one after the other
+pub struct Chain {
+    transforms: Vec<Box<dyn Transform + Send>>
+}
+
+impl Chain {
+    pub fn new(transforms: Vec<Box<dyn Transform + Send>>) -> Chain {
+        Chain {
+            transforms: transforms
+        }
+    }
+
+    /// Names of the transforms, in the order they run
+    pub fn names(&self) -> Vec<&str> {
+        self.transforms.iter().map(|t| t.name()).collect()
+    }
+
+    fn run(&mut self, input: &[u8], out: &mut Vec<u8>, finish: bool) {
+        let mut data = input.to_vec();
+        for t in &mut self.transforms {
+            let mut next = Vec::with_capacity(data.len());
+            t.transform(&data, &mut next);
+            if finish {
+                t.finish(&mut next);
+            }
+            data = next;
+        }
+        out.append(&mut data);
+    }
+}
+
+impl Transform for Chain {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        self.run(input, out, false);
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        self.run(b"", out, true);
+    }
+
+    fn name(&self) -> &str {
+        "chain"
+    }
+}
+
+/// Run a transform over a whole buffer
+pub fn apply<T: Transform + ?Sized>(t: &mut T, input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    t.transform(input, &mut out);
+    t.finish(&mut out);
+    out
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Feed one byte at a time, the worst case for held back output */
+    fn bytewise<T: Transform>(t: &mut T, input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for b in input {
+            t.transform(&[*b], &mut out);
+        }
+        t.finish(&mut out);
+        out
+    }
+
+    #[test]
+    fn line_endings_test() {
+        assert_eq!(apply(&mut LineEndings::new(), b"a\r\nb\rc\r"), b"a\nb\rc\r");
+        assert_eq!(bytewise(&mut LineEndings::new(), b"a\r\nb\rc\r"), b"a\nb\rc\r");
+    }
+
+    #[test]
+    fn replace_test() {
+        let input = b"v{{version}} {{versio {{version}}";
+        assert_eq!(apply(&mut Replace::new(b"{{version}}", b"1.2"), input), b"v1.2 {{versio 1.2");
+        assert_eq!(bytewise(&mut Replace::new(b"{{version}}", b"1.2"), input), b"v1.2 {{versio 1.2");
+
+        let mut chain = Chain::new(vec![Box::new(LineEndings::new()), Box::new(Replace::new(b"hunter2", b"***"))]);
+        assert_eq!(bytewise(&mut chain, b"pass=hunter2\r\n"), b"pass=***\n");
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::HashSet;
+use std::io::{self, Read};
+use std::mem;
+
+use nom::IResult;
+
+use archive::EntryMetadata;
+use field::{checksum, read_checksum};
+use error::Error;
+use framing::{is_extension, is_zero_block, Framer, ParseOptions, Pending};
+use parser::{padding, parse_header, TypeFlag};
+use paths::{self, glob_match};
+use sums::{copy, read_block};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Checking an archive in one pass before accepting it
+ */
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Severity {
+    Info,
+    Warning,
+    Error
+}
+
+/// What an `Issue` is about
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Check {
+    /// Headers, records and framing. The walk ends on these.
+    Structure,
+    Checksum,
+    Limit,
+    /// Absolute, escaping or duplicate paths
+    Path,
+    Link,
+    /// Setuid and setgid bits
+    Permissions,
+    /// Device nodes and FIFOs
+    Special,
+    /// Entries nested deeper than `ValidateOptions::max_depth`
+    Depth
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Issue {
+    pub severity: Severity,
+    pub check:    Check,
+    /// Offset of the header the issue was found in
+    pub offset:   u64,
+    pub path:     Option<String>,
+    pub message:  String
+}
+
+/// What `validate_stream` checks, and when it gives up
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ValidateOptions {
+    pub parse:          ParseOptions,
+    /// Stop at the first issue at least this severe
+    pub stop_at:        Severity,
+    pub max_entries:    Option<u64>,
+    pub max_entry_size: Option<u64>,
+    /// Limit on the contents size of all the entries together
+    pub max_total_size: Option<u64>,
+    pub max_depth:      Option<usize>,
+    /// Accept symbolic and hard links, only warning about those pointing
+    /// outside the archive
+    pub links:          bool,
+    /// Accept device nodes and FIFOs
+    pub special_files:  bool
+}
+
+impl Default for ValidateOptions {
+    fn default() -> ValidateOptions {
+        ValidateOptions {
+            parse:          ParseOptions::default(),
+            stop_at:        Severity::Error,
+            max_entries:    None,
+            max_entry_size: None,
+            max_total_size: None,
+            max_depth:      None,
+            links:          true,
+            special_files:  false
+        }
+    }
+}
+
+#[derive(Clone,Debug,Default,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ValidationReport {
+    /// Issues in archive order
+    pub issues:  Vec<Issue>,
+    /// Entries checked, volume labels not included
+    pub entries: u64,
+    /// Bytes read before the walk ended
+    pub bytes:   u64,
+    /// Whether the walk ended before the end of the archive, on an issue
+    /// reaching `stop_at` or on a structural one
+    pub stopped: bool
+}
+
+impl ValidationReport {
+    /// Most severe issue found, if any
+    pub fn worst(&self) -> Option<Severity> {
+        self.issues.iter().map(|i| i.severity).max()
+    }
+
+    /// Whether no issue reaches `severity`
+    pub fn passed(&self, severity: Severity) -> bool {
+        self.worst().map(|w| w < severity).unwrap_or(true)
+    }
+}
+
+/* Why a walk ended early */
+enum Halt {
+    Stopped,
+    Failed(Error)
+}
+
+impl From<Error> for Halt {
+    fn from(e: Error) -> Halt {
+        Halt::Failed(e)
+    }
+}
+
+struct Validator<'a> {
+    options: &'a ValidateOptions,
+    report:  ValidationReport,
+    paths:   HashSet<String>,
+    total:   u64,
+    /* Header being checked */
+    offset:  u64
+}
+
+impl<'a> Validator<'a> {
+    fn issue(&mut self, severity: Severity, check: Check, path: Option<&str>, message: String) -> Result<(), Halt> {
+        self.report.issues.push(Issue {
+            severity: severity,
+            check:    check,
+            offset:   self.offset,
+            path:     path.map(|p| p.to_owned()),
+            message:  message
+        });
+        if severity >= self.options.stop_at {
+            return Err(Halt::Stopped);
+        }
+        Ok(())
+    }
+
+    fn walk<R: Read>(&mut self, reader: &mut R) -> Result<(), Halt> {
+        let mut framer = Framer::new(self.options.parse.clone());
+        let mut pending = Pending::default();
+        let mut extension_offset = None;
+        let mut offset = 0;
+        let mut zero_blocks = 0;
+        let mut block = [0u8; 512];
+        loop {
+            self.report.bytes = offset;
+            if !read_block(reader, &mut block, extension_offset.unwrap_or(offset))? {
+                if let Some(o) = extension_offset {
+                    return Err(Error::Truncated { offset: o }.into());
+                }
+                if zero_blocks < 2 {
+                    self.offset = offset;
+                    self.issue(Severity::Warning, Check::Structure, None, "archive does not end with two zero blocks".to_owned())?;
+                }
+                return Ok(());
+            }
+            if is_zero_block(&block, extension_offset)? {
+                zero_blocks += 1;
+                offset += 512;
+                continue;
+            }
+            zero_blocks = 0;
+            self.offset = offset;
+            let header = match parse_header(&block) {
+                IResult::Done(_, h) => h,
+                _ => return Err(Error::InvalidHeader { offset: offset }.into())
+            };
+            let recorded = read_checksum(&block);
+            if recorded != Some(checksum(&block)) {
+                let message = format!("header checksum {:?} does not match the header, summing to {:o}", header.chksum, checksum(&block));
+                self.issue(Severity::Error, Check::Checksum, Some(&header.path()), message)?;
+            }
+            let first = *extension_offset.get_or_insert(offset);
+            let size = header.size;
+
+            if is_extension(header.typeflag) {
+                framer.check_extension(&mut pending, size, offset)?;
+                let mut record = Vec::with_capacity(size as usize);
+                copy(reader, size + padding(size), &mut record, first)?;
+                framer.push_extension(&mut pending, header.typeflag, &record[..size as usize], offset)?;
+                offset += 512 + size + padding(size);
+                continue;
+            }
+
+            extension_offset = None;
+            let pending = mem::take(&mut pending);
+            let size = if framer.is_empty(&pending, &header, offset)? {
+                self.issue(Severity::Info, Check::Structure, None, "skipping a header with an empty name".to_owned())?;
+                size
+            } else {
+                let metadata = framer.metadata(pending, &header, first, offset, offset + 512)?;
+                self.entry(&metadata, first == 0 && self.report.entries == 0)?;
+                metadata.size
+            };
+            copy(reader, size + padding(size), &mut io::sink(), offset)?;
+            offset += 512 + size + padding(size);
+        }
+    }
+
+    fn entry(&mut self, m: &EntryMetadata, first: bool) -> Result<(), Halt> {
+        let path = Some(&m.path[..]);
+        if let Some(ref pattern) = self.options.parse.label {
+            let label = Some(m.path.clone()).filter(|_| m.typeflag == TypeFlag::GnuVolumeHeader);
+            if first && !label.as_ref().map(|l| glob_match(pattern, l)).unwrap_or(false) {
+                let message = Error::LabelMismatch { pattern: pattern.clone(), label: label }.to_string();
+                self.issue(Severity::Error, Check::Structure, path, message)?;
+            }
+        }
+        if m.typeflag == TypeFlag::GnuVolumeHeader {
+            return Ok(());
+        }
+
+        self.report.entries += 1;
+        match self.options.max_entries {
+            Some(limit) if self.report.entries > limit => {
+                self.issue(Severity::Error, Check::Limit, path, format!("more than {} entries", limit))?;
+            },
+            _ => {}
+        }
+        match self.options.max_entry_size {
+            Some(limit) if m.size > limit => {
+                self.issue(Severity::Error, Check::Limit, path, format!("{} bytes exceed the {} bytes entry limit", m.size, limit))?;
+            },
+            _ => {}
+        }
+        self.total += m.size;
+        match self.options.max_total_size {
+            Some(limit) if self.total > limit && self.total - m.size <= limit => {
+                self.issue(Severity::Error, Check::Limit, path, format!("contents exceed the {} bytes total limit", limit))?;
+            },
+            _ => {}
+        }
+
+        match (self.options.max_depth, paths::depth(&m.path)) {
+            (Some(limit), Some(depth)) if depth > limit => {
+                self.issue(Severity::Error, Check::Depth, path, format!("{} levels deep, more than {}", depth, limit))?;
+            },
+            _ => {}
+        }
+        match paths::normalize(&m.path) {
+            None => self.issue(Severity::Error, Check::Path, path, "path climbs above the archive root".to_owned())?,
+            Some(key) => {
+                if m.path.starts_with('/') {
+                    self.issue(Severity::Warning, Check::Path, path, "path is absolute".to_owned())?;
+                }
+                if !self.paths.insert(key) && m.typeflag != TypeFlag::Directory {
+                    self.issue(Severity::Warning, Check::Path, path, "path appears more than once".to_owned())?;
+                }
+            }
+        }
+
+        match m.typeflag {
+            TypeFlag::SymbolicLink | TypeFlag::HardLink if !self.options.links => {
+                self.issue(Severity::Error, Check::Link, path, "links are not allowed".to_owned())?;
+            },
+            TypeFlag::SymbolicLink => {
+                let parent = paths::parent(&paths::normalize(&m.path).unwrap_or_default()).to_owned();
+                if m.linkname.starts_with('/') || paths::normalize(&format!("{}/{}", parent, m.linkname)).is_none() {
+                    self.issue(Severity::Warning, Check::Link, path, format!("symbolic link to {:?} points outside the archive", m.linkname))?;
+                }
+            },
+            TypeFlag::HardLink if paths::normalize(&m.linkname).is_none() => {
+                self.issue(Severity::Error, Check::Link, path, format!("hard link to {:?} climbs above the archive root", m.linkname))?;
+            },
+            TypeFlag::CharacterSpecial | TypeFlag::BlockSpecial | TypeFlag::FIFO => {
+                let severity = if self.options.special_files { Severity::Info } else { Severity::Error };
+                self.issue(severity, Check::Special, path, "device node or FIFO".to_owned())?;
+            },
+            _ => {}
+        }
+        if m.mode & 0o6000 != 0 {
+            self.issue(Severity::Warning, Check::Permissions, path, format!("mode {:o} has the setuid or setgid bit", m.mode))?;
+        }
+        Ok(())
+    }
+}
+
+/// Walk an archive read once from `reader`, checking its structure,
+/// header checksums, size limits and what kinds of entries it holds,
+/// without keeping any contents. The walk stops at the first issue
+/// reaching `options.stop_at`, and at any structural problem, which leaves
+/// the rest of the stream unreadable. Only read errors fail the call.
+pub fn validate_stream<R: Read>(mut reader: R, options: &ValidateOptions) -> Result<ValidationReport, Error> {
+    let mut validator = Validator {
+        options: options,
+        report:  ValidationReport::default(),
+        paths:   HashSet::new(),
+        total:   0,
+        offset:  0
+    };
+    match validator.walk(&mut reader) {
+        Ok(()) => {},
+        Err(Halt::Stopped) => validator.report.stopped = true,
+        Err(Halt::Failed(Error::Io(e))) => return Err(Error::Io(e)),
+        Err(Halt::Failed(e)) => {
+            validator.report.stopped = true;
+            validator.report.issues.push(Issue {
+                severity: Severity::Error,
+                check:    Check::Structure,
+                offset:   validator.offset,
+                path:     None,
+                message:  e.to_string()
+            });
+        }
+    }
+    Ok(validator.report)
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::{Builder, Header};
+
+    fn build(entries: &[Header]) -> Vec<u8> {
+        let mut b = Builder::new(Vec::new());
+        for h in entries {
+            b.append(h, b"data").unwrap();
+        }
+        b.finish().unwrap()
+    }
+
+    fn header(path: &str, typeflag: TypeFlag) -> Header {
+        let mut h = Header::new(path);
+        h.typeflag = typeflag;
+        h
+    }
+
+    #[test]
+    fn validate_test() {
+        let tar = include_bytes!("../examples/simple/test.tar");
+        let report = validate_stream(&tar[..], &ValidateOptions::default()).unwrap();
+        assert_eq!((report.issues.len(), report.entries, report.bytes, report.stopped), (0, 4, 10240, false));
+        assert!(report.passed(Severity::Info));
+
+        let mut fifo = header("pipe", TypeFlag::FIFO);
+        fifo.mode = 0o4755;
+        let data = build(&[Header::new("a"), Header::new("a"), fifo, Header::new("b")]);
+        let options = ValidateOptions { stop_at: Severity::Error, special_files: true, ..ValidateOptions::default() };
+        let report = validate_stream(&data[..], &options).unwrap();
+        assert_eq!(report.issues.iter().map(|i| (i.severity, i.check)).collect::<Vec<_>>(), vec![
+            (Severity::Warning, Check::Path),
+            (Severity::Info, Check::Special),
+            (Severity::Warning, Check::Permissions)
+        ]);
+        assert_eq!((report.issues[1].offset, report.entries, report.stopped), (2048, 4, false));
+        assert!(report.passed(Severity::Error) && !report.passed(Severity::Warning));
+
+        /* Stops early, before the later entries are read */
+        let options = ValidateOptions { stop_at: Severity::Warning, ..options };
+        let report = validate_stream(&data[..], &options).unwrap();
+        assert_eq!((report.issues.len(), report.entries, report.bytes, report.stopped), (1, 2, 1024, true));
+    }
+
+    #[test]
+    fn structure_test() {
+        let mut data = build(&[Header::new("a"), Header::new("../up")]);
+        data[1024 + 148] ^= 1;
+        let report = validate_stream(&data[..], &ValidateOptions::default()).unwrap();
+        assert_eq!(report.issues.iter().map(|i| (i.check, i.offset)).collect::<Vec<_>>(), vec![(Check::Checksum, 1024)]);
+        assert_eq!((report.issues[0].path.as_ref().map(|p| &p[..]), report.stopped), (Some("../up"), true));
+
+        data[1024 + 148] ^= 1;
+        let report = validate_stream(&data[..], &ValidateOptions::default()).unwrap();
+        assert_eq!(report.issues[0].message, "path climbs above the archive root");
+
+        /* Truncated contents end the walk, a missing terminator only warns */
+        let report = validate_stream(&data[..700], &ValidateOptions::default()).unwrap();
+        assert_eq!((report.issues[0].check, report.stopped), (Check::Structure, true));
+        assert_eq!(report.issues[0].message, Error::Truncated { offset: 0 }.to_string());
+        let report = validate_stream(&data[..1024], &ValidateOptions::default()).unwrap();
+        assert_eq!((report.issues[0].severity, report.stopped), (Severity::Warning, false));
+
+        let options = ValidateOptions { max_depth: Some(1), ..ValidateOptions::default() };
+        let report = validate_stream(&build(&[Header::new("a"), Header::new("a/b")])[..], &options).unwrap();
+        assert_eq!(report.issues.iter().map(|i| (i.check, i.offset)).collect::<Vec<_>>(), vec![(Check::Depth, 1024)]);
+
+        let options = ValidateOptions { links: false, max_entry_size: Some(3), ..ValidateOptions::default() };
+        let report = validate_stream(&build(&[Header::new("big")])[..], &options).unwrap();
+        assert_eq!((report.issues[0].check, report.stopped), (Check::Limit, true));
+    }
+}
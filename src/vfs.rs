@@ -0,0 +1,889 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::error;
+use std::fmt;
+#[cfg(feature = "gzip")]
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+#[cfg(feature = "gzip")]
+use std::path::Path;
+#[cfg(feature = "gzip")]
+use std::sync::{Arc, Mutex};
+
+use archive::{Archive, EntryMetadata};
+#[cfg(feature = "gzip")]
+use archive::read_entries;
+#[cfg(feature = "gzip")]
+use error::Error;
+#[cfg(feature = "gzip")]
+use framing::ParseOptions;
+#[cfg(feature = "gzip")]
+use gzindex::{GzipIndex, GzipReader, DEFAULT_SPAN};
+use parser::TypeFlag;
+use paths::{self, components};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/*
+ * Read-only virtual filesystem abstraction
+ */
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Stat {
+    pub file_type: FileType,
+    pub size:      u64,
+    pub mode:      u64,
+    pub uid:       u64,
+    pub gid:       u64,
+    pub mtime:     u64,
+    pub devmajor:  u64,
+    pub devminor:  u64,
+    /// Stable node number, the root being 1
+    pub ino:       u64
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DirEntry {
+    pub name:      String,
+    pub file_type: FileType,
+    pub ino:       u64
+}
+
+/// Read-only filesystem operations. Paths are `/`-separated, relative to the root.
+pub trait Vfs {
+    type File: Read + Seek;
+
+    /// Open a regular file, following symlinks
+    fn open(&self, path: &str) -> io::Result<Self::File>;
+
+    /// Stat a path, following symlinks
+    fn stat(&self, path: &str) -> io::Result<Stat>;
+
+    /// Stat a path without following a final symlink
+    fn lstat(&self, path: &str) -> io::Result<Stat>;
+
+    /// List a directory, following symlinks, sorted by name
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>>;
+
+    /// Target of a symlink
+    fn read_link(&self, path: &str) -> io::Result<String>;
+
+    /// Whole contents of a regular file
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/*
+ * Archive backed implementation
+ */
+
+/* Bound on symlink and hardlink chains, like Linux's ELOOP limit */
+const MAX_LINKS: usize = 40;
+
+const ROOT: usize = 0;
+
+struct Node {
+    parent:   usize,
+    /* Archive entry describing this node, implicit directories have none */
+    entry:    Option<usize>,
+    /* Entries kept for this path, oldest first, the last being `entry` */
+    versions: Vec<usize>,
+    children: Option<BTreeMap<String, usize>>
+}
+
+impl Node {
+    fn dir(parent: usize, entry: Option<usize>) -> Node {
+        Node {
+            parent:   parent,
+            entry:    entry,
+            versions: entry.into_iter().collect(),
+            children: Some(BTreeMap::new())
+        }
+    }
+
+    fn file(parent: usize, entry: usize) -> Node {
+        Node {
+            parent:   parent,
+            entry:    Some(entry),
+            versions: vec![entry],
+            children: None
+        }
+    }
+}
+
+fn error(kind: io::ErrorKind, msg: &str, path: &str) -> io::Error {
+    io::Error::new(kind, format!("{}: {}", msg, path))
+}
+
+/* Payload of the errors of link chains longer than MAX_LINKS */
+#[derive(Debug)]
+struct LinkLoop(String);
+
+impl fmt::Display for LinkLoop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for LinkLoop {}
+
+fn link_loop(msg: &str, path: &str) -> io::Error {
+    io::Error::other(LinkLoop(format!("{}: {}", msg, path)))
+}
+
+/// Whether an error comes from following too many symbolic or hard links,
+/// what `ELOOP` reports for a real filesystem
+pub fn is_link_loop(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<LinkLoop>())
+}
+
+fn not_found(path: &str) -> io::Error {
+    error(io::ErrorKind::NotFound, "no such file or directory", path)
+}
+
+/// Which of several entries of the same kind with one path is seen
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DuplicatePolicy {
+    /// The last one, as when extracting with `tar -x`
+    LastWins,
+    FirstWins,
+    /// The last one, earlier ones staying reachable through `versions`
+    KeepAll
+}
+
+/// What happens when a file and a directory have the same path
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TypeConflictPolicy {
+    /// The later one wins, except that a directory holding entries is
+    /// never replaced by a file
+    KeepDirectory,
+    /// The later one wins, a file dropping the contents of the directory
+    Replace
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum CollisionKind {
+    /// Two entries of the same kind
+    Duplicate,
+    /// A non-directory entry where there is a directory
+    FileOverDirectory,
+    /// A directory, given or implied by an entry below it, where there is
+    /// a non-directory
+    DirectoryOverFile
+}
+
+/// Two entries claiming one path, reported while building an `ArchiveFs`
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Collision {
+    pub path:     String,
+    pub kind:     CollisionKind,
+    /// Header offset of the later entry
+    pub offset:   u64,
+    /// Whether the later entry is the one seen
+    pub replaced: bool
+}
+
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FsOptions {
+    /// Leave out entries nested deeper, listing them in `too_deep`
+    pub max_depth:      Option<usize>,
+    pub duplicates:     DuplicatePolicy,
+    pub type_conflicts: TypeConflictPolicy
+}
+
+impl Default for FsOptions {
+    fn default() -> FsOptions {
+        FsOptions {
+            max_depth:      None,
+            duplicates:     DuplicatePolicy::LastWins,
+            type_conflicts: TypeConflictPolicy::KeepDirectory
+        }
+    }
+}
+
+/* Where file contents are read from */
+#[derive(Clone)]
+enum Source {
+    Buffer(Archive),
+    #[cfg(feature = "gzip")]
+    Gzip(Arc<GzipSource>)
+}
+
+/* A gzip compressed archive, read through its seek index */
+#[cfg(feature = "gzip")]
+struct GzipSource {
+    entries: Vec<EntryMetadata>,
+    reader:  Mutex<GzipReader<File>>
+}
+
+impl Source {
+    fn entries(&self) -> &[EntryMetadata] {
+        match *self {
+            Source::Buffer(ref archive) => archive.entries(),
+            #[cfg(feature = "gzip")]
+            Source::Gzip(ref gzip) => &gzip.entries
+        }
+    }
+
+    /* Fill `buf` from `offset` in the uncompressed archive */
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match *self {
+            Source::Buffer(ref archive) => {
+                let start = offset as usize;
+                buf.copy_from_slice(&archive.as_bytes()[start..start + buf.len()]);
+                Ok(())
+            },
+            #[cfg(feature = "gzip")]
+            Source::Gzip(ref gzip) => {
+                let mut reader = gzip.reader.lock().unwrap_or_else(|e| e.into_inner());
+                reader.seek(SeekFrom::Start(offset))?;
+                reader.read_exact(buf)
+            }
+        }
+    }
+}
+
+/// A filesystem view of an archive. Parent directories missing from the
+/// archive are implied, entries sharing a path are resolved as told by
+/// `FsOptions`: by default later entries replace earlier ones.
+pub struct ArchiveFs {
+    source:     Source,
+    options:    FsOptions,
+    nodes:      Vec<Node>,
+    too_deep:   Vec<String>,
+    collisions: Vec<Collision>
+}
+
+impl ArchiveFs {
+    pub fn new(archive: Archive) -> ArchiveFs {
+        ArchiveFs::with_options(archive, FsOptions::default())
+    }
+
+    /// A view leaving out entries nested deeper than `max_depth`, listed
+    /// by `too_deep`
+    pub fn with_max_depth(archive: Archive, max_depth: usize) -> ArchiveFs {
+        ArchiveFs::with_options(archive, FsOptions { max_depth: Some(max_depth), ..FsOptions::default() })
+    }
+
+    pub fn with_options(archive: Archive, options: FsOptions) -> ArchiveFs {
+        ArchiveFs::from_source(Source::Buffer(archive), options)
+    }
+
+    /// A view of a gzip compressed archive. Contents are decompressed when
+    /// read, from the closest point of a seek index built while opening.
+    #[cfg(feature = "gzip")]
+    pub fn open_gzip<P: AsRef<Path>>(path: P, options: FsOptions) -> Result<ArchiveFs, Error> {
+        let mut file = File::open(path)?;
+        let index = GzipIndex::build(io::BufReader::new(&file), DEFAULT_SPAN)?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = GzipReader::new(file, Arc::new(index));
+        let entries = read_entries(&mut reader, &ParseOptions::default())?;
+        let gzip = GzipSource {
+            entries: entries,
+            reader:  Mutex::new(reader)
+        };
+        Ok(ArchiveFs::from_source(Source::Gzip(Arc::new(gzip)), options))
+    }
+
+    fn from_source(source: Source, options: FsOptions) -> ArchiveFs {
+        let mut fs = ArchiveFs {
+            source:     source,
+            options:    options,
+            nodes:      vec![Node::dir(ROOT, None)],
+            too_deep:   Vec::new(),
+            collisions: Vec::new()
+        };
+        let entries = fs.source.entries().len();
+        for i in 0..entries {
+            let path = &fs.source.entries()[i].path;
+            match (fs.options.max_depth, paths::depth(path)) {
+                (Some(limit), Some(depth)) if depth > limit => fs.too_deep.push(path.clone()),
+                _ => fs.insert(i)
+            }
+        }
+        fs
+    }
+
+    /// The archive buffer, unless the view reads a compressed archive
+    pub fn archive(&self) -> Option<&Archive> {
+        match self.source {
+            Source::Buffer(ref archive) => Some(archive),
+            #[cfg(feature = "gzip")]
+            Source::Gzip(_) => None
+        }
+    }
+
+    /// Paths of the entries left out by `with_max_depth`
+    pub fn too_deep(&self) -> &[String] {
+        &self.too_deep
+    }
+
+    /// Paths claimed by more than one entry, in archive order
+    pub fn collisions(&self) -> &[Collision] {
+        &self.collisions
+    }
+
+    fn collide(&mut self, path: &str, kind: CollisionKind, i: usize, replaced: bool) {
+        self.collisions.push(Collision {
+            path:     path.to_owned(),
+            kind:     kind,
+            offset:   self.source.entries()[i].header_offset,
+            replaced: replaced
+        });
+    }
+
+    fn insert(&mut self, i: usize) {
+        let path = match paths::normalize(&self.source.entries()[i].path) {
+            Some(p) => p,
+            None => return
+        };
+        let is_dir = self.source.entries()[i].typeflag == TypeFlag::Directory;
+        if path.is_empty() {
+            if is_dir {
+                self.nodes[ROOT].entry = Some(i);
+                self.nodes[ROOT].versions = vec![i];
+            }
+            return;
+        }
+        let parent = self.ensure_dir(paths::parent(&path), i);
+        let name = paths::file_name(&path).to_owned();
+        let existing = self.nodes[parent].children.as_ref().and_then(|c| c.get(&name).cloned());
+        let n = match existing {
+            Some(n) => n,
+            None => {
+                let node = if is_dir { Node::dir(parent, Some(i)) } else { Node::file(parent, i) };
+                self.add_child(parent, name, node);
+                return;
+            }
+        };
+        let was_dir = self.nodes[n].children.is_some();
+        if is_dir != was_dir {
+            let (kind, replaced) = match is_dir {
+                true => (CollisionKind::DirectoryOverFile, true),
+                /* Never drop the contents of a directory for a non-directory, unless told to */
+                false => (CollisionKind::FileOverDirectory, self.options.type_conflicts == TypeConflictPolicy::Replace
+                    || self.nodes[n].children.as_ref().map(|c| c.is_empty()).unwrap_or(true))
+            };
+            self.collide(&path, kind, i, replaced);
+            if replaced {
+                let node = if is_dir { Node::dir(parent, Some(i)) } else { Node::file(parent, i) };
+                self.add_child(parent, name, node);
+            }
+            return;
+        }
+        /* An implied directory is not a duplicate of the one given later */
+        if self.nodes[n].entry.is_none() {
+            self.nodes[n].entry = Some(i);
+            self.nodes[n].versions = vec![i];
+            return;
+        }
+        let duplicates = self.options.duplicates;
+        self.collide(&path, CollisionKind::Duplicate, i, duplicates != DuplicatePolicy::FirstWins);
+        let node = &mut self.nodes[n];
+        match duplicates {
+            DuplicatePolicy::LastWins => node.versions.clear(),
+            DuplicatePolicy::FirstWins => return,
+            DuplicatePolicy::KeepAll => {}
+        }
+        node.entry = Some(i);
+        node.versions.push(i);
+    }
+
+    fn add_child(&mut self, parent: usize, name: String, node: Node) -> usize {
+        let n = self.nodes.len();
+        self.nodes.push(node);
+        self.nodes[parent].children.as_mut().expect("parent is a directory").insert(name, n);
+        n
+    }
+
+    /* Walk down from the root creating implicit directories for entry `i`,
+     * turning files in the way into directories */
+    fn ensure_dir(&mut self, path: &str, i: usize) -> usize {
+        let mut cur = ROOT;
+        let mut prefix = String::new();
+        for c in components(path) {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(c);
+            let existing = self.nodes[cur].children.as_ref().and_then(|ch| ch.get(c).cloned());
+            cur = match existing {
+                Some(n) if self.nodes[n].children.is_some() => n,
+                Some(_) => {
+                    self.collide(&prefix, CollisionKind::DirectoryOverFile, i, true);
+                    self.add_child(cur, c.to_owned(), Node::dir(cur, None))
+                },
+                None => self.add_child(cur, c.to_owned(), Node::dir(cur, None))
+            };
+        }
+        cur
+    }
+
+    /// Every entry kept for a path, oldest first, without following a final
+    /// symlink. Only `DuplicatePolicy::KeepAll` keeps more than the one seen.
+    pub fn versions(&self, path: &str) -> io::Result<Vec<&EntryMetadata>> {
+        let node = self.resolve(path, false)?;
+        Ok(self.nodes[node].versions.iter().map(|&i| &self.source.entries()[i]).collect())
+    }
+
+    /// Open a version of a regular file, as numbered by `versions`
+    pub fn open_version(&self, path: &str, version: usize) -> io::Result<ArchiveFile> {
+        let node = self.resolve(path, false)?;
+        match self.nodes[node].versions.get(version).map(|&i| &self.source.entries()[i]) {
+            Some(e) if e.typeflag == TypeFlag::NormalFile || e.typeflag == TypeFlag::ContiguousFile => Ok(self.file(e)),
+            Some(_) => Err(error(io::ErrorKind::Unsupported, "not a regular file", path)),
+            None => Err(not_found(&format!("{} version {}", path, version)))
+        }
+    }
+
+    fn file(&self, e: &EntryMetadata) -> ArchiveFile {
+        ArchiveFile {
+            source: self.source.clone(),
+            start:  e.data_offset,
+            len:    e.size,
+            pos:    0
+        }
+    }
+
+    fn entry(&self, node: usize) -> Option<&EntryMetadata> {
+        self.nodes[node].entry.map(|i| &self.source.entries()[i])
+    }
+
+    fn is_symlink(&self, node: usize) -> bool {
+        self.entry(node).map(|e| e.typeflag == TypeFlag::SymbolicLink).unwrap_or(false)
+    }
+
+    /* Resolve a path to a node, following intermediate symlinks and the last one if asked to */
+    fn resolve(&self, path: &str, follow_last: bool) -> io::Result<usize> {
+        let mut pending = components(path).into_iter().map(|c| c.to_owned()).collect::<VecDeque<String>>();
+        let mut cur = ROOT;
+        let mut links = 0;
+        while let Some(c) = pending.pop_front() {
+            if c == ".." {
+                cur = self.nodes[cur].parent;
+                continue;
+            }
+            let child = match self.nodes[cur].children {
+                Some(ref children) => *children.get(&c).ok_or_else(|| not_found(path))?,
+                None => return Err(error(io::ErrorKind::NotADirectory, "not a directory", path))
+            };
+            if self.is_symlink(child) && (follow_last || !pending.is_empty()) {
+                links += 1;
+                if links > MAX_LINKS {
+                    return Err(link_loop("too many levels of symbolic links", path));
+                }
+                let target = &self.entry(child).expect("symlinks have an entry").linkname;
+                if target.starts_with('/') {
+                    cur = ROOT;
+                }
+                for t in components(target).into_iter().rev() {
+                    pending.push_front(t.to_owned());
+                }
+                continue;
+            }
+            cur = child;
+        }
+        Ok(cur)
+    }
+
+    fn lookup(&self, path: &str, follow_last: bool) -> io::Result<usize> {
+        let node = self.resolve(path, follow_last)?;
+        self.follow_hardlinks(node, path)
+    }
+
+    /* Hardlink targets are archive paths relative to the root */
+    fn follow_hardlinks(&self, mut node: usize, path: &str) -> io::Result<usize> {
+        for _ in 0..MAX_LINKS {
+            match self.entry(node) {
+                Some(e) if e.typeflag == TypeFlag::HardLink => node = self.resolve(&e.linkname, true)?,
+                _ => return Ok(node)
+            }
+        }
+        Err(link_loop("too many levels of hard links", path))
+    }
+
+    fn node_stat(&self, node: usize) -> Stat {
+        let file_type = self.node_type(node);
+        match self.entry(node) {
+            Some(e) => Stat {
+                file_type: file_type,
+                size:      match file_type {
+                    FileType::File => e.size,
+                    FileType::Symlink => e.linkname.len() as u64,
+                    _ => 0
+                },
+                mode:      e.mode,
+                uid:       e.uid,
+                gid:       e.gid,
+                mtime:     e.mtime,
+                devmajor:  e.devmajor,
+                devminor:  e.devminor,
+                ino:       node as u64 + 1
+            },
+            None => Stat {
+                file_type: file_type,
+                size:      0,
+                mode:      0o755,
+                uid:       0,
+                gid:       0,
+                mtime:     0,
+                devmajor:  0,
+                devminor:  0,
+                ino:       node as u64 + 1
+            }
+        }
+    }
+
+    fn node_type(&self, node: usize) -> FileType {
+        if self.nodes[node].children.is_some() {
+            return FileType::Directory;
+        }
+        match self.entry(node).map(|e| e.typeflag) {
+            Some(TypeFlag::SymbolicLink) => FileType::Symlink,
+            Some(TypeFlag::CharacterSpecial) => FileType::CharDevice,
+            Some(TypeFlag::BlockSpecial) => FileType::BlockDevice,
+            Some(TypeFlag::FIFO) => FileType::Fifo,
+            _ => FileType::File
+        }
+    }
+}
+
+/*
+ * Inode interface, for consumers addressing nodes by number like FUSE
+ */
+
+/// Node number of the root directory
+pub const ROOT_INO: u64 = 1;
+
+impl ArchiveFs {
+    fn node(&self, ino: u64, what: &str) -> io::Result<usize> {
+        match ino.checked_sub(1) {
+            Some(n) if (n as usize) < self.nodes.len() => Ok(n as usize),
+            _ => Err(error(io::ErrorKind::NotFound, "no such node", &format!("{} {}", what, ino)))
+        }
+    }
+
+    fn node_children(&self, node: usize) -> io::Result<&BTreeMap<String, usize>> {
+        self.nodes[node].children.as_ref().ok_or_else(|| error(io::ErrorKind::NotADirectory, "not a directory", &format!("node {}", node + 1)))
+    }
+
+    pub fn stat_ino(&self, ino: u64) -> io::Result<Stat> {
+        self.node(ino, "node").map(|n| self.node_stat(n))
+    }
+
+    /// Node number of the parent directory, the root being its own parent
+    pub fn parent_ino(&self, ino: u64) -> io::Result<u64> {
+        self.node(ino, "node").map(|n| self.nodes[n].parent as u64 + 1)
+    }
+
+    /// Stat a directory child without following symlinks, hardlinks resolve to their target
+    pub fn lookup_ino(&self, parent: u64, name: &str) -> io::Result<Stat> {
+        let node = self.node(parent, "directory")?;
+        let child = *self.node_children(node)?.get(name).ok_or_else(|| not_found(name))?;
+        self.follow_hardlinks(child, name).map(|n| self.node_stat(n))
+    }
+
+    /// List a directory node, sorted by name
+    pub fn read_dir_ino(&self, ino: u64) -> io::Result<Vec<DirEntry>> {
+        let node = self.node(ino, "directory")?;
+        self.node_children(node)?.iter().map(|(name, &n)| {
+            let target = self.follow_hardlinks(n, name)?;
+            Ok(DirEntry {
+                name:      name.clone(),
+                file_type: self.node_type(target),
+                ino:       target as u64 + 1
+            })
+        }).collect()
+    }
+
+    pub fn read_link_ino(&self, ino: u64) -> io::Result<String> {
+        let node = self.node(ino, "node")?;
+        match self.entry(node) {
+            Some(e) if e.typeflag == TypeFlag::SymbolicLink => Ok(e.linkname.clone()),
+            _ => Err(error(io::ErrorKind::InvalidInput, "not a symbolic link", &format!("node {}", ino)))
+        }
+    }
+
+    /// Up to `len` bytes of a regular file from `offset`, borrowed from the
+    /// archive buffer unless the archive is compressed
+    pub fn read_ino(&self, ino: u64, offset: u64, len: usize) -> io::Result<Cow<'_, [u8]>> {
+        let node = self.node(ino, "node")?;
+        match self.node_type(node) {
+            FileType::File => {},
+            FileType::Directory => return Err(error(io::ErrorKind::IsADirectory, "is a directory", &format!("node {}", ino))),
+            _ => return Err(error(io::ErrorKind::Unsupported, "not a regular file", &format!("node {}", ino)))
+        }
+        let e = self.entry(node).expect("files have an entry");
+        let start = cmp::min(offset, e.size);
+        let end = cmp::min(start.saturating_add(len as u64), e.size);
+        match self.source {
+            Source::Buffer(ref archive) => Ok(Cow::Borrowed(&archive.contents(e)[start as usize..end as usize])),
+            #[cfg(feature = "gzip")]
+            Source::Gzip(_) => {
+                let mut buf = vec![0; (end - start) as usize];
+                self.source.read_at(e.data_offset + start, &mut buf)?;
+                Ok(Cow::Owned(buf))
+            }
+        }
+    }
+}
+
+impl Vfs for ArchiveFs {
+    type File = ArchiveFile;
+
+    fn open(&self, path: &str) -> io::Result<ArchiveFile> {
+        let node = self.lookup(path, true)?;
+        match self.node_type(node) {
+            FileType::File => {},
+            FileType::Directory => return Err(error(io::ErrorKind::IsADirectory, "is a directory", path)),
+            _ => return Err(error(io::ErrorKind::Unsupported, "not a regular file", path))
+        }
+        Ok(self.file(self.entry(node).expect("files have an entry")))
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Stat> {
+        self.lookup(path, true).map(|n| self.node_stat(n))
+    }
+
+    fn lstat(&self, path: &str) -> io::Result<Stat> {
+        self.lookup(path, false).map(|n| self.node_stat(n))
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>> {
+        let node = self.lookup(path, true)?;
+        self.read_dir_ino(node as u64 + 1)
+    }
+
+    fn read_link(&self, path: &str) -> io::Result<String> {
+        let node = self.lookup(path, false)?;
+        self.read_link_ino(node as u64 + 1)
+    }
+}
+
+/// An open regular file, keeping its archive alive
+pub struct ArchiveFile {
+    source: Source,
+    start:  u64,
+    len:    u64,
+    pos:    u64
+}
+
+impl ArchiveFile {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for ArchiveFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let n = cmp::min(remaining, buf.len() as u64) as usize;
+        self.source.read_at(self.start + self.pos, &mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for ArchiveFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d)
+        };
+        match target {
+            Some(p) => {
+                self.pos = p;
+                Ok(p)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file"))
+        }
+    }
+}
+
+/*
+ * Tests
+ */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use builder::{Builder, Header};
+
+    fn link(path: &str, typeflag: TypeFlag, target: &str) -> Header {
+        let mut h = Header::new(path);
+        h.typeflag = typeflag;
+        h.linkname = target.to_owned();
+        h
+    }
+
+    fn fs() -> ArchiveFs {
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("./usr/lib/libfoo.so.1"), b"ELF").unwrap();
+        b.append(&link("usr/lib/libfoo.so", TypeFlag::SymbolicLink, "libfoo.so.1"), b"").unwrap();
+        b.append(&link("lib", TypeFlag::SymbolicLink, "/usr/lib"), b"").unwrap();
+        b.append(&link("usr/hard", TypeFlag::HardLink, "usr/lib/libfoo.so.1"), b"").unwrap();
+        b.append(&link("loop", TypeFlag::SymbolicLink, "loop"), b"").unwrap();
+        ArchiveFs::new(Archive::new(b.finish().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn symlink_resolution_test() {
+        let fs = fs();
+        assert_eq!(fs.read("lib/libfoo.so").unwrap(), b"ELF");
+        assert_eq!(fs.read("usr/hard").unwrap(), b"ELF");
+        assert_eq!(fs.read_link("lib").unwrap(), "/usr/lib");
+        assert_eq!(fs.lstat("lib").unwrap().file_type, FileType::Symlink);
+        assert_eq!(fs.stat("lib").unwrap().file_type, FileType::Directory);
+        match fs.stat("loop") {
+            Err(ref e) if is_link_loop(e) => {},
+            r => panic!("unexpected result: {:?}", r)
+        }
+        assert!(!is_link_loop(&fs.stat("missing").unwrap_err()));
+        assert_eq!(fs.open("usr/missing").err().map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn read_dir_test() {
+        let fs = fs();
+        let names = fs.read_dir("").unwrap().into_iter().map(|e| e.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["lib", "loop", "usr"]);
+        let names = fs.read_dir("lib").unwrap().into_iter().map(|e| e.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["libfoo.so", "libfoo.so.1"]);
+        assert_eq!(fs.stat("usr").unwrap().file_type, FileType::Directory);
+
+        let mut f = fs.open("usr/lib/libfoo.so.1").unwrap();
+        f.seek(SeekFrom::Start(1)).unwrap();
+        let mut s = String::new();
+        f.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "LF");
+    }
+
+    #[test]
+    fn deep_nesting_test() {
+        let deep = "d/".repeat(10000) + "file";
+        let mut b = Builder::new(Vec::new());
+        let mut record = Header::new("././@LongLink");
+        record.typeflag = TypeFlag::GnuLongName;
+        b.append(&record, deep.as_bytes()).unwrap();
+        b.append(&Header::new("x"), b"deep").unwrap();
+        b.append(&Header::new("d/shallow"), b"").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+
+        let fs = ArchiveFs::new(archive.clone());
+        assert_eq!(fs.read(&deep).unwrap(), b"deep");
+        assert!(fs.too_deep().is_empty());
+        let fs = ArchiveFs::with_max_depth(archive, 100);
+        assert_eq!(fs.too_deep().to_vec(), vec![deep.clone()]);
+        assert!(fs.stat("d/shallow").is_ok());
+        assert!(fs.stat(&deep).is_err());
+    }
+
+    #[test]
+    fn collision_test() {
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("a"), b"one").unwrap();
+        b.append(&Header::new("./a"), b"two").unwrap();
+        b.append(&Header::new("d"), b"file").unwrap();
+        b.append(&Header::new("d/x"), b"").unwrap();
+        b.append(&Header::new("d"), b"again").unwrap();
+        let archive = Archive::new(b.finish().unwrap()).unwrap();
+        let fs = |duplicates, type_conflicts| ArchiveFs::with_options(archive.clone(), FsOptions { max_depth: None, duplicates: duplicates, type_conflicts: type_conflicts });
+
+        let last = fs(DuplicatePolicy::LastWins, TypeConflictPolicy::KeepDirectory);
+        assert_eq!(last.read("a").unwrap(), b"two");
+        assert_eq!(last.stat("d").unwrap().file_type, FileType::Directory);
+        assert_eq!(last.versions("a").unwrap().len(), 1);
+        assert_eq!(last.collisions().iter().map(|c| (&c.path[..], c.kind, c.offset, c.replaced)).collect::<Vec<_>>(), vec![
+            ("a", CollisionKind::Duplicate, 1024, true),
+            ("d", CollisionKind::DirectoryOverFile, 3072, true),
+            ("d", CollisionKind::FileOverDirectory, 3584, false)
+        ]);
+
+        let first = fs(DuplicatePolicy::FirstWins, TypeConflictPolicy::Replace);
+        assert_eq!(first.read("a").unwrap(), b"one");
+        assert_eq!(first.read("d").unwrap(), b"again");
+        assert!(first.stat("d/x").is_err());
+
+        let all = fs(DuplicatePolicy::KeepAll, TypeConflictPolicy::KeepDirectory);
+        assert_eq!(all.read("a").unwrap(), b"two");
+        assert_eq!(all.versions("a").unwrap().iter().map(|e| e.header_offset).collect::<Vec<_>>(), vec![0, 1024]);
+        let mut old = String::new();
+        all.open_version("a", 0).unwrap().read_to_string(&mut old).unwrap();
+        assert_eq!(old, "one");
+        assert!(all.open_version("a", 2).is_err());
+    }
+
+    #[test]
+    fn inode_test() {
+        let fs = fs();
+        let usr = fs.lookup_ino(ROOT_INO, "usr").unwrap();
+        assert_eq!(fs.parent_ino(usr.ino).unwrap(), ROOT_INO);
+        let hard = fs.lookup_ino(usr.ino, "hard").unwrap();
+        assert_eq!(hard, fs.stat("usr/lib/libfoo.so.1").unwrap());
+        assert_eq!(&fs.read_ino(hard.ino, 1, 100).unwrap()[..], b"LF");
+        assert_eq!(&fs.read_ino(hard.ino, 10, 100).unwrap()[..], b"");
+        assert_eq!(fs.read_link_ino(fs.lookup_ino(ROOT_INO, "lib").unwrap().ino).unwrap(), "/usr/lib");
+        assert!(fs.stat_ino(0).is_err());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_test() {
+        extern crate flate2;
+
+        use std::env;
+        use std::fs;
+        use std::io::Write;
+        use std::process;
+
+        let mut b = Builder::new(Vec::new());
+        b.append(&Header::new("./usr/lib/libfoo.so.1"), b"ELF").unwrap();
+        b.append(&link("usr/hard", TypeFlag::HardLink, "usr/lib/libfoo.so.1"), b"").unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&b.finish().unwrap()).unwrap();
+        let path = env::temp_dir().join(format!("tar-vfs-{}.tar.gz", process::id()));
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+
+        let gz = ArchiveFs::open_gzip(&path, FsOptions::default()).unwrap();
+        assert!(gz.archive().is_none());
+        assert_eq!(gz.read("usr/hard").unwrap(), b"ELF");
+        let hard = gz.stat("usr/hard").unwrap();
+        assert_eq!(&gz.read_ino(hard.ino, 1, 100).unwrap()[..], b"LF");
+        assert_eq!(&gz.read_ino(hard.ino, 10, 100).unwrap()[..], b"");
+
+        fs::write(&path, b"not gzip").unwrap();
+        assert!(ArchiveFs::open_gzip(&path, FsOptions::default()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}